@@ -0,0 +1,6 @@
+-- Add up migration script here
+CREATE TABLE IF NOT EXISTS processed_stripe_events (
+    event_id TEXT PRIMARY KEY NOT NULL, -- Stripe event ID, e.g. evt_...
+    event_type TEXT NOT NULL,
+    processed_at TIMESTAMP NOT NULL DEFAULT NOW()
+);
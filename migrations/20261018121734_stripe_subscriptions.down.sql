@@ -0,0 +1,7 @@
+-- Add down migration script here
+BEGIN;
+
+DROP TABLE IF EXISTS stripe_customers;
+ALTER TABLE subscription_plans DROP COLUMN IF EXISTS stripe_price_id;
+
+COMMIT;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+BEGIN;
+
+-- Recurring Stripe price used for auto-renewing subscriptions to each plan, created on first use
+ALTER TABLE subscription_plans ADD COLUMN IF NOT EXISTS stripe_price_id TEXT UNIQUE;
+
+CREATE TABLE IF NOT EXISTS stripe_customers (
+    user_id UUID PRIMARY KEY NOT NULL REFERENCES users(id),
+    customer_id TEXT UNIQUE NOT NULL, -- e.g. cus_...
+    subscription_id TEXT UNIQUE, -- e.g. sub_..., NULL if the user has never auto-renewed
+    subscription_status TEXT, -- Stripe subscription status, e.g. active, past_due, canceled
+    plan_id TEXT REFERENCES subscription_plans(id),
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+
+COMMIT;
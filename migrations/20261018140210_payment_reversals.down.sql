@@ -0,0 +1,11 @@
+-- Add down migration script here
+BEGIN;
+
+ALTER TABLE users DROP COLUMN IF EXISTS flag_reason;
+ALTER TABLE users DROP COLUMN IF EXISTS flagged;
+ALTER TABLE payment_log DROP COLUMN IF EXISTS refunded_cents;
+ALTER TABLE payment_log DROP COLUMN IF EXISTS months;
+ALTER TABLE payment_log DROP COLUMN IF EXISTS status;
+DROP TABLE IF EXISTS payment_statuses;
+
+COMMIT;
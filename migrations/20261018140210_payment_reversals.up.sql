@@ -0,0 +1,25 @@
+-- Add up migration script here
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS payment_statuses (
+    id TEXT PRIMARY KEY NOT NULL,
+    description TEXT
+);
+
+INSERT INTO payment_statuses (id, description) VALUES
+('paid', 'Payment received and credited'),
+('partially refunded', 'Part of the payment was refunded and the matching time removed'),
+('refunded', 'The whole payment was refunded and its time removed'),
+('disputed', 'The cardholder disputed the payment and its time was removed');
+
+ALTER TABLE payment_log ADD COLUMN IF NOT EXISTS status TEXT NOT NULL DEFAULT 'paid' REFERENCES payment_statuses(id);
+-- Months of credit the payment bought, taken back again on refund or dispute. 0 for payments
+-- logged before this was tracked.
+ALTER TABLE payment_log ADD COLUMN IF NOT EXISTS months INT NOT NULL DEFAULT 0 CHECK (months >= 0);
+ALTER TABLE payment_log ADD COLUMN IF NOT EXISTS refunded_cents INT NOT NULL DEFAULT 0 CHECK (refunded_cents >= 0);
+
+-- Set when a payment of the user was disputed, for support to follow up on
+ALTER TABLE users ADD COLUMN IF NOT EXISTS flagged TIMESTAMP;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS flag_reason TEXT;
+
+COMMIT;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+-- One row per reminder sent, so each paid_until a user reaches is only announced once
+CREATE TABLE IF NOT EXISTS expiry_notifications (
+    user_id UUID NOT NULL REFERENCES users(id),
+    paid_until TIMESTAMP NOT NULL,
+    notified TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, paid_until)
+);
@@ -0,0 +1,8 @@
+-- Add down migration script here
+BEGIN;
+
+DROP TABLE IF EXISTS promo_redemptions;
+DROP TABLE IF EXISTS promo_codes;
+ALTER TABLE users DROP COLUMN IF EXISTS admin;
+
+COMMIT;
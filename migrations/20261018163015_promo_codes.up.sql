@@ -0,0 +1,29 @@
+-- Add up migration script here
+BEGIN;
+
+ALTER TABLE users ADD COLUMN IF NOT EXISTS admin BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS promo_codes (
+    code TEXT PRIMARY KEY NOT NULL CHECK (code ~ '^[A-Z0-9_-]{4,32}$'),
+    percent_off INT CHECK (percent_off BETWEEN 1 AND 100), -- discount applied at checkout
+    free_months INT CHECK (free_months > 0), -- months granted directly on redemption
+    plan_id TEXT REFERENCES subscription_plans(id), -- NULL if a discount applies to every plan
+    max_redemptions INT CHECK (max_redemptions > 0), -- NULL if unlimited
+    redemptions INT NOT NULL DEFAULT 0 CHECK (redemptions >= 0),
+    expires TIMESTAMP, -- NULL if the code never expires
+    stripe_coupon_id TEXT UNIQUE, -- coupon backing a discount, created on first use
+    created_by UUID NOT NULL REFERENCES users(id),
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    -- a code either discounts a checkout or grants free months of a specific plan
+    CHECK ((percent_off IS NULL) <> (free_months IS NULL)),
+    CHECK (free_months IS NULL OR plan_id IS NOT NULL)
+);
+
+CREATE TABLE IF NOT EXISTS promo_redemptions (
+    code TEXT NOT NULL REFERENCES promo_codes(code),
+    user_id UUID NOT NULL REFERENCES users(id),
+    redeemed TIMESTAMP NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (code, user_id)
+);
+
+COMMIT;
@@ -0,0 +1,43 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS users_username_unique;
+
+ALTER TABLE client_repos DROP CONSTRAINT client_repos_repo_id_fkey;
+ALTER TABLE broadcast_messages DROP CONSTRAINT broadcast_messages_repo_id_fkey;
+ALTER TABLE commits DROP CONSTRAINT commits_repo_id_fkey;
+
+DROP INDEX IF EXISTS repos_owner_name_live;
+ALTER TABLE repos DROP CONSTRAINT repos_pkey;
+ALTER TABLE repos RENAME COLUMN id TO uuid;
+ALTER TABLE repos ADD COLUMN id TEXT;
+UPDATE repos SET id = owner::text || '/' || name;
+ALTER TABLE repos ALTER COLUMN id SET NOT NULL;
+ALTER TABLE repos ADD CONSTRAINT repos_id_key UNIQUE (id);
+ALTER TABLE repos ADD PRIMARY KEY (name);
+ALTER TABLE repos ADD CONSTRAINT repos_owner_name_key UNIQUE (owner, name);
+
+ALTER TABLE client_repos ADD COLUMN repo_text TEXT;
+UPDATE client_repos SET repo_text = repos.id FROM repos WHERE repos.uuid = client_repos.repo_id;
+ALTER TABLE client_repos DROP COLUMN repo_id;
+ALTER TABLE client_repos RENAME COLUMN repo_text TO repo_id;
+ALTER TABLE client_repos ALTER COLUMN repo_id SET NOT NULL;
+ALTER TABLE client_repos ADD CONSTRAINT client_repos_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+
+ALTER TABLE broadcast_messages ADD COLUMN repo_text TEXT;
+UPDATE broadcast_messages SET repo_text = repos.id FROM repos WHERE repos.uuid = broadcast_messages.repo_id;
+ALTER TABLE broadcast_messages DROP COLUMN repo_id;
+ALTER TABLE broadcast_messages RENAME COLUMN repo_text TO repo_id;
+ALTER TABLE broadcast_messages ALTER COLUMN repo_id SET NOT NULL;
+ALTER TABLE broadcast_messages ADD CONSTRAINT broadcast_messages_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+
+ALTER TABLE commits ADD COLUMN repo_text TEXT;
+UPDATE commits SET repo_text = repos.id FROM repos WHERE repos.uuid = commits.repo_id;
+ALTER TABLE commits DROP COLUMN repo_id;
+ALTER TABLE commits RENAME COLUMN repo_text TO repo_id;
+ALTER TABLE commits ALTER COLUMN repo_id SET NOT NULL;
+ALTER TABLE commits ADD CONSTRAINT commits_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+
+ALTER TABLE repos DROP COLUMN uuid;
+
+COMMIT;
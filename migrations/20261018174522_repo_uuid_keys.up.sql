@@ -0,0 +1,42 @@
+-- Add up migration script here
+-- Repos used to be keyed by their name (globally unique) and referenced by an "owner/name" text id.
+-- They are now keyed by a UUID, and names only need to be unique among an owner's live repos.
+BEGIN;
+
+ALTER TABLE repos ADD COLUMN uuid UUID NOT NULL DEFAULT gen_random_uuid();
+
+ALTER TABLE client_repos ADD COLUMN repo_uuid UUID;
+UPDATE client_repos SET repo_uuid = repos.uuid FROM repos WHERE repos.id = client_repos.repo_id;
+ALTER TABLE client_repos DROP COLUMN repo_id;
+ALTER TABLE client_repos RENAME COLUMN repo_uuid TO repo_id;
+ALTER TABLE client_repos ALTER COLUMN repo_id SET NOT NULL;
+
+ALTER TABLE broadcast_messages ADD COLUMN repo_uuid UUID;
+UPDATE broadcast_messages SET repo_uuid = repos.uuid FROM repos WHERE repos.id = broadcast_messages.repo_id;
+ALTER TABLE broadcast_messages DROP COLUMN repo_id;
+ALTER TABLE broadcast_messages RENAME COLUMN repo_uuid TO repo_id;
+ALTER TABLE broadcast_messages ALTER COLUMN repo_id SET NOT NULL;
+
+ALTER TABLE commits ADD COLUMN repo_uuid UUID;
+UPDATE commits SET repo_uuid = repos.uuid FROM repos WHERE repos.id = commits.repo_id;
+ALTER TABLE commits DROP COLUMN repo_id;
+ALTER TABLE commits RENAME COLUMN repo_uuid TO repo_id;
+ALTER TABLE commits ALTER COLUMN repo_id SET NOT NULL;
+
+ALTER TABLE repos DROP CONSTRAINT repos_pkey;
+ALTER TABLE repos DROP CONSTRAINT repos_owner_name_key;
+ALTER TABLE repos DROP COLUMN id;
+ALTER TABLE repos RENAME COLUMN uuid TO id;
+ALTER TABLE repos ALTER COLUMN id DROP DEFAULT;
+ALTER TABLE repos ADD PRIMARY KEY (id);
+-- deleted repos keep their name, so it can be reused
+CREATE UNIQUE INDEX IF NOT EXISTS repos_owner_name_live ON repos (owner, name) WHERE deleted IS NULL;
+
+ALTER TABLE client_repos ADD CONSTRAINT client_repos_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+ALTER TABLE broadcast_messages ADD CONSTRAINT broadcast_messages_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+ALTER TABLE commits ADD CONSTRAINT commits_repo_id_fkey FOREIGN KEY (repo_id) REFERENCES repos(id);
+
+-- usernames are the owner part of "owner/name" repo slugs, so they must resolve to one user
+CREATE UNIQUE INDEX IF NOT EXISTS users_username_unique ON users (lower(username));
+
+COMMIT;
@@ -0,0 +1,3 @@
+-- Add up migration script here
+-- When the user last set their username, used to rate limit changes
+ALTER TABLE users ADD COLUMN IF NOT EXISTS username_changed TIMESTAMP;
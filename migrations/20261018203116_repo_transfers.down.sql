@@ -0,0 +1,8 @@
+-- Add down migration script here
+BEGIN;
+
+DROP TABLE IF EXISTS repo_audit_log;
+DROP TABLE IF EXISTS repo_redirects;
+DROP TABLE IF EXISTS repo_transfers;
+
+COMMIT;
@@ -0,0 +1,37 @@
+-- Add up migration script here
+BEGIN;
+
+CREATE TABLE IF NOT EXISTS repo_transfers (
+    id UUID PRIMARY KEY NOT NULL,
+    repo_id UUID NOT NULL REFERENCES repos(id),
+    from_user UUID NOT NULL REFERENCES users(id),
+    to_user UUID NOT NULL REFERENCES users(id),
+    created TIMESTAMP NOT NULL DEFAULT NOW(),
+    accepted TIMESTAMP,
+    cancelled TIMESTAMP, -- declined by the recipient or withdrawn by the sender
+    CHECK (from_user <> to_user),
+    CHECK (accepted IS NULL OR cancelled IS NULL)
+);
+-- a repo can only be offered to one user at a time
+CREATE UNIQUE INDEX IF NOT EXISTS repo_transfers_pending ON repo_transfers (repo_id) WHERE accepted IS NULL AND cancelled IS NULL;
+
+-- old "owner/name" slugs that keep resolving for a while after a transfer
+CREATE TABLE IF NOT EXISTS repo_redirects (
+    owner_name TEXT NOT NULL, -- username, or user id, of the previous owner at the time
+    name TEXT NOT NULL,
+    repo_id UUID NOT NULL REFERENCES repos(id),
+    expires TIMESTAMP NOT NULL,
+    PRIMARY KEY (owner_name, name)
+);
+
+CREATE TABLE IF NOT EXISTS repo_audit_log (
+    id BIGSERIAL PRIMARY KEY,
+    repo_id UUID NOT NULL REFERENCES repos(id),
+    actor UUID NOT NULL REFERENCES users(id),
+    action TEXT NOT NULL,
+    detail TEXT,
+    created TIMESTAMP NOT NULL DEFAULT NOW()
+);
+CREATE INDEX IF NOT EXISTS repo_audit_log_repo ON repo_audit_log (repo_id, created);
+
+COMMIT;
@@ -0,0 +1,8 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS mls_clients_user;
+ALTER TABLE mls_clients DROP COLUMN IF EXISTS created;
+ALTER TABLE mls_clients DROP COLUMN IF EXISTS name;
+
+COMMIT;
@@ -0,0 +1,8 @@
+-- Add up migration script here
+BEGIN;
+
+ALTER TABLE mls_clients ADD COLUMN IF NOT EXISTS name TEXT; -- set by the user to tell their devices apart
+ALTER TABLE mls_clients ADD COLUMN IF NOT EXISTS created TIMESTAMP NOT NULL DEFAULT NOW();
+CREATE INDEX IF NOT EXISTS mls_clients_user ON mls_clients (user_id) WHERE deleted IS NULL;
+
+COMMIT;
@@ -0,0 +1,13 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS key_packages_last_resort;
+DROP INDEX IF EXISTS key_packages_available;
+ALTER TABLE key_packages DROP COLUMN IF EXISTS claimed_by;
+ALTER TABLE key_packages DROP COLUMN IF EXISTS claimed;
+ALTER TABLE key_packages DROP COLUMN IF EXISTS created;
+ALTER TABLE key_packages DROP COLUMN IF EXISTS last_resort;
+ALTER TABLE key_packages DROP CONSTRAINT IF EXISTS key_packages_pkey;
+ALTER TABLE key_packages DROP COLUMN IF EXISTS id;
+
+COMMIT;
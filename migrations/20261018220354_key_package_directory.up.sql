@@ -0,0 +1,16 @@
+-- Add up migration script here
+BEGIN;
+
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS id UUID NOT NULL DEFAULT gen_random_uuid();
+ALTER TABLE key_packages ALTER COLUMN id DROP DEFAULT;
+ALTER TABLE key_packages ADD PRIMARY KEY (id);
+-- handed out again whenever the client has no other package left, instead of being consumed
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS last_resort BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS created TIMESTAMP NOT NULL DEFAULT NOW();
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS claimed TIMESTAMP;
+ALTER TABLE key_packages ADD COLUMN IF NOT EXISTS claimed_by UUID REFERENCES mls_clients(id);
+
+CREATE INDEX IF NOT EXISTS key_packages_available ON key_packages (client_id, expiration_date) WHERE deleted IS NULL AND claimed IS NULL AND NOT last_resort;
+CREATE UNIQUE INDEX IF NOT EXISTS key_packages_last_resort ON key_packages (client_id) WHERE deleted IS NULL AND last_resort;
+
+COMMIT;
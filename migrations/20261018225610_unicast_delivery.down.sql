@@ -0,0 +1,7 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS unicast_messages_inbox;
+ALTER TABLE unicast_messages_read_receipts DROP CONSTRAINT IF EXISTS unicast_messages_read_receipts_message_id_key;
+
+COMMIT;
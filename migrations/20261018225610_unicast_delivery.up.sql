@@ -0,0 +1,11 @@
+-- Add up migration script here
+BEGIN;
+
+-- the reader of a unicast message is always its recipient, so a message is read at most once
+DELETE FROM unicast_messages_read_receipts a USING unicast_messages_read_receipts b
+WHERE a.message_id = b.message_id AND a.ctid > b.ctid;
+ALTER TABLE unicast_messages_read_receipts ADD CONSTRAINT unicast_messages_read_receipts_message_id_key UNIQUE (message_id);
+
+CREATE INDEX IF NOT EXISTS unicast_messages_inbox ON unicast_messages (recipient_id, created) WHERE deleted IS NULL;
+
+COMMIT;
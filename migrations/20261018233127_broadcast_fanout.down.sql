@@ -0,0 +1,8 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS broadcast_messages_repo;
+DROP INDEX IF EXISTS client_repos_live_member;
+ALTER TABLE client_repos DROP COLUMN IF EXISTS joined;
+
+COMMIT;
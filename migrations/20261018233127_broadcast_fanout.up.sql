@@ -0,0 +1,10 @@
+-- Add up migration script here
+BEGIN;
+
+-- members only receive broadcasts sent after they joined, earlier ones are not encrypted for them
+ALTER TABLE client_repos ADD COLUMN IF NOT EXISTS joined TIMESTAMP NOT NULL DEFAULT NOW();
+CREATE UNIQUE INDEX IF NOT EXISTS client_repos_live_member ON client_repos (repo_id, client_id) WHERE deleted IS NULL;
+
+CREATE INDEX IF NOT EXISTS broadcast_messages_repo ON broadcast_messages (repo_id, created) WHERE deleted IS NULL;
+
+COMMIT;
@@ -0,0 +1,13 @@
+-- Add down migration script here
+BEGIN;
+
+DROP INDEX IF EXISTS broadcast_messages_epoch_commit;
+DROP INDEX IF EXISTS broadcast_messages_repo_sequence;
+
+ALTER TABLE broadcast_messages DROP COLUMN IF EXISTS epoch;
+ALTER TABLE broadcast_messages DROP COLUMN IF EXISTS sequence;
+
+ALTER TABLE repos DROP COLUMN IF EXISTS epoch;
+ALTER TABLE repos DROP COLUMN IF EXISTS message_sequence;
+
+COMMIT;
@@ -0,0 +1,37 @@
+-- Add up migration script here
+BEGIN;
+
+-- the next broadcast sequence number is message_sequence + 1, and epoch is the MLS epoch the
+-- group is in, advanced by every accepted commit
+ALTER TABLE repos ADD COLUMN IF NOT EXISTS message_sequence BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE repos ADD COLUMN IF NOT EXISTS epoch BIGINT NOT NULL DEFAULT 0;
+
+ALTER TABLE broadcast_messages ADD COLUMN IF NOT EXISTS sequence BIGINT;
+ALTER TABLE broadcast_messages ADD COLUMN IF NOT EXISTS epoch BIGINT NOT NULL DEFAULT 0;
+
+UPDATE broadcast_messages SET sequence = numbered.n
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY repo_id ORDER BY created, id) AS n FROM broadcast_messages
+) numbered
+WHERE numbered.id = broadcast_messages.id;
+
+-- earlier commits are assumed to have advanced the epoch one at a time
+UPDATE broadcast_messages SET epoch = numbered.n - 1
+FROM (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY repo_id ORDER BY sequence) AS n FROM broadcast_messages
+    WHERE message_type = 'commit' AND deleted IS NULL
+) numbered
+WHERE numbered.id = broadcast_messages.id;
+
+UPDATE repos SET
+    message_sequence = COALESCE((SELECT MAX(sequence) FROM broadcast_messages WHERE repo_id = repos.id), 0),
+    epoch = (SELECT COUNT(*) FROM broadcast_messages WHERE repo_id = repos.id AND message_type = 'commit' AND deleted IS NULL);
+
+ALTER TABLE broadcast_messages ALTER COLUMN sequence SET NOT NULL;
+
+CREATE UNIQUE INDEX IF NOT EXISTS broadcast_messages_repo_sequence ON broadcast_messages (repo_id, sequence);
+-- only one commit is accepted per epoch
+CREATE UNIQUE INDEX IF NOT EXISTS broadcast_messages_epoch_commit ON broadcast_messages (repo_id, epoch)
+    WHERE message_type = 'commit' AND deleted IS NULL;
+
+COMMIT;
@@ -0,0 +1,9 @@
+-- Add down migration script here
+BEGIN;
+
+DROP TRIGGER IF EXISTS broadcast_messages_notify ON broadcast_messages;
+DROP TRIGGER IF EXISTS unicast_messages_notify ON unicast_messages;
+DROP FUNCTION IF EXISTS notify_broadcast_message();
+DROP FUNCTION IF EXISTS notify_unicast_message();
+
+COMMIT;
@@ -0,0 +1,28 @@
+-- Add up migration script here
+BEGIN;
+
+-- wakes long-polling fetches once the inserting transaction commits. Payloads are the id of the
+-- recipient client or the repository.
+CREATE OR REPLACE FUNCTION notify_unicast_message() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('unicast_messages', NEW.recipient_id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION notify_broadcast_message() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('broadcast_messages', NEW.repo_id::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS unicast_messages_notify ON unicast_messages;
+CREATE TRIGGER unicast_messages_notify AFTER INSERT ON unicast_messages
+    FOR EACH ROW EXECUTE FUNCTION notify_unicast_message();
+
+DROP TRIGGER IF EXISTS broadcast_messages_notify ON broadcast_messages;
+CREATE TRIGGER broadcast_messages_notify AFTER INSERT ON broadcast_messages
+    FOR EACH ROW EXECUTE FUNCTION notify_broadcast_message();
+
+COMMIT;
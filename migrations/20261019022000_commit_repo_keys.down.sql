@@ -0,0 +1,13 @@
+-- Add down migration script here
+BEGIN;
+
+ALTER TABLE blob_server_backups DROP CONSTRAINT blob_server_backups_related_commit_fkey;
+
+ALTER TABLE commits DROP CONSTRAINT commits_pkey;
+ALTER TABLE commits ADD PRIMARY KEY (id);
+
+ALTER TABLE blob_server_backups DROP COLUMN repo_id;
+ALTER TABLE blob_server_backups ADD CONSTRAINT blob_server_backups_related_commit_fkey
+    FOREIGN KEY (related_commit) REFERENCES commits(id);
+
+COMMIT;
@@ -0,0 +1,17 @@
+-- Add up migration script here
+BEGIN;
+
+-- commit hashes are only unique within a repository, so the same hash in another repository is
+-- neither rejected nor revealed
+ALTER TABLE blob_server_backups ADD COLUMN repo_id UUID;
+UPDATE blob_server_backups SET repo_id = commits.repo_id FROM commits WHERE commits.id = blob_server_backups.related_commit;
+ALTER TABLE blob_server_backups ALTER COLUMN repo_id SET NOT NULL;
+ALTER TABLE blob_server_backups DROP CONSTRAINT blob_server_backups_related_commit_fkey;
+
+ALTER TABLE commits DROP CONSTRAINT commits_pkey;
+ALTER TABLE commits ADD PRIMARY KEY (repo_id, id);
+
+ALTER TABLE blob_server_backups ADD CONSTRAINT blob_server_backups_related_commit_fkey
+    FOREIGN KEY (repo_id, related_commit) REFERENCES commits(repo_id, id);
+
+COMMIT;
@@ -0,0 +1,16 @@
+-- Add up migration script here
+BEGIN;
+
+-- named heads of a repository's commit graph. Heads only move by compare-and-swap, so devices
+-- pushing to the same branch cannot overwrite each other's commits.
+CREATE TABLE IF NOT EXISTS branches (
+    repo_id UUID NOT NULL REFERENCES repos(id),
+    name TEXT NOT NULL,
+    head TEXT NOT NULL,
+    created TIMESTAMP NOT NULL,
+    updated TIMESTAMP NOT NULL,
+    PRIMARY KEY (repo_id, name),
+    FOREIGN KEY (repo_id, head) REFERENCES commits(repo_id, id)
+);
+
+COMMIT;
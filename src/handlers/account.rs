@@ -1,29 +1,82 @@
-use crate::models::account::SubscriptionType;
 use crate::models::account::AutoCommitBehaviour;
 use crate::models::account::AutoPullBehaviour;
 use crate::models::account::AutoPushBehaviour;
 use crate::models::account::CommandStyle;
+use crate::models::account::PaymentInfo;
 use crate::models::account::Settings;
+use crate::models::user::UserProfile;
 use crate::{AppState, logic};
 use axum::Extension;
-use axum::extract::{Json, State};
+use axum::extract::{Json, Path, State};
 use axum::http::StatusCode;
 use axum::response::Result;
 use chrono::TimeDelta;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+#[derive(Serialize, Deserialize)]
+pub struct PaymentResponse {
+    pub payment_id: String,
+    pub amount_cents: u32,
+    pub payment_date: i64, // milliseconds since the unix epoch
+    pub status: String,    // paid | partially refunded | refunded | disputed
+    pub refunded_cents: u32,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PaymentInfoResponse {
-    paid_until: u64,
-    subscription_type: String,
+    pub paid_until: i64, // milliseconds since the unix epoch, 0 if the user has never paid
+    pub subscription_type: String, // cloud sync | sync collaborate | none
+    pub active: bool,
+    pub days_remaining: i64,
+    pub recent_payments: Vec<PaymentResponse>,
+}
+
+impl From<PaymentInfo> for PaymentInfoResponse {
+    fn from(info: PaymentInfo) -> Self {
+        let recent_payments = info
+            .recent_payments
+            .into_iter()
+            .map(|payment| PaymentResponse {
+                payment_id: payment.payment_id,
+                amount_cents: payment.amount_cents,
+                payment_date: payment.payment_date.and_utc().timestamp_millis(),
+                status: payment.status.to_string().into(),
+                refunded_cents: payment.refunded_cents,
+            })
+            .collect();
+        match info.subscription {
+            Some(sub_info) => PaymentInfoResponse {
+                paid_until: sub_info.paid_until.and_utc().timestamp_millis(),
+                subscription_type: sub_info.subscription_type.to_string().into(),
+                active: sub_info.is_active(),
+                days_remaining: sub_info.days_remaining(),
+                recent_payments,
+            },
+            None => PaymentInfoResponse {
+                paid_until: 0,
+                subscription_type: "none".to_string(),
+                active: false,
+                days_remaining: 0,
+                recent_payments,
+            },
+        }
+    }
 }
 
 pub async fn payment_info(
     State(state): State<AppState>,
     uid: Extension<Uuid>,
-) -> Result<usize, StatusCode> {
-    unimplemented!();
+) -> Result<Json<PaymentInfoResponse>, StatusCode> {
+    return logic::user::get_payment_info(
+        &state.subscription_repository,
+        &state.payment_log_repository,
+        uid.0,
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(PaymentInfoResponse::from)
+    .map(Json);
 }
 
 #[derive(Serialize, Deserialize)]
@@ -143,3 +196,63 @@ pub async fn post_settings(
         .map_err(|e| e.into());
 }
 
+
+#[derive(Serialize, Deserialize)]
+pub struct ProfileResponse {
+    pub id: String,
+    pub username: Option<String>,
+    pub created: i64, // milliseconds since the unix epoch
+}
+
+impl From<UserProfile> for ProfileResponse {
+    fn from(profile: UserProfile) -> Self {
+        ProfileResponse {
+            id: profile.id.to_string(),
+            username: profile.username,
+            created: profile.created.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UsernameRequest {
+    pub username: String,
+}
+
+pub async fn get_profile(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+) -> Result<Json<ProfileResponse>, StatusCode> {
+    return logic::user::get_profile(&state.user_repository, uid.0)
+        .await
+        .map_err(|e| e.into())
+        .map(ProfileResponse::from)
+        .map(Json);
+}
+
+pub async fn set_username(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<UsernameRequest>,
+) -> Result<Json<ProfileResponse>, StatusCode> {
+    let profile = logic::user::set_username(&state.user_repository, uid.0, &payload.username)
+        .await
+        .map_err(|e| {
+            tracing::warn!(user_id = %uid.0, error = %e, "Could not set username");
+            StatusCode::from(e)
+        })?;
+    tracing::info!(user_id = %uid.0, username = %payload.username, "Set username");
+    Ok(Json(ProfileResponse::from(profile)))
+}
+
+// Public profile of the user with that username
+pub async fn lookup(
+    State(state): State<AppState>,
+    Path(username): Path<String>,
+) -> Result<Json<ProfileResponse>, StatusCode> {
+    return logic::user::find_by_username(&state.user_repository, &username)
+        .await
+        .map_err(|e| e.into())
+        .map(ProfileResponse::from)
+        .map(Json);
+}
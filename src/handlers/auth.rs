@@ -2,7 +2,7 @@ use crate::logic::error::ServiceError;
 use crate::repository::error::RepoError;
 use crate::{AppState, logic};
 use axum::Extension;
-use axum::extract::State;
+use axum::extract::{Json, State};
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Result};
 use firebase_auth::FirebaseUser;
@@ -10,12 +10,14 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Serialize, Deserialize)]
-pub struct SignupPayload {}
+pub struct SignupPayload {
+    pub username: Option<String>, // can also be claimed later through /account/username
+}
 
 pub async fn init(
     State(state): State<AppState>,
     user: FirebaseUser,
-    // Json(payload): Json<SignupPayload>,
+    payload: Option<Json<SignupPayload>>,
 ) -> Result<impl IntoResponse, StatusCode> {
     if user.email.is_none() {
         return Err(StatusCode::UNAUTHORIZED);
@@ -24,9 +26,11 @@ pub async fn init(
     let verified = user.email_verified.unwrap_or(false);
     crate::logic::user::verify_email(state.environment, &eml, verified);
 
+    let username = payload.and_then(|Json(payload)| payload.username);
     let uid = logic::auth::register_user(
         &state.user_repository,
         &eml,
+        username.as_deref(),
     ).await;
 
     return uid
@@ -0,0 +1,454 @@
+use crate::AppState;
+use crate::logic;
+use crate::logic::error::ServiceError;
+use crate::logic::payment::{
+    ChargeReversal, CheckoutPurchase, EventOutcome, SubscriptionChange, SubscriptionInvoice, WebhookEvent,
+    CheckoutPayment, CheckoutSubscription, CompletedCheckout, SubscriptionPurchase, plan_from_metadata,
+};
+use crate::models::account::SubscriptionType;
+use crate::models::account::PlanChange;
+use axum::Extension;
+use axum::body::Body;
+use axum::extract::{FromRequest, Json, Request, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response, Result};
+use chrono::{DateTime, NaiveDateTime};
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use stripe_checkout::CheckoutSessionMode;
+use stripe_shared::{Invoice, Subscription};
+use stripe_webhook::{Event, EventObject, Webhook};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct CheckoutRequest {
+    pub plan: String, // cloud sync | sync collaborate
+    #[serde(default)]
+    pub mode: CheckoutMode,
+    #[serde(default)]
+    pub months: u32, // only used for prepaid checkouts
+    #[serde(default)]
+    pub promo_code: Option<String>, // discount code, see /billing/promo/redeem for free months
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckoutMode {
+    // pay once for a bundle of months
+    #[default]
+    Prepaid,
+    // pay every month until canceled
+    Subscription,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CheckoutResponse {
+    pub url: String,
+}
+
+pub async fn checkout(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<CheckoutRequest>,
+) -> Result<Json<CheckoutResponse>, StatusCode> {
+    let subscription_type =
+        SubscriptionType::from_string(&payload.plan).ok_or(StatusCode::BAD_REQUEST)?;
+    let url = match payload.mode {
+        CheckoutMode::Prepaid => {
+            let purchase = CheckoutPurchase {
+                user_id: uid.0,
+                subscription_type,
+                months: payload.months,
+                promo_code: payload.promo_code,
+            };
+            logic::payment::checkout(
+                &state.subscription_repository,
+                &state.promo_code_repository,
+                &state.billing.stripe_client,
+                &state.billing.checkout_urls,
+                purchase,
+            )
+            .await
+        }
+        CheckoutMode::Subscription => {
+            logic::payment::subscription_checkout(
+                &state.subscription_repository,
+                &state.stripe_customer_repository,
+                &state.promo_code_repository,
+                &state.billing.stripe_client,
+                &state.billing.checkout_urls,
+                SubscriptionPurchase {
+                    user_id: uid.0,
+                    subscription_type,
+                    promo_code: payload.promo_code,
+                },
+            )
+            .await
+        }
+    };
+    url.map_err(|e| {
+        tracing::warn!(user_id = %uid.0, error = %e, "Error starting checkout session");
+        e.into()
+    })
+    .map(|url| Json(CheckoutResponse { url }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePlanRequest {
+    pub plan: String, // cloud sync | sync collaborate
+    #[serde(default)]
+    pub preview: bool, // only compute the new paid_until, don't apply it
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePlanResponse {
+    pub previous_plan: String,
+    pub previous_paid_until: i64, // milliseconds since the unix epoch
+    pub plan: String,
+    pub paid_until: i64, // milliseconds since the unix epoch
+    pub applied: bool,
+}
+
+impl From<PlanChange> for ChangePlanResponse {
+    fn from(change: PlanChange) -> Self {
+        ChangePlanResponse {
+            previous_plan: change.previous.subscription_type.to_string().into(),
+            previous_paid_until: change.previous.paid_until.and_utc().timestamp_millis(),
+            plan: change.updated.subscription_type.to_string().into(),
+            paid_until: change.updated.paid_until.and_utc().timestamp_millis(),
+            applied: change.applied,
+        }
+    }
+}
+
+pub async fn change_plan(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<ChangePlanRequest>,
+) -> Result<Json<ChangePlanResponse>, StatusCode> {
+    let subscription_type =
+        SubscriptionType::from_string(&payload.plan).ok_or(StatusCode::BAD_REQUEST)?;
+    logic::payment::change_plan(
+        &state.subscription_repository,
+        &state.stripe_customer_repository,
+        uid.0,
+        subscription_type,
+        payload.preview,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, error = %e, "Plan change rejected");
+        e.into()
+    })
+    .map(ChangePlanResponse::from)
+    .map(Json)
+}
+
+pub struct StripeEvent(Event);
+
+impl FromRequest<AppState> for StripeEvent {
+    type Rejection = Response;
+
+    async fn from_request(req: Request<Body>, state: &AppState) -> Result<Self, Self::Rejection> {
+        let signature = if let Some(sig) = req.headers().get("stripe-signature") {
+            sig.to_owned()
+        } else {
+            return Err(StatusCode::BAD_REQUEST.into_response());
+        };
+        let signature = signature
+            .to_str()
+            .map_err(|_| StatusCode::BAD_REQUEST.into_response())?
+            .to_owned();
+
+        let payload = String::from_request(req, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        Ok(Self(
+            Webhook::construct_event(&payload, &signature, &state.billing.webhook_secret)
+                .map_err(|e| {
+                    tracing::warn!(error = %e, "Rejected Stripe webhook with invalid signature");
+                    StatusCode::BAD_REQUEST.into_response()
+                })?,
+        ))
+    }
+}
+
+// Number of redelivered events acknowledged without being applied since the server started
+static DUPLICATE_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+pub async fn webhook(
+    State(state): State<AppState>,
+    StripeEvent(event): StripeEvent,
+) -> Result<(), StatusCode> {
+    let webhook_event = WebhookEvent {
+        id: event.id.to_string(),
+        event_type: event.type_.to_string(),
+    };
+    let event_id = &webhook_event.id;
+    let event_type = &webhook_event.event_type;
+    let outcome = match event.data.object {
+        EventObject::CheckoutSessionCompleted(session) if session.mode == CheckoutSessionMode::Subscription => {
+            let meta = session.metadata.as_ref().ok_or(StatusCode::BAD_REQUEST)?;
+            let purchase = SubscriptionPurchase::from_metadata(meta).map_err(|e| {
+                tracing::warn!(event_id = %event_id, session_id = %session.id, error = %e, "Invalid checkout session metadata");
+                StatusCode::from(e)
+            })?;
+            let user_id = purchase.user_id;
+            let subscription_id = session
+                .subscription
+                .as_ref()
+                .map(|sub| sub.id().to_string())
+                .ok_or(StatusCode::BAD_REQUEST)?;
+            let subscription = CheckoutSubscription {
+                subscription_id: subscription_id.clone(),
+                invoice_id: session.invoice.as_ref().and_then(|invoice| invoice.id().as_ref()).map(|id| id.to_string()),
+            };
+            logic::payment::link_subscription(
+                &state.stripe_event_repository,
+                &state.stripe_customer_repository,
+                &state.promo_code_repository,
+                &state.billing.stripe_client,
+                &webhook_event,
+                purchase,
+                subscription,
+            )
+            .await
+            .inspect(|outcome| {
+                if let EventOutcome::Applied(()) = outcome {
+                    tracing::info!(event_id = %event_id, user_id = %user_id, subscription_id = %subscription_id, "Linked Stripe subscription");
+                }
+            })
+        }
+        EventObject::CheckoutSessionCompleted(session) => {
+            let meta = session.metadata.as_ref().ok_or(StatusCode::BAD_REQUEST)?;
+            let purchase = CheckoutPurchase::from_metadata(meta).map_err(|e| {
+                tracing::warn!(event_id = %event_id, session_id = %session.id, error = %e, "Invalid checkout session metadata");
+                StatusCode::from(e)
+            })?;
+            let payment_id = session
+                .payment_intent
+                .as_ref()
+                .map(|pi| pi.id().to_string())
+                .unwrap_or_else(|| session.id.to_string());
+            let amount_cents = session.amount_total.unwrap_or(0).max(0) as u32;
+            let user_id = purchase.user_id;
+            logic::payment::complete_checkout(
+                &state.stripe_event_repository,
+                &state.subscription_repository,
+                &state.payment_log_repository,
+                &state.promo_code_repository,
+                &state.billing.stripe_client,
+                &webhook_event,
+                CompletedCheckout {
+                    purchase,
+                    payment: CheckoutPayment {
+                        payment_id: payment_id.clone(),
+                        amount_cents,
+                    },
+                },
+            )
+            .await
+            .map(|outcome| {
+                if let EventOutcome::Applied(sub_info) = &outcome {
+                    tracing::info!(
+                        event_id = %event_id,
+                        user_id = %user_id,
+                        payment_id = %payment_id,
+                        paid_until = %sub_info.paid_until,
+                        "Extended subscription from checkout session"
+                    );
+                }
+                outcome.map(|_| ())
+            })
+        }
+        EventObject::InvoicePaid(invoice) => {
+            let Some(invoice) = subscription_invoice(&invoice) else {
+                tracing::info!(event_id = %event_id, "Ignoring paid invoice that is not for a subscription");
+                return Ok(());
+            };
+            let subscription_id = invoice.subscription_id.clone();
+            logic::payment::renew_subscription(
+                &state.stripe_event_repository,
+                &state.subscription_repository,
+                &state.payment_log_repository,
+                &state.stripe_customer_repository,
+                &webhook_event,
+                invoice,
+            )
+            .await
+            .map(|outcome| {
+                if let EventOutcome::Applied(sub_info) = &outcome {
+                    tracing::info!(
+                        event_id = %event_id,
+                        subscription_id = %subscription_id,
+                        paid_until = %sub_info.paid_until,
+                        "Renewed subscription from paid invoice"
+                    );
+                }
+                outcome.map(|_| ())
+            })
+        }
+        EventObject::CustomerSubscriptionUpdated(subscription)
+        | EventObject::CustomerSubscriptionDeleted(subscription) => {
+            let change = subscription_change(&subscription);
+            let subscription_id = change.subscription_id.clone();
+            let status = change.status.clone();
+            logic::payment::update_subscription(
+                &state.stripe_event_repository,
+                &state.subscription_repository,
+                &state.stripe_customer_repository,
+                &webhook_event,
+                change,
+            )
+            .await
+            .map(|outcome| {
+                if let EventOutcome::Applied(sub_info) = &outcome {
+                    tracing::info!(
+                        event_id = %event_id,
+                        subscription_id = %subscription_id,
+                        status = %status,
+                        paid_until = ?sub_info.as_ref().map(|s| s.paid_until),
+                        "Updated subscription status"
+                    );
+                }
+                outcome.map(|_| ())
+            })
+        }
+        EventObject::ChargeRefunded(charge) => {
+            let Some(payment_intent) = charge.payment_intent.as_ref() else {
+                tracing::info!(event_id = %event_id, charge_id = %charge.id, "Ignoring refund of a charge without a payment intent");
+                return Ok(());
+            };
+            let reversal = ChargeReversal::Refund {
+                payment_id: payment_intent.id().to_string(),
+                refunded_cents: charge.amount_refunded.max(0) as u32,
+            };
+            reverse_payment(&state, &webhook_event, reversal).await
+        }
+        EventObject::ChargeDisputeCreated(dispute) => {
+            let Some(payment_intent) = dispute.payment_intent.as_ref() else {
+                tracing::info!(event_id = %event_id, dispute_id = %dispute.id, "Ignoring dispute of a charge without a payment intent");
+                return Ok(());
+            };
+            let reversal = ChargeReversal::Dispute {
+                payment_id: payment_intent.id().to_string(),
+                reason: dispute.reason.clone(),
+            };
+            reverse_payment(&state, &webhook_event, reversal).await
+        }
+        EventObject::AccountUpdated(account) => {
+            tracing::info!(event_id = %event_id, account_id = %account.id, "Received account updated webhook");
+            return Ok(());
+        }
+        _ => {
+            tracing::info!(event_id = %event_id, event_type = %event_type, "Unhandled event encountered in webhook");
+            return Ok(());
+        }
+    };
+
+    match outcome {
+        Ok(EventOutcome::Applied(())) => Ok(()),
+        Ok(EventOutcome::Ignored(reason)) => {
+            tracing::warn!(event_id = %event_id, event_type = %event_type, reason = %reason, "Ignored Stripe event");
+            Ok(())
+        }
+        Ok(EventOutcome::Duplicate) => {
+            let duplicates = DUPLICATE_EVENTS.fetch_add(1, Ordering::Relaxed) + 1;
+            tracing::info!(
+                event_id = %event_id,
+                event_type = %event_type,
+                duplicate_events = duplicates,
+                "Skipped already processed Stripe event"
+            );
+            Ok(())
+        }
+        Err(e) => {
+            tracing::error!(event_id = %event_id, event_type = %event_type, error = %e, "Error processing Stripe event");
+            Err(StatusCode::from(e))
+        }
+    }
+}
+
+async fn reverse_payment(
+    state: &AppState,
+    event: &WebhookEvent,
+    reversal: ChargeReversal,
+) -> Result<EventOutcome<()>, ServiceError> {
+    logic::payment::reverse_payment(
+        &state.stripe_event_repository,
+        &state.subscription_repository,
+        &state.payment_log_repository,
+        &state.user_repository,
+        event,
+        reversal,
+    )
+    .await
+    .map(|outcome| {
+        if let EventOutcome::Applied(payment) = &outcome {
+            tracing::warn!(
+                event_id = %event.id,
+                user_id = %payment.user_id,
+                payment_id = %payment.payment_id,
+                status = %payment.status.to_string(),
+                refunded_cents = payment.refunded_cents,
+                "Reversed payment"
+            );
+        }
+        outcome.map(|_| ())
+    })
+}
+
+fn from_timestamp(timestamp: i64) -> Option<NaiveDateTime> {
+    DateTime::from_timestamp(timestamp, 0).map(|dt| dt.naive_utc())
+}
+
+fn subscription_invoice(invoice: &Invoice) -> Option<SubscriptionInvoice> {
+    let details = invoice.parent.as_ref()?.subscription_details.as_ref()?;
+    let invoice_id = invoice.id.as_ref()?.to_string();
+    // Refunds and disputes only know the payment intent, so log the payment under it if we can
+    let payment_id = invoice
+        .payments
+        .iter()
+        .flat_map(|payments| payments.data.iter())
+        .find_map(|payment| payment.payment.payment_intent.as_ref())
+        .map(|pi| pi.id().to_string())
+        .unwrap_or_else(|| invoice_id.clone());
+    Some(SubscriptionInvoice {
+        invoice_id,
+        payment_id,
+        customer_id: invoice.customer.as_ref()?.id().to_string(),
+        subscription_id: details.subscription.id().to_string(),
+        subscription_type: details
+            .metadata
+            .as_ref()
+            .and_then(|meta| plan_from_metadata(meta).ok())
+            .map(|(_, subscription_type)| subscription_type),
+        amount_cents: invoice.amount_paid.max(0) as u32,
+        period_end: invoice
+            .lines
+            .data
+            .iter()
+            .map(|line| line.period.end)
+            .max()
+            .and_then(from_timestamp)?,
+    })
+}
+
+fn subscription_change(subscription: &Subscription) -> SubscriptionChange {
+    SubscriptionChange {
+        customer_id: subscription.customer.id().to_string(),
+        subscription_id: subscription.id.to_string(),
+        subscription_type: plan_from_metadata(&subscription.metadata)
+            .ok()
+            .map(|(_, subscription_type)| subscription_type),
+        status: subscription.status.as_str().to_string(),
+        current_period_end: subscription
+            .items
+            .data
+            .iter()
+            .map(|item| item.current_period_end)
+            .max()
+            .and_then(from_timestamp),
+    }
+}
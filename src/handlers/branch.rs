@@ -0,0 +1,139 @@
+use crate::logic::branch::{BranchOutcome, BranchUpdate};
+use crate::models::repository::{Branch, CommitHash};
+use crate::models::user::MLSClientId;
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct BranchParams {
+    pub client: Uuid, // the member client reading
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UpdateBranchRequest {
+    pub client: Uuid, // the current user's client, with at least contributor permission
+    pub expected: Option<String>, // head the client last saw, absent to create the branch
+    pub head: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BranchResponse {
+    pub name: String,
+    pub head: String,
+    pub updated: i64, // milliseconds since the unix epoch
+}
+
+impl From<Branch> for BranchResponse {
+    fn from(branch: Branch) -> Self {
+        BranchResponse {
+            name: branch.name,
+            head: branch.head.0,
+            updated: branch.updated_at.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+// Sent with 409, so the client can merge with the current head and try again
+#[derive(Serialize, Deserialize)]
+pub struct BranchConflictResponse {
+    pub name: String,
+    pub head: Option<String>, // None if the branch does not exist
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Query(params): Query<BranchParams>,
+) -> Result<Json<Vec<BranchResponse>>, StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    return logic::branch::list_branches(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.branch_repository,
+        uid.0,
+        repository.id,
+        &MLSClientId(params.client),
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|branches| Json(branches.into_iter().map(BranchResponse::from).collect()));
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name, branch)): Path<(String, String, String)>,
+    Query(params): Query<BranchParams>,
+) -> Result<Json<BranchResponse>, StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    return logic::branch::get_branch(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.branch_repository,
+        uid.0,
+        repository.id,
+        &branch,
+        &MLSClientId(params.client),
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|branch| Json(BranchResponse::from(branch)));
+}
+
+// Creates the branch in the path, or moves it if its head is still the expected one
+pub async fn update(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name, branch)): Path<(String, String, String)>,
+    Json(payload): Json<UpdateBranchRequest>,
+) -> Result<Response, StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    let update = BranchUpdate {
+        repo: repository.id,
+        name: branch.clone(),
+        client: MLSClientId(payload.client),
+        expected: payload.expected.clone().map(CommitHash),
+        head: CommitHash(payload.head.clone()),
+    };
+    let outcome = logic::branch::update_branch(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.commit_repository,
+        &state.branch_repository,
+        &state.events,
+        uid.0,
+        update,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, client = %payload.client, repository = %repository.id, branch = %branch, error = %e, "Could not update branch");
+        StatusCode::from(e)
+    })?;
+    match outcome {
+        BranchOutcome::Updated(updated) => {
+            tracing::info!(user_id = %uid.0, repository = %repository.id, branch = %branch, head = %updated.head.0, "Updated branch");
+            let status = if payload.expected.is_some() { StatusCode::OK } else { StatusCode::CREATED };
+            Ok((status, Json(BranchResponse::from(updated))).into_response())
+        }
+        BranchOutcome::Conflict(current) => {
+            tracing::info!(user_id = %uid.0, repository = %repository.id, branch = %branch, "Rejected stale branch update");
+            let conflict = BranchConflictResponse {
+                name: branch,
+                head: current.map(|current| current.head.0),
+            };
+            Ok((StatusCode::CONFLICT, Json(conflict)).into_response())
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use crate::logic::commit::Push;
+use crate::models::repository::{Commit, CommitHash, EncryptedChangeSet, EncryptedCommitMessage};
+use crate::models::user::MLSClientId;
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use axum::response::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct RepositorySlug {
+    pub owner: String,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PushCommit {
+    pub hash: String,
+    pub parents: Vec<String>,
+    pub author: Uuid,    // MLS client
+    pub changes: String, // base64 encrypted change set
+    pub message: String, // base64 encrypted commit message
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PushRequest {
+    pub repo: RepositorySlug,
+    pub client: Uuid, // the current user's pushing client
+    pub epoch: u64,   // MLS epoch the changes and messages are encrypted in
+    pub commits: Vec<PushCommit>, // parents before children
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CommitResponse {
+    pub hash: String,
+    pub repository: String,
+    pub parents: Vec<String>,
+    pub author: String,
+    pub created: i64, // milliseconds since the unix epoch
+}
+
+impl From<Commit> for CommitResponse {
+    fn from(commit: Commit) -> Self {
+        CommitResponse {
+            hash: commit.hash.0,
+            repository: commit.repo.to_string(),
+            parents: commit.parents.into_iter().map(|parent| parent.0).collect(),
+            author: commit.author.0.to_string(),
+            created: commit.created_at.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PushResponse {
+    pub commits: Vec<CommitResponse>,
+}
+
+// Stores a batch of commits, either all of them or none
+pub async fn push(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<PushRequest>,
+) -> Result<(StatusCode, Json<PushResponse>), StatusCode> {
+    let repository =
+        logic::repository::find_repository(&state.repo_repository, &payload.repo.owner, &payload.repo.name)
+            .await
+            .map_err(StatusCode::from)?;
+    let created_at = chrono::Utc::now().naive_utc();
+    let commits = payload
+        .commits
+        .iter()
+        .map(|commit| {
+            Ok(Commit {
+                hash: CommitHash(commit.hash.clone()),
+                repo: repository.id,
+                parents: commit.parents.iter().cloned().map(CommitHash).collect(),
+                author: MLSClientId(commit.author),
+                changes: EncryptedChangeSet(STANDARD.decode(&commit.changes)?),
+                message: EncryptedCommitMessage(STANDARD.decode(&commit.message)?),
+                created_at,
+            })
+        })
+        .collect::<Result<Vec<Commit>, base64::DecodeError>>()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    let push = Push {
+        repo: repository.id,
+        pusher: MLSClientId(payload.client),
+        epoch: i64::try_from(payload.epoch).map_err(|_| StatusCode::BAD_REQUEST)?,
+        commits,
+    };
+    let commits = logic::commit::push_commits(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.commit_repository,
+        &state.broadcast_message_repository,
+        &state.events,
+        uid.0,
+        push,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, client = %payload.client, repository = %repository.id, error = %e, "Could not push commits");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, repository = %repository.id, commits = commits.len(), "Pushed commits");
+    Ok((
+        StatusCode::CREATED,
+        Json(PushResponse {
+            commits: commits.into_iter().map(CommitResponse::from).collect(),
+        }),
+    ))
+}
@@ -13,6 +13,8 @@ impl From<ServiceError> for StatusCode {
             ServiceError::InvalidInput(_) => StatusCode::BAD_REQUEST,
             ServiceError::AuthenticationError(_) => StatusCode::UNAUTHORIZED,
             ServiceError::AuthorizationError(_) => StatusCode::FORBIDDEN,
+            ServiceError::PaymentRequired(_) => StatusCode::PAYMENT_REQUIRED,
+            ServiceError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
             ServiceError::Unknown(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
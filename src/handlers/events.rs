@@ -0,0 +1,308 @@
+use crate::logic::events::{RepositorySubscription, Subscription};
+use crate::models::repository::RepositoryEvent;
+use crate::models::user::MLSClientId;
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code};
+use axum::extract::{Path, Query, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{Response, Result};
+use futures::stream::{self, AbortHandle, BoxStream, SelectAll, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use uuid::Uuid;
+
+// Sent by the server. Events only announce what is new, the messages themselves are fetched from
+// the messages endpoints.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventResponse {
+    Broadcast {
+        repository: String,
+        id: String,
+        sequence: i64,
+        epoch: i64,
+        sender: String,
+        message_type: String,
+    },
+    Unicast {
+        client: String,
+        id: String,
+        sender: String,
+        message_type: String,
+    },
+    Commit {
+        repository: String,
+        hash: String,
+        author: String,
+    },
+    Branch {
+        repository: String,
+        name: String,
+        head: String,
+    },
+    // follows the replayed events. Without `more_available` the subscription is live, otherwise
+    // it ends here and the client subscribes again after the last event it got.
+    Subscribed { repository: String, more_available: bool },
+    Error {
+        repository: Option<String>,
+        status: u16,
+        message: String,
+    },
+}
+
+impl From<RepositoryEvent> for EventResponse {
+    fn from(event: RepositoryEvent) -> Self {
+        match event {
+            RepositoryEvent::Broadcast { repo, id, sequence, epoch, sender, message_type } => EventResponse::Broadcast {
+                repository: repo.to_string(),
+                id: id.to_string(),
+                sequence,
+                epoch,
+                sender: sender.0.to_string(),
+                message_type: message_type.to_string().into(),
+            },
+            RepositoryEvent::Unicast { recipient, id, sender, message_type } => EventResponse::Unicast {
+                client: recipient.0.to_string(),
+                id: id.to_string(),
+                sender: sender.0.to_string(),
+                message_type: message_type.to_string().into(),
+            },
+            RepositoryEvent::Commit { repo, hash, author } => EventResponse::Commit {
+                repository: repo.to_string(),
+                hash: hash.0,
+                author: author.0.to_string(),
+            },
+            RepositoryEvent::Branch { repo, name, head } => EventResponse::Branch {
+                repository: repo.to_string(),
+                name,
+                head: head.0,
+            },
+        }
+    }
+}
+
+// Sent by the client over the socket
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SubscriptionRequest {
+    // `since` is the last sequence number the client saw in the repository, events after it are
+    // replayed first. Without it only new events are sent.
+    Subscribe { repository: Uuid, since: Option<i64> },
+    Unsubscribe { repository: Uuid },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StreamParams {
+    pub client: Uuid, // the member client listening
+    pub since: Option<i64>,
+}
+
+// Opens a WebSocket for the client in the path, which must be one of the current user's. Events
+// for messages sent to the client are always delivered, repository events once subscribed.
+pub async fn connect(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(client): Path<Uuid>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, StatusCode> {
+    let client = MLSClientId(client);
+    let inbox = logic::events::subscribe_client(
+        &state.mls_client_repository,
+        &state.unicast_message_repository,
+        &state.events,
+        uid.0,
+        &client,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, client = %client.0, error = %e, "Could not open event stream");
+        StatusCode::from(e)
+    })?;
+    Ok(ws.on_upgrade(move |socket| stream_events(socket, state, uid.0, client, inbox)))
+}
+
+// Each live stream ends with `None` unless it was cancelled, which means the event bus dropped
+// it and the client has to reconnect and resume
+fn terminated(events: BoxStream<'static, RepositoryEvent>) -> BoxStream<'static, Option<EventResponse>> {
+    events
+        .map(|event| Some(EventResponse::from(event)))
+        .chain(stream::once(async { None }))
+        .boxed()
+}
+
+fn subscribed(repository: Uuid, subscription: &RepositorySubscription) -> EventResponse {
+    EventResponse::Subscribed {
+        repository: repository.to_string(),
+        more_available: subscription.live.is_none(),
+    }
+}
+
+// Replayed events, then `Subscribed`, then new events. A subscription without new events ends
+// quietly after `Subscribed`.
+fn subscription_events(repository: Uuid, subscription: RepositorySubscription) -> BoxStream<'static, Option<EventResponse>> {
+    let subscribed = subscribed(repository, &subscription);
+    let live = match subscription.live {
+        Some(live) => terminated(live),
+        None => stream::empty().boxed(),
+    };
+    stream::iter(subscription.replay)
+        .map(|event| Some(EventResponse::from(event)))
+        .chain(stream::once(async { Some(subscribed) }))
+        .chain(live)
+        .boxed()
+}
+
+async fn send(socket: &mut WebSocket, response: EventResponse) -> bool {
+    match serde_json::to_string(&response) {
+        Ok(text) => socket.send(Message::Text(text.into())).await.is_ok(),
+        Err(_) => false,
+    }
+}
+
+// Shared by both transports
+async fn subscribe(
+    state: &AppState,
+    uid: Uuid,
+    client: &MLSClientId,
+    repository: Uuid,
+    since: Option<i64>,
+) -> Result<RepositorySubscription, StatusCode> {
+    let subscription = Subscription {
+        repo: repository,
+        client: client.clone(),
+        after: since,
+    };
+    logic::events::subscribe_repository(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.broadcast_message_repository,
+        &state.events,
+        uid,
+        &subscription,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid, client = %client.0, repository = %repository, error = %e, "Could not subscribe to repository");
+        StatusCode::from(e)
+    })
+}
+
+async fn stream_events(
+    mut socket: WebSocket,
+    state: AppState,
+    uid: Uuid,
+    client: MLSClientId,
+    inbox: BoxStream<'static, RepositoryEvent>,
+) {
+    let mut streams = SelectAll::new();
+    streams.push(terminated(inbox));
+    let mut subscriptions: HashMap<Uuid, AbortHandle> = HashMap::new();
+    loop {
+        tokio::select! {
+            frame = socket.recv() => {
+                let text = match frame {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    // pings are answered by axum
+                    Some(Ok(_)) => continue,
+                };
+                let response = match serde_json::from_str::<SubscriptionRequest>(text.as_str()) {
+                    Ok(SubscriptionRequest::Subscribe { repository, since }) => {
+                        match subscribe(&state, uid, &client, repository, since).await {
+                            Ok(subscription) => {
+                                let (events, handle) = stream::abortable(subscription_events(repository, subscription));
+                                if let Some(previous) = subscriptions.insert(repository, handle) {
+                                    previous.abort();
+                                }
+                                streams.push(events.boxed());
+                                continue;
+                            }
+                            Err(status) => EventResponse::Error {
+                                repository: Some(repository.to_string()),
+                                status: status.as_u16(),
+                                message: status.canonical_reason().unwrap_or_default().to_string(),
+                            },
+                        }
+                    }
+                    Ok(SubscriptionRequest::Unsubscribe { repository }) => {
+                        if let Some(handle) = subscriptions.remove(&repository) {
+                            handle.abort();
+                        }
+                        continue;
+                    }
+                    Err(e) => EventResponse::Error {
+                        repository: None,
+                        status: StatusCode::BAD_REQUEST.as_u16(),
+                        message: e.to_string(),
+                    },
+                };
+                if !send(&mut socket, response).await {
+                    break;
+                }
+            }
+            Some(event) = streams.next() => {
+                let Some(event) = event else {
+                    let _ = socket
+                        .send(Message::Close(Some(CloseFrame {
+                            code: close_code::AGAIN,
+                            reason: "fell behind, reconnect and resume".into(),
+                        })))
+                        .await;
+                    break;
+                };
+                if !send(&mut socket, event).await {
+                    break;
+                }
+            }
+        }
+    }
+    tracing::info!(user_id = %uid, client = %client.0, "Closed event stream");
+}
+
+// Same repository events as the WebSocket, as Server-Sent Events for networks that block
+// upgrades. Event ids are sequence numbers, so a reconnecting EventSource resumes on its own
+// through Last-Event-ID, which takes precedence over `since`. That is also how it catches up on
+// more missed events than one page holds, the stream ends after the first page.
+pub async fn stream_repository(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Query(params): Query<StreamParams>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, StatusCode> {
+    let last_event_id = match headers.get("last-event-id") {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|id| id.parse::<i64>().ok())
+                .ok_or(StatusCode::BAD_REQUEST)?,
+        ),
+        None => None,
+    };
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    let client = MLSClientId(params.client);
+    let subscription = subscribe(&state, uid.0, &client, repository.id, last_event_id.or(params.since)).await?;
+    // `Subscribed` carries no id, so Last-Event-ID keeps pointing at the last replayed event
+    let subscribed = Event::default()
+        .json_data(subscribed(repository.id, &subscription))
+        .unwrap_or_default();
+    let live = subscription.live.unwrap_or_else(|| stream::empty().boxed());
+    let to_sse = |event: RepositoryEvent| {
+        let mut sse = Event::default();
+        if let Some(sequence) = event.sequence() {
+            sse = sse.id(sequence.to_string());
+        }
+        Ok(sse.json_data(EventResponse::from(event)).unwrap_or_default())
+    };
+    let events = stream::iter(subscription.replay)
+        .map(to_sse)
+        .chain(stream::once(async { Ok(subscribed) }))
+        .chain(live.map(to_sse));
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
@@ -0,0 +1,156 @@
+use crate::models::user::{KeyPackage, KeyPackageInventory, MLSClientId};
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, Path, State};
+use axum::http::StatusCode;
+use axum::response::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyPackageUpload {
+    pub data: String, // base64
+    pub expires: i64, // milliseconds since the unix epoch
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UploadKeyPackagesRequest {
+    #[serde(default)]
+    pub key_packages: Vec<KeyPackageUpload>,
+    pub last_resort: Option<KeyPackageUpload>, // replaces the client's previous one
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UploadKeyPackagesResponse {
+    pub uploaded: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ClaimKeyPackagesRequest {
+    pub claimer: Uuid,      // one of the current user's clients
+    pub clients: Vec<Uuid>, // clients to claim a key package for
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyPackageResponse {
+    pub id: String,
+    pub client_id: String,
+    pub data: String, // base64
+    pub expires: i64, // milliseconds since the unix epoch
+    pub last_resort: bool,
+}
+
+impl From<KeyPackage> for KeyPackageResponse {
+    fn from(package: KeyPackage) -> Self {
+        KeyPackageResponse {
+            id: package.id.to_string(),
+            client_id: package.mls_client_id.0.to_string(),
+            data: STANDARD.encode(&package.kpkg),
+            expires: package.expires_at.and_utc().timestamp_millis(),
+            last_resort: package.last_resort,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct InventoryResponse {
+    pub client_id: String,
+    pub available: u32, // unexpired, unclaimed one-time packages
+    pub has_last_resort: bool,
+    pub target: u32,
+    pub upload: u32, // one-time packages to upload to get back to `target`
+}
+
+impl InventoryResponse {
+    fn new(inventory: KeyPackageInventory, target: u32) -> Self {
+        InventoryResponse {
+            client_id: inventory.mls_client_id.0.to_string(),
+            available: inventory.available,
+            has_last_resort: inventory.has_last_resort,
+            target,
+            upload: inventory.to_upload(target),
+        }
+    }
+}
+
+fn decode_upload(client: Uuid, upload: KeyPackageUpload, last_resort: bool) -> Result<KeyPackage, StatusCode> {
+    let kpkg = STANDARD.decode(upload.data).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let expires_at = DateTime::from_timestamp_millis(upload.expires)
+        .ok_or(StatusCode::BAD_REQUEST)?
+        .naive_utc();
+    Ok(KeyPackage {
+        id: Uuid::new_v4(),
+        mls_client_id: MLSClientId(client),
+        kpkg,
+        expires_at,
+        last_resort,
+    })
+}
+
+pub async fn upload(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UploadKeyPackagesRequest>,
+) -> Result<(StatusCode, Json<UploadKeyPackagesResponse>), StatusCode> {
+    let mut packages = payload
+        .key_packages
+        .into_iter()
+        .map(|upload| decode_upload(id, upload, false))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(upload) = payload.last_resort {
+        packages.push(decode_upload(id, upload, true)?);
+    }
+    let uploaded = logic::key_package::upload_key_packages(
+        &state.mls_client_repository,
+        &state.key_package_repository,
+        uid.0,
+        &MLSClientId(id),
+        packages,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, client_id = %id, error = %e, "Could not upload key packages");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, client_id = %id, uploaded, "Uploaded key packages");
+    Ok((StatusCode::CREATED, Json(UploadKeyPackagesResponse { uploaded })))
+}
+
+pub async fn claim(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<ClaimKeyPackagesRequest>,
+) -> Result<Json<Vec<KeyPackageResponse>>, StatusCode> {
+    let targets: Vec<MLSClientId> = payload.clients.into_iter().map(MLSClientId).collect();
+    let packages = logic::key_package::claim_key_packages(
+        &state.mls_client_repository,
+        &state.key_package_repository,
+        uid.0,
+        &MLSClientId(payload.claimer),
+        &targets,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, claimer = %payload.claimer, error = %e, "Could not claim key packages");
+        StatusCode::from(e)
+    })?;
+    Ok(Json(packages.into_iter().map(KeyPackageResponse::from).collect()))
+}
+
+// Remaining key packages of each of the current user's clients
+pub async fn inventory(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+) -> Result<Json<Vec<InventoryResponse>>, StatusCode> {
+    let target = state.messaging.key_package_target;
+    return logic::key_package::key_package_inventory(&state.key_package_repository, uid.0)
+        .await
+        .map_err(|e| e.into())
+        .map(|inventory| {
+            Json(inventory.into_iter().map(|client| InventoryResponse::new(client, target)).collect())
+        });
+}
@@ -0,0 +1,279 @@
+use crate::logic::message::{BroadcastOutcome, BroadcastReader, FetchOptions};
+use crate::models::repository::{BroadcastMessage, MessageType, UnicastMessage};
+use crate::models::user::MLSClientId;
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct SendUnicastRequest {
+    pub sender: Uuid,         // one of the current user's clients
+    pub message_type: String, // proposal | commit | welcome | application
+    pub payload: String,      // base64 MLS message
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct UnicastMessageResponse {
+    pub id: String,
+    pub sender: String,
+    pub recipient: String,
+    pub message_type: String,
+    pub payload: String, // base64
+    pub created: i64,    // milliseconds since the unix epoch
+}
+
+impl From<UnicastMessage> for UnicastMessageResponse {
+    fn from(message: UnicastMessage) -> Self {
+        UnicastMessageResponse {
+            id: message.id.to_string(),
+            sender: message.sender.0.to_string(),
+            recipient: message.recipient.0.to_string(),
+            message_type: message.message_type.to_string().into(),
+            payload: STANDARD.encode(&message.payload),
+            created: message.created_at.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendBroadcastRequest {
+    pub sender: Uuid,         // one of the current user's clients that is a member of the repository
+    pub message_type: String, // proposal | commit | application
+    pub epoch: u64,           // MLS epoch the sender's group state is in
+    pub payload: String,      // base64 MLS message
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BroadcastMessageResponse {
+    pub id: String,
+    pub repository: String,
+    pub sender: String,
+    pub message_type: String,
+    pub payload: String, // base64
+    pub sequence: i64,
+    pub epoch: i64,
+    pub created: i64, // milliseconds since the unix epoch
+}
+
+impl From<BroadcastMessage> for BroadcastMessageResponse {
+    fn from(message: BroadcastMessage) -> Self {
+        BroadcastMessageResponse {
+            id: message.id.to_string(),
+            repository: message.repo.to_string(),
+            sender: message.sender.0.to_string(),
+            message_type: message.message_type.to_string().into(),
+            payload: STANDARD.encode(&message.payload),
+            sequence: message.sequence,
+            epoch: message.epoch,
+            created: message.created_at.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FetchParams {
+    pub limit: Option<u32>,
+    pub wait: Option<u64>, // seconds to wait for a message if there are none yet
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcknowledgeRequest {
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct FetchBroadcastParams {
+    pub client: Uuid, // the reading client
+    pub limit: Option<u32>,
+    pub wait: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcknowledgeBroadcastRequest {
+    pub client: Uuid,
+    pub ids: Vec<Uuid>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AcknowledgeResponse {
+    pub acknowledged: u64,
+}
+
+// Sends a message to the client in the path
+pub async fn send_unicast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(recipient): Path<Uuid>,
+    Json(payload): Json<SendUnicastRequest>,
+) -> Result<(StatusCode, Json<UnicastMessageResponse>), StatusCode> {
+    let message = UnicastMessage {
+        id: Uuid::new_v4(),
+        recipient: MLSClientId(recipient),
+        sender: MLSClientId(payload.sender),
+        message_type: MessageType::from_string(&payload.message_type).ok_or(StatusCode::BAD_REQUEST)?,
+        payload: STANDARD.decode(&payload.payload).map_err(|_| StatusCode::BAD_REQUEST)?,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    let message = logic::message::send_unicast(
+        &state.mls_client_repository,
+        &state.unicast_message_repository,
+        &state.events,
+        uid.0,
+        message,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, sender = %payload.sender, recipient = %recipient, error = %e, "Could not send message");
+        StatusCode::from(e)
+    })?;
+    Ok((StatusCode::CREATED, Json(UnicastMessageResponse::from(message))))
+}
+
+// Pending messages for the client in the path, which must be one of the current user's. With
+// `wait`, blocks until one arrives or the wait is over.
+pub async fn fetch_unicast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(client): Path<Uuid>,
+    Query(params): Query<FetchParams>,
+) -> Result<Json<Vec<UnicastMessageResponse>>, StatusCode> {
+    let options = FetchOptions {
+        limit: params.limit,
+        wait: params.wait,
+    };
+    return logic::message::fetch_unicast(
+        &state.mls_client_repository,
+        &state.unicast_message_repository,
+        &state.waiters,
+        uid.0,
+        &MLSClientId(client),
+        &options,
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|messages| Json(messages.into_iter().map(UnicastMessageResponse::from).collect()));
+}
+
+pub async fn acknowledge_unicast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(client): Path<Uuid>,
+    Json(payload): Json<AcknowledgeRequest>,
+) -> Result<Json<AcknowledgeResponse>, StatusCode> {
+    return logic::message::acknowledge_unicast(
+        &state.mls_client_repository,
+        &state.unicast_message_repository,
+        uid.0,
+        &MLSClientId(client),
+        &payload.ids,
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|acknowledged| Json(AcknowledgeResponse { acknowledged }));
+}
+
+// Sends a message to every other member client of the repository in the path. A commit for an
+// epoch that already has one is rejected with 409 and the commit that won.
+pub async fn send_broadcast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Json(payload): Json<SendBroadcastRequest>,
+) -> Result<(StatusCode, Json<BroadcastMessageResponse>), StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    let message = BroadcastMessage {
+        id: Uuid::new_v4(),
+        repo: repository.id,
+        sender: MLSClientId(payload.sender),
+        message_type: MessageType::from_string(&payload.message_type).ok_or(StatusCode::BAD_REQUEST)?,
+        payload: STANDARD.decode(&payload.payload).map_err(|_| StatusCode::BAD_REQUEST)?,
+        sequence: 0,
+        epoch: i64::try_from(payload.epoch).map_err(|_| StatusCode::BAD_REQUEST)?,
+        created_at: chrono::Utc::now().naive_utc(),
+    };
+    let outcome = logic::message::send_broadcast(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.broadcast_message_repository,
+        &state.events,
+        uid.0,
+        message,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, sender = %payload.sender, repository = %repository.id, error = %e, "Could not broadcast message");
+        StatusCode::from(e)
+    })?;
+    match outcome {
+        BroadcastOutcome::Accepted(message) => {
+            Ok((StatusCode::CREATED, Json(BroadcastMessageResponse::from(message))))
+        }
+        BroadcastOutcome::Conflict(winner) => {
+            tracing::info!(user_id = %uid.0, sender = %payload.sender, repository = %repository.id, epoch = winner.epoch, "Rejected concurrent commit");
+            Ok((StatusCode::CONFLICT, Json(BroadcastMessageResponse::from(winner))))
+        }
+    }
+}
+
+// Messages of the repository in the path that the given member client has not acknowledged yet
+pub async fn fetch_broadcast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Query(params): Query<FetchBroadcastParams>,
+) -> Result<Json<Vec<BroadcastMessageResponse>>, StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    let reader = BroadcastReader {
+        repo: repository.id,
+        client: MLSClientId(params.client),
+    };
+    let options = FetchOptions {
+        limit: params.limit,
+        wait: params.wait,
+    };
+    return logic::message::fetch_broadcast(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.broadcast_message_repository,
+        &state.waiters,
+        uid.0,
+        &reader,
+        &options,
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|messages| Json(messages.into_iter().map(BroadcastMessageResponse::from).collect()));
+}
+
+pub async fn acknowledge_broadcast(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Json(payload): Json<AcknowledgeBroadcastRequest>,
+) -> Result<Json<AcknowledgeResponse>, StatusCode> {
+    let repository = logic::repository::find_repository(&state.repo_repository, &owner, &name)
+        .await
+        .map_err(StatusCode::from)?;
+    return logic::message::acknowledge_broadcast(
+        &state.mls_client_repository,
+        &state.repo_repository,
+        &state.broadcast_message_repository,
+        uid.0,
+        repository.id,
+        &MLSClientId(payload.client),
+        &payload.ids,
+    )
+    .await
+    .map_err(|e| e.into())
+    .map(|acknowledged| Json(AcknowledgeResponse { acknowledged }));
+}
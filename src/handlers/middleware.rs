@@ -1,12 +1,15 @@
 use crate::AppState;
 use crate::logic::auth::login_user;
+use crate::models::account::SubscriptionType;
 use axum::extract::Request;
+use axum::{Extension, Router};
 use axum::extract::State;
 use axum::http::StatusCode;
 use axum::middleware::Next;
-use axum::response::{IntoResponse, Result};
+use axum::response::{IntoResponse, Response, Result};
 use firebase_auth::FirebaseUser;
 use tracing::Level;
+use uuid::Uuid;
 
 pub async fn with_authenticated(
     State(state): State<AppState>,
@@ -41,6 +44,92 @@ pub async fn with_authenticated(
     Ok(next.run(req).await)
 }
 
+// Set on responses to users whose plan has lapsed but who are still inside the grace period
+pub const SUBSCRIPTION_WARNING_HEADER: &str = "x-subscription-warning";
+
+// Entitlement layers. These must be added before `with_authenticated` in the router so that they
+// run after it and can read the user id it inserts. Requests from users without an active plan
+// that includes the required one are rejected with 402 Payment Required. Plans that lapsed less
+// than the grace period ago still pass, but the response carries `SUBSCRIPTION_WARNING_HEADER`.
+pub async fn with_cloud_sync(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, StatusCode> {
+    with_entitlement(state, uid.0, SubscriptionType::CloudSync, req, next).await
+}
+
+pub async fn with_sync_collaborate(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, StatusCode> {
+    with_entitlement(state, uid.0, SubscriptionType::SyncCollaborate, req, next).await
+}
+
+// Puts a group of routes behind the layer for `plan`. Sync and sharing endpoints are added to
+// their group in `main`, so each one is gated by the group it is declared in. `route_layer` only
+// wraps routes that already exist and panics on a group without any, so empty groups are
+// returned as they are.
+pub fn require_plan(routes: Router<AppState>, state: &AppState, plan: SubscriptionType) -> Router<AppState> {
+    if !routes.has_routes() {
+        return routes;
+    }
+    match plan {
+        SubscriptionType::CloudSync => {
+            routes.route_layer(axum::middleware::from_fn_with_state(state.clone(), with_cloud_sync))
+        }
+        SubscriptionType::SyncCollaborate => {
+            routes.route_layer(axum::middleware::from_fn_with_state(state.clone(), with_sync_collaborate))
+        }
+    }
+}
+
+async fn with_entitlement(
+    state: AppState,
+    uid: Uuid,
+    required: SubscriptionType,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let grace_period = state.billing.grace_period;
+    let sub_info = crate::logic::user::require_entitlement(&state.subscription_repository, uid, required, grace_period)
+        .await
+        .map_err(|e| {
+            tracing::info!(user_id = %uid, error = %e, "Rejected request without required plan");
+            StatusCode::from(e)
+        })?;
+    let grace_ends = (!sub_info.is_active()).then(|| sub_info.paid_until + grace_period);
+    req.extensions_mut().insert(sub_info);
+    let mut response = next.run(req).await;
+    if let Some(grace_ends) = grace_ends {
+        let warning = format!("expired; access ends {}", grace_ends.and_utc().to_rfc3339());
+        response
+            .headers_mut()
+            .insert(SUBSCRIPTION_WARNING_HEADER, warning.parse().unwrap());
+    }
+    Ok(response)
+}
+
+// Restricts routes to admins. Like the entitlement layers it must be added before
+// `with_authenticated`. Everyone else gets 403 Forbidden.
+pub async fn with_admin(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    req: Request,
+    next: Next,
+) -> Result<impl IntoResponse, StatusCode> {
+    crate::logic::user::require_admin(&state.user_repository, uid.0)
+        .await
+        .map_err(|e| {
+            tracing::warn!(user_id = %uid.0, error = %e, "Rejected admin request");
+            StatusCode::from(e)
+        })?;
+    Ok(next.run(req).await)
+}
+
 pub async fn with_logging(req: Request, next: Next) -> Result<impl IntoResponse, StatusCode> {
     let log_id = uuid::Uuid::new_v4();
 
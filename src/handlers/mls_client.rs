@@ -0,0 +1,83 @@
+use crate::models::user::{MLSClient, MLSClientId};
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, Path, State};
+use axum::http::StatusCode;
+use axum::response::Result;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct ClientResponse {
+    pub id: String,
+    pub name: Option<String>,
+    pub created: i64, // milliseconds since the unix epoch
+}
+
+impl From<MLSClient> for ClientResponse {
+    fn from(client: MLSClient) -> Self {
+        ClientResponse {
+            id: client.id.0.to_string(),
+            name: client.name,
+            created: client.created.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct ClientRequest {
+    pub name: Option<String>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+) -> Result<Json<Vec<ClientResponse>>, StatusCode> {
+    return logic::mls_client::list_clients(&state.mls_client_repository, uid.0)
+        .await
+        .map_err(|e| e.into())
+        .map(|clients| Json(clients.into_iter().map(ClientResponse::from).collect()));
+}
+
+pub async fn register(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    payload: Option<Json<ClientRequest>>,
+) -> Result<(StatusCode, Json<ClientResponse>), StatusCode> {
+    let Json(payload) = payload.unwrap_or_default();
+    let client = logic::mls_client::register_client(&state.mls_client_repository, uid.0, payload.name.as_deref())
+        .await
+        .map_err(|e| {
+            tracing::info!(user_id = %uid.0, error = %e, "Could not register client");
+            StatusCode::from(e)
+        })?;
+    tracing::info!(user_id = %uid.0, client_id = %client.id.0, "Registered client");
+    Ok((StatusCode::CREATED, Json(ClientResponse::from(client))))
+}
+
+pub async fn rename(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<ClientRequest>,
+) -> Result<Json<ClientResponse>, StatusCode> {
+    return logic::mls_client::rename_client(&state.mls_client_repository, uid.0, &MLSClientId(id), payload.name.as_deref())
+        .await
+        .map_err(|e| e.into())
+        .map(|client| Json(ClientResponse::from(client)));
+}
+
+pub async fn revoke(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    logic::mls_client::revoke_client(&state.mls_client_repository, uid.0, &MLSClientId(id))
+        .await
+        .map_err(|e| {
+            tracing::info!(user_id = %uid.0, client_id = %id, error = %e, "Could not revoke client");
+            StatusCode::from(e)
+        })?;
+    tracing::info!(user_id = %uid.0, client_id = %id, "Revoked client");
+    Ok(StatusCode::NO_CONTENT)
+}
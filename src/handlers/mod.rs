@@ -1,5 +1,14 @@
 pub mod auth;
 pub mod status;
 pub mod account;
+pub mod billing;
+pub mod promo;
+pub mod repository;
+pub mod mls_client;
+pub mod key_package;
+pub mod message;
+pub mod events;
+pub mod commit;
+pub mod branch;
 pub mod middleware;
 mod error;
@@ -0,0 +1,110 @@
+use crate::AppState;
+use crate::logic;
+use crate::models::account::PromoCode;
+use crate::models::account::PromoReward;
+use crate::models::account::SubscriptionType;
+use axum::Extension;
+use axum::extract::{Json, State};
+use axum::http::StatusCode;
+use axum::response::Result;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct RedeemPromoRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RedeemPromoResponse {
+    pub plan: String,
+    pub paid_until: i64, // milliseconds since the unix epoch
+}
+
+pub async fn redeem(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<RedeemPromoRequest>,
+) -> Result<Json<RedeemPromoResponse>, StatusCode> {
+    let sub_info = logic::promo::redeem_promo_code(
+        &state.promo_code_repository,
+        &state.subscription_repository,
+        uid.0,
+        &payload.code,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, error = %e, "Promo code redemption rejected");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, paid_until = %sub_info.paid_until, "Redeemed promo code");
+    Ok(Json(RedeemPromoResponse {
+        plan: sub_info.subscription_type.to_string().into(),
+        paid_until: sub_info.paid_until.and_utc().timestamp_millis(),
+    }))
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MintPromoRequest {
+    pub code: String,
+    pub percent_off: Option<u32>, // set exactly one of percent_off and free_months
+    pub free_months: Option<u32>,
+    pub plan: Option<String>, // required for free months, optional restriction for discounts
+    pub max_redemptions: Option<u32>,
+    pub expires: Option<i64>, // milliseconds since the unix epoch
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MintPromoResponse {
+    pub code: String,
+}
+
+pub async fn mint(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<MintPromoRequest>,
+) -> Result<(StatusCode, Json<MintPromoResponse>), StatusCode> {
+    let reward = match (payload.percent_off, payload.free_months) {
+        (Some(percent), None) => PromoReward::PercentOff(percent),
+        (None, Some(months)) => PromoReward::FreeMonths(months),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    let subscription_type = match payload.plan {
+        Some(plan) => Some(SubscriptionType::from_string(&plan).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+    let expires = match payload.expires {
+        Some(ms) => Some(
+            DateTime::from_timestamp_millis(ms)
+                .ok_or(StatusCode::BAD_REQUEST)?
+                .naive_utc(),
+        ),
+        None => None,
+    };
+    let promo_code = logic::promo::mint_promo_code(
+        &state.promo_code_repository,
+        uid.0,
+        PromoCode {
+            code: payload.code,
+            reward,
+            subscription_type,
+            max_redemptions: payload.max_redemptions,
+            redemptions: 0,
+            expires,
+            stripe_coupon_id: None,
+        },
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, error = %e, "Could not mint promo code");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, code = %promo_code.code, "Minted promo code");
+    Ok((
+        StatusCode::CREATED,
+        Json(MintPromoResponse {
+            code: promo_code.code,
+        }),
+    ))
+}
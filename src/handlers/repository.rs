@@ -0,0 +1,261 @@
+use crate::logic::error::ServiceError;
+use crate::models::repository::{Repository, RepositoryTransfer};
+use crate::models::user::MLSClientId;
+use crate::repository::error::RepoError;
+use crate::{AppState, logic};
+use axum::Extension;
+use axum::extract::{Json, Path, State};
+use axum::http::StatusCode;
+use axum::response::{ErrorResponse, Redirect, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize)]
+pub struct RepositoryResponse {
+    pub id: String,
+    pub identifier: String, // owner/name
+    pub owner: String,      // username, or user id if the owner has no username
+    pub owner_id: String,
+    pub name: String,
+    pub members: Vec<String>, // MLS client ids
+}
+
+impl From<Repository> for RepositoryResponse {
+    fn from(repository: Repository) -> Self {
+        RepositoryResponse {
+            id: repository.id.to_string(),
+            identifier: repository.identifier(),
+            owner: repository.owner_name,
+            owner_id: repository.owner_id.to_string(),
+            name: repository.name,
+            members: repository
+                .members
+                .into_iter()
+                .map(|member| member.0.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferResponse {
+    pub id: String,
+    pub repository_id: String,
+    pub repository: String, // owner/name before the transfer
+    pub from: String,       // username, or user id if unset
+    pub from_id: String,
+    pub to: String,
+    pub to_id: String,
+    pub created: i64, // milliseconds since the unix epoch
+}
+
+impl From<RepositoryTransfer> for TransferResponse {
+    fn from(transfer: RepositoryTransfer) -> Self {
+        TransferResponse {
+            id: transfer.id.to_string(),
+            repository_id: transfer.repo_id.to_string(),
+            repository: format!("{}/{}", transfer.from_name, transfer.repo_name),
+            from: transfer.from_name,
+            from_id: transfer.from_user.to_string(),
+            to: transfer.to_name,
+            to_id: transfer.to_user.to_string(),
+            created: transfer.created.and_utc().timestamp_millis(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferRequest {
+    pub to: String, // recipient's username or user id
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CreateRepositoryRequest {
+    pub name: String,
+    pub client: Option<Uuid>, // the current user's client that founds the MLS group, added as admin
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RenameRepositoryRequest {
+    pub name: String,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+) -> Result<Json<Vec<RepositoryResponse>>, StatusCode> {
+    return logic::repository::list_repositories(&state.repo_repository, uid.0)
+        .await
+        .map_err(|e| {
+            tracing::warn!(user_id = %uid.0, error = %e, "Error listing repositories");
+            e.into()
+        })
+        .map(|repositories| Json(repositories.into_iter().map(RepositoryResponse::from).collect()));
+}
+
+// Turns a missing repository into a redirect if its slug was recently transferred away.
+// The location is relative to /repositories/{owner}/{name} so it survives path prefixes. The
+// redirect is temporary, since the old slug may be claimed again once the redirect period ends.
+async fn moved_or(state: &AppState, owner: &str, name: &str, error: ServiceError) -> ErrorResponse {
+    if !matches!(error, ServiceError::RepositoryError(RepoError::NotFound(_))) {
+        return StatusCode::from(error).into();
+    }
+    match logic::repository::find_moved_repository(&state.repo_repository, owner, name).await {
+        Ok(Some(repository)) => Redirect::temporary(&format!(
+            "../{}/{}",
+            urlencoding::encode(&repository.owner_name),
+            urlencoding::encode(&repository.name)
+        ))
+        .into(),
+        Ok(None) => StatusCode::from(error).into(),
+        Err(e) => StatusCode::from(e).into(),
+    }
+}
+
+// Like `moved_or`, but for requests that change the repository. Those are not redirected, a
+// client replaying them against the new slug would act on a repository it never named, so a
+// recently transferred slug answers 410 Gone instead.
+async fn gone_or(state: &AppState, owner: &str, name: &str, error: ServiceError) -> StatusCode {
+    if !matches!(error, ServiceError::RepositoryError(RepoError::NotFound(_))) {
+        return StatusCode::from(error);
+    }
+    match logic::repository::find_moved_repository(&state.repo_repository, owner, name).await {
+        Ok(Some(_)) => StatusCode::GONE,
+        Ok(None) => StatusCode::from(error),
+        Err(e) => StatusCode::from(e),
+    }
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+) -> Result<Json<RepositoryResponse>> {
+    match logic::repository::get_repository(&state.repo_repository, uid.0, &owner, &name).await {
+        Ok(repository) => Ok(Json(RepositoryResponse::from(repository))),
+        Err(e) => Err(moved_or(&state, &owner, &name, e).await),
+    }
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Json(payload): Json<CreateRepositoryRequest>,
+) -> Result<(StatusCode, Json<RepositoryResponse>), StatusCode> {
+    let founder = payload.client.map(MLSClientId);
+    let repository = logic::repository::create_repository(
+        &state.repo_repository,
+        &state.mls_client_repository,
+        uid.0,
+        &payload.name,
+        founder.as_ref(),
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, name = %payload.name, error = %e, "Could not create repository");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, repository = %repository.identifier(), "Created repository");
+    Ok((StatusCode::CREATED, Json(RepositoryResponse::from(repository))))
+}
+
+pub async fn rename(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Json(payload): Json<RenameRepositoryRequest>,
+) -> Result<Json<RepositoryResponse>> {
+    let repository = match logic::repository::rename_repository(&state.repo_repository, uid.0, &owner, &name, &payload.name).await {
+        Ok(repository) => repository,
+        Err(e) => {
+            tracing::info!(user_id = %uid.0, name = %name, error = %e, "Could not rename repository");
+            return Err(gone_or(&state, &owner, &name, e).await.into());
+        }
+    };
+    tracing::info!(user_id = %uid.0, from = %name, repository = %repository.identifier(), "Renamed repository");
+    Ok(Json(RepositoryResponse::from(repository)))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+) -> Result<StatusCode> {
+    if let Err(e) = logic::repository::delete_repository(&state.repo_repository, uid.0, &owner, &name).await {
+        tracing::info!(user_id = %uid.0, name = %name, error = %e, "Could not delete repository");
+        return Err(gone_or(&state, &owner, &name, e).await.into());
+    }
+    tracing::info!(user_id = %uid.0, owner = %owner, name = %name, "Deleted repository");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn offer_transfer(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path((owner, name)): Path<(String, String)>,
+    Json(payload): Json<TransferRequest>,
+) -> Result<(StatusCode, Json<TransferResponse>), StatusCode> {
+    let repository = logic::repository::get_repository(&state.repo_repository, uid.0, &owner, &name).await?;
+    let transfer = logic::repository::offer_transfer(
+        &state.repo_repository,
+        &state.repo_transfer_repository,
+        &state.user_repository,
+        &state.subscription_repository,
+        uid.0,
+        &repository,
+        &payload.to,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, repository = %repository.identifier(), to = %payload.to, error = %e, "Could not offer repository transfer");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, transfer_id = %transfer.id, repository = %repository.identifier(), to = %transfer.to_user, "Offered repository transfer");
+    Ok((StatusCode::CREATED, Json(TransferResponse::from(transfer))))
+}
+
+pub async fn list_transfers(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+) -> Result<Json<Vec<TransferResponse>>, StatusCode> {
+    return logic::repository::list_transfers(&state.repo_transfer_repository, uid.0)
+        .await
+        .map_err(|e| e.into())
+        .map(|transfers| Json(transfers.into_iter().map(TransferResponse::from).collect()));
+}
+
+pub async fn accept_transfer(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<RepositoryResponse>, StatusCode> {
+    let repository = logic::repository::accept_transfer(
+        &state.repo_repository,
+        &state.repo_transfer_repository,
+        &state.subscription_repository,
+        uid.0,
+        id,
+    )
+    .await
+    .map_err(|e| {
+        tracing::info!(user_id = %uid.0, transfer_id = %id, error = %e, "Could not accept repository transfer");
+        StatusCode::from(e)
+    })?;
+    tracing::info!(user_id = %uid.0, transfer_id = %id, repository = %repository.identifier(), "Accepted repository transfer");
+    Ok(Json(RepositoryResponse::from(repository)))
+}
+
+pub async fn cancel_transfer(
+    State(state): State<AppState>,
+    uid: Extension<Uuid>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, StatusCode> {
+    logic::repository::cancel_transfer(&state.repo_repository, &state.repo_transfer_repository, uid.0, id)
+        .await
+        .map_err(|e| {
+            tracing::info!(user_id = %uid.0, transfer_id = %id, error = %e, "Could not cancel repository transfer");
+            StatusCode::from(e)
+        })?;
+    tracing::info!(user_id = %uid.0, transfer_id = %id, "Cancelled repository transfer");
+    Ok(StatusCode::NO_CONTENT)
+}
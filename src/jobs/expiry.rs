@@ -0,0 +1,25 @@
+use crate::logic::notification::{ExpiryNotifier, notify_expiring};
+use crate::repository::subscription::SubscriptionRepository;
+use chrono::TimeDelta;
+use std::time::Duration;
+
+// How often the job looks for plans that are about to lapse
+pub const EXPIRY_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Runs forever, reminding users whose plan lapses within `notice_period`. Meant to be spawned
+// next to the API server.
+pub async fn run<N: ExpiryNotifier>(
+    subscription_repository: SubscriptionRepository,
+    notifier: N,
+    notice_period: TimeDelta,
+) {
+    let mut interval = tokio::time::interval(EXPIRY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match notify_expiring(&subscription_repository, &notifier, notice_period).await {
+            Ok(0) => {}
+            Ok(sent) => tracing::info!(sent, "Sent expiry notifications"),
+            Err(e) => tracing::error!(error = %e, "Expiry notification run failed"),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use crate::logic::key_package::sweep_key_packages;
+use crate::repository::key_package::KeyPackageRepository;
+use std::time::Duration;
+
+// How often claimed and expired key packages are purged
+pub const KEY_PACKAGE_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Runs forever, hard deleting key packages that can no longer be handed out. Meant to be spawned
+// next to the API server.
+pub async fn run(key_package_repository: KeyPackageRepository) {
+    let mut interval = tokio::time::interval(KEY_PACKAGE_SWEEP_INTERVAL);
+    loop {
+        interval.tick().await;
+        match sweep_key_packages(&key_package_repository).await {
+            Ok(0) => {}
+            Ok(deleted) => tracing::info!(deleted, "Swept stale key packages"),
+            Err(e) => tracing::error!(error = %e, "Key package sweep failed"),
+        }
+    }
+}
@@ -0,0 +1,31 @@
+use crate::logic::events::MessageWaiters;
+use crate::repository::message_notification::{MessageNotificationRepository, MessageNotificationRepositoryTrait};
+use std::time::Duration;
+
+// How long to wait before listening again after the connection could not be set up
+pub const LISTEN_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Runs forever, waking long-polling fetches on this instance whenever any instance stores a
+// message. Meant to be spawned next to the API server.
+pub async fn run(notification_repository: MessageNotificationRepository, waiters: MessageWaiters) {
+    loop {
+        let mut listener = match notification_repository.listen().await {
+            Ok(listener) => listener,
+            Err(e) => {
+                tracing::error!(error = %e, "Could not listen for stored messages");
+                tokio::time::sleep(LISTEN_RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+        loop {
+            match listener.recv().await {
+                Ok(Some(topic)) => waiters.wake(&topic),
+                Ok(None) => {}
+                Err(e) => {
+                    tracing::error!(error = %e, "Lost message notifications");
+                    break;
+                }
+            }
+        }
+    }
+}
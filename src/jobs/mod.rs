@@ -0,0 +1,3 @@
+pub mod expiry;
+pub mod key_packages;
+pub mod message_wakeups;
@@ -5,8 +5,12 @@ use crate::repository::user::UserRepositoryTrait;
 pub async fn register_user<T: UserRepositoryTrait>(
     user_repository: &T,
     eml: &str,
+    username: Option<&str>,
 ) -> Result<Uuid, ServiceError> {
-    let uid = user_repository.create(&eml).await;
+    if let Some(username) = username {
+        crate::logic::user::validate_username(username)?;
+    }
+    let uid = user_repository.create(eml, username).await;
     return uid
         .map_err(|e| ServiceError::from(e));
 }
@@ -0,0 +1,169 @@
+use crate::logic::commit::{ensure_contributor, validate_hash};
+use crate::logic::error::ServiceError;
+use crate::logic::events::{publish_event, EventBus};
+use crate::logic::message::ensure_member;
+use crate::logic::mls_client::get_client;
+use crate::models::repository::{Branch, CommitHash, EventTopic, RepositoryEvent};
+use crate::models::user::MLSClientId;
+use crate::repository::branch::BranchRepositoryTrait;
+use crate::repository::commit::CommitRepositoryTrait;
+use crate::repository::error::RepoError;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::repository::RepoRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use uuid::Uuid;
+
+pub const MAX_BRANCH_NAME_LENGTH: usize = 100;
+
+// Branch names end up in URL paths, so they are limited to letters, digits, '-', '_' and '.',
+// starting with a letter or digit
+pub fn validate_branch_name(name: &str) -> Result<(), ServiceError> {
+    let valid_start = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+    if !valid_start
+        || name.len() > MAX_BRANCH_NAME_LENGTH
+        || !name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(ServiceError::InvalidInput(format!(
+            "branch names are 1 to {} letters, digits, '-', '_' or '.', starting with a letter or digit",
+            MAX_BRANCH_NAME_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+// Moves branch `name` to `head` if it still points at `expected`. Without `expected` the branch
+// is created and must not exist yet.
+#[derive(Debug, Clone)]
+pub struct BranchUpdate {
+    pub repo: Uuid,
+    pub name: String,
+    pub client: MLSClientId,
+    pub expected: Option<CommitHash>,
+    pub head: CommitHash,
+}
+
+#[derive(Debug, Clone)]
+pub enum BranchOutcome {
+    Updated(Branch),
+    // the branch moved since the client last saw it, or does not exist (`None`) or already does
+    Conflict(Option<Branch>),
+}
+
+pub async fn list_branches<C, R, B>(
+    client_repository: &C,
+    repo_repository: &R,
+    branch_repository: &B,
+    uid: Uuid,
+    repo: Uuid,
+    client: &MLSClientId,
+) -> Result<Vec<Branch>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    B: BranchRepositoryTrait,
+{
+    ensure_member(client_repository, repo_repository, uid, repo, client).await?;
+    Ok(branch_repository.list(repo).await?)
+}
+
+pub async fn get_branch<C, R, B>(
+    client_repository: &C,
+    repo_repository: &R,
+    branch_repository: &B,
+    uid: Uuid,
+    repo: Uuid,
+    name: &str,
+    client: &MLSClientId,
+) -> Result<Branch, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    B: BranchRepositoryTrait,
+{
+    ensure_member(client_repository, repo_repository, uid, repo, client).await?;
+    branch_repository
+        .find(repo, name)
+        .await?
+        .ok_or_else(|| RepoError::NotFound(format!("branch {} not found", name)).into())
+}
+
+// Compare-and-swap of a branch head by a contributor client. The new head must be a commit of the
+// repository already, so commits are pushed before the branch is moved to them.
+pub async fn update_branch<C, R, K, B, E>(
+    client_repository: &C,
+    repo_repository: &R,
+    commit_repository: &K,
+    branch_repository: &B,
+    events: &E,
+    uid: Uuid,
+    update: BranchUpdate,
+) -> Result<BranchOutcome, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    K: CommitRepositoryTrait,
+    B: BranchRepositoryTrait + TransactionalRepository,
+    E: EventBus,
+{
+    validate_branch_name(&update.name)?;
+    validate_hash(&update.head)?;
+    if let Some(expected) = &update.expected {
+        validate_hash(expected)?;
+    }
+    get_client(client_repository, uid, &update.client).await?;
+    ensure_contributor(repo_repository, update.repo, &update.client).await?;
+
+    let mut tx = branch_repository.begin().await?;
+    let known = commit_repository
+        .find_existing(&mut tx, update.repo, std::slice::from_ref(&update.head))
+        .await?;
+    if known.is_empty() {
+        return Err(ServiceError::InvalidInput(format!(
+            "commit {} is not in the repository",
+            update.head.0
+        )));
+    }
+    let branch = Branch {
+        repo: update.repo,
+        name: update.name,
+        head: update.head,
+        updated_at: chrono::Utc::now().naive_utc(),
+    };
+    let swapped = match &update.expected {
+        Some(expected) => branch_repository.compare_and_swap(&mut tx, &branch, expected).await?,
+        None => branch_repository.create(&mut tx, &branch).await?,
+    };
+    if !swapped {
+        drop(tx);
+        return Ok(BranchOutcome::Conflict(
+            branch_repository.find(branch.repo, &branch.name).await?,
+        ));
+    }
+    tx.commit().await.map_err(RepoError::from)?;
+
+    publish_event(
+        events,
+        EventTopic::Repository(branch.repo),
+        RepositoryEvent::from(&branch),
+    )
+    .await;
+    Ok(BranchOutcome::Updated(branch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_branch_name, MAX_BRANCH_NAME_LENGTH};
+
+    #[test]
+    fn validate_branch_name_tests() {
+        assert!(validate_branch_name("main").is_ok());
+        assert!(validate_branch_name("laptop_2.0-wip").is_ok());
+        assert!(validate_branch_name(&"a".repeat(MAX_BRANCH_NAME_LENGTH)).is_ok());
+        assert!(validate_branch_name(&"a".repeat(MAX_BRANCH_NAME_LENGTH + 1)).is_err());
+        assert!(validate_branch_name("").is_err());
+        assert!(validate_branch_name(".hidden").is_err());
+        assert!(validate_branch_name("-main").is_err());
+        assert!(validate_branch_name("feature/tabs").is_err());
+        assert!(validate_branch_name("main branch").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use crate::logic::error::ServiceError;
+use crate::logic::events::{publish_event, EventBus};
+use crate::logic::message::{validate_epoch, validate_payload};
+use crate::logic::mls_client::get_client;
+use crate::models::repository::{
+    BroadcastMessage, Commit, CommitHash, EventTopic, MessageType, RepositoryEvent, RepositoryPermission,
+};
+use crate::models::user::MLSClientId;
+use crate::repository::broadcast_message::BroadcastMessageRepositoryTrait;
+use crate::repository::commit::CommitRepositoryTrait;
+use crate::repository::error::RepoError;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::repository::RepoRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use std::collections::HashSet;
+use uuid::Uuid;
+
+pub const MAX_PUSH_BATCH: usize = 100;
+pub const MAX_COMMIT_HASH_LENGTH: usize = 128;
+
+// A batch of commits sent by one of the user's clients. `epoch` is the MLS epoch their contents
+// are encrypted in.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub repo: Uuid,
+    pub pusher: MLSClientId,
+    pub epoch: i64,
+    pub commits: Vec<Commit>,
+}
+
+pub fn validate_hash(hash: &CommitHash) -> Result<(), ServiceError> {
+    if hash.0.is_empty()
+        || hash.0.len() > MAX_COMMIT_HASH_LENGTH
+        || !hash.0.chars().all(|c| c.is_ascii_alphanumeric())
+    {
+        return Err(ServiceError::InvalidInput(format!(
+            "commit hashes are 1 to {} letters and digits",
+            MAX_COMMIT_HASH_LENGTH
+        )));
+    }
+    Ok(())
+}
+
+fn validate_push_batch(commits: &[Commit]) -> Result<(), ServiceError> {
+    if commits.is_empty() || commits.len() > MAX_PUSH_BATCH {
+        return Err(ServiceError::InvalidInput(format!(
+            "push 1 to {} commits at a time",
+            MAX_PUSH_BATCH
+        )));
+    }
+    for commit in commits {
+        validate_hash(&commit.hash)?;
+        commit.parents.iter().try_for_each(validate_hash)?;
+        validate_payload(&commit.changes.0)?;
+        validate_payload(&commit.message.0)?;
+    }
+    Ok(())
+}
+
+// Every parent must be a commit of the repository already, or come earlier in the batch. Pushing
+// a commit the repository has is a conflict.
+fn validate_commit_graph(commits: &[Commit], existing: &HashSet<CommitHash>) -> Result<(), ServiceError> {
+    let mut pushed = HashSet::new();
+    for commit in commits {
+        if existing.contains(&commit.hash) {
+            return Err(RepoError::DuplicateEntry(format!("commit {} already exists", commit.hash.0)).into());
+        }
+        let mut parents = HashSet::new();
+        for parent in &commit.parents {
+            if !parents.insert(parent) {
+                return Err(ServiceError::InvalidInput(format!(
+                    "commit {} lists parent {} twice",
+                    commit.hash.0, parent.0
+                )));
+            }
+            if !existing.contains(parent) && !pushed.contains(parent) {
+                return Err(ServiceError::InvalidInput(format!(
+                    "parent {} of commit {} is unknown",
+                    parent.0, commit.hash.0
+                )));
+            }
+        }
+        if !pushed.insert(&commit.hash) {
+            return Err(ServiceError::InvalidInput(format!(
+                "commit {} is pushed twice",
+                commit.hash.0
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Pushers and authors alike need at least contributor permission
+pub async fn ensure_contributor<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    repo: Uuid,
+    client: &MLSClientId,
+) -> Result<(), ServiceError> {
+    match repo_repository.find_permission(repo, client).await? {
+        Some(permission) if permission.includes(RepositoryPermission::Contributor) => Ok(()),
+        Some(_) => Err(ServiceError::AuthorizationError(format!(
+            "client {} cannot contribute to this repository",
+            client.0
+        ))),
+        None => Err(ServiceError::AuthorizationError(format!(
+            "client {} is not a member of this repository",
+            client.0
+        ))),
+    }
+}
+
+fn contents_message(push: &Push, commit: &Commit, payload: &[u8], sequence: i64) -> BroadcastMessage {
+    BroadcastMessage {
+        id: Uuid::new_v4(),
+        repo: push.repo,
+        sender: push.pusher.clone(),
+        message_type: MessageType::Application,
+        payload: payload.to_vec(),
+        sequence,
+        epoch: push.epoch,
+        created_at: commit.created_at,
+    }
+}
+
+// Stores the batch and sends each commit's encrypted changes and message to the other members as
+// application messages. All or nothing.
+pub async fn push_commits<C, R, K, M, E>(
+    client_repository: &C,
+    repo_repository: &R,
+    commit_repository: &K,
+    message_repository: &M,
+    events: &E,
+    uid: Uuid,
+    push: Push,
+) -> Result<Vec<Commit>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    K: CommitRepositoryTrait + TransactionalRepository,
+    M: BroadcastMessageRepositoryTrait,
+    E: EventBus,
+{
+    validate_push_batch(&push.commits)?;
+    get_client(client_repository, uid, &push.pusher).await?;
+    ensure_contributor(repo_repository, push.repo, &push.pusher).await?;
+    let authors: HashSet<&MLSClientId> = push.commits.iter().map(|commit| &commit.author).collect();
+    for author in authors {
+        ensure_contributor(repo_repository, push.repo, author).await?;
+    }
+
+    let mut tx = commit_repository.begin().await?;
+    // two messages per commit, for its changes and its message. Claiming their sequence numbers
+    // also holds the repository row, so concurrent pushes see each other's commits.
+    let (first_sequence, current_epoch) = repo_repository
+        .next_message_sequence(&mut tx, push.repo, 2 * push.commits.len() as i64)
+        .await?;
+    validate_epoch(push.epoch, current_epoch)?;
+    let referenced: Vec<CommitHash> = push
+        .commits
+        .iter()
+        .flat_map(|commit| std::iter::once(&commit.hash).chain(&commit.parents))
+        .cloned()
+        .collect();
+    let existing: HashSet<CommitHash> = commit_repository
+        .find_existing(&mut tx, push.repo, &referenced)
+        .await?
+        .into_iter()
+        .collect();
+    validate_commit_graph(&push.commits, &existing)?;
+
+    let mut messages = Vec::new();
+    for (commit, sequence) in push.commits.iter().zip((first_sequence..).step_by(2)) {
+        let changes = contents_message(&push, commit, &commit.changes.0, sequence);
+        let message = contents_message(&push, commit, &commit.message.0, sequence + 1);
+        message_repository.create(&mut tx, &changes).await?;
+        message_repository.create(&mut tx, &message).await?;
+        commit_repository.create(&mut tx, commit, changes.id, message.id).await?;
+        messages.push(changes);
+        messages.push(message);
+    }
+    tx.commit().await.map_err(RepoError::from)?;
+
+    let topic = EventTopic::Repository(push.repo);
+    for message in &messages {
+        publish_event(events, topic.clone(), RepositoryEvent::from(message)).await;
+    }
+    for commit in &push.commits {
+        publish_event(events, topic.clone(), RepositoryEvent::from(commit)).await;
+    }
+    Ok(push.commits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_commit_graph, validate_hash, validate_push_batch, MAX_COMMIT_HASH_LENGTH, MAX_PUSH_BATCH};
+    use crate::logic::error::ServiceError;
+    use crate::models::repository::{Commit, CommitHash, EncryptedChangeSet, EncryptedCommitMessage};
+    use crate::models::user::MLSClientId;
+    use crate::repository::error::RepoError;
+    use std::collections::HashSet;
+    use uuid::Uuid;
+
+    fn commit(hash: &str, parents: &[&str]) -> Commit {
+        Commit {
+            hash: CommitHash(hash.to_string()),
+            repo: Uuid::nil(),
+            parents: parents.iter().map(|parent| CommitHash(parent.to_string())).collect(),
+            author: MLSClientId(Uuid::nil()),
+            changes: EncryptedChangeSet(vec![1]),
+            message: EncryptedCommitMessage(vec![2]),
+            created_at: chrono::Utc::now().naive_utc(),
+        }
+    }
+
+    #[test]
+    fn validate_hash_tests() {
+        assert!(validate_hash(&CommitHash("a1b2c3".to_string())).is_ok());
+        assert!(validate_hash(&CommitHash("a".repeat(MAX_COMMIT_HASH_LENGTH))).is_ok());
+        assert!(validate_hash(&CommitHash("a".repeat(MAX_COMMIT_HASH_LENGTH + 1))).is_err());
+        assert!(validate_hash(&CommitHash("".to_string())).is_err());
+        assert!(validate_hash(&CommitHash("../a1".to_string())).is_err());
+    }
+
+    #[test]
+    fn validate_push_batch_tests() {
+        assert!(validate_push_batch(&[commit("a1", &[])]).is_ok());
+        assert!(validate_push_batch(&[]).is_err());
+        let too_many: Vec<Commit> = (0..=MAX_PUSH_BATCH).map(|i| commit(&format!("c{}", i), &[])).collect();
+        assert!(validate_push_batch(&too_many).is_err());
+        let mut empty_changes = commit("a1", &[]);
+        empty_changes.changes = EncryptedChangeSet(vec![]);
+        assert!(validate_push_batch(&[empty_changes]).is_err());
+        assert!(validate_push_batch(&[commit("a1", &["not a hash"])]).is_err());
+    }
+
+    #[test]
+    fn validate_commit_graph_tests() {
+        let existing: HashSet<CommitHash> = [CommitHash("a1".to_string())].into_iter().collect();
+
+        // parents already in the repository or earlier in the batch, including merges
+        assert!(
+            validate_commit_graph(
+                &[commit("b2", &["a1"]), commit("c3", &["a1"]), commit("d4", &["b2", "c3"])],
+                &existing
+            )
+            .is_ok()
+        );
+        assert!(validate_commit_graph(&[commit("b2", &[])], &existing).is_ok());
+
+        // parents later in the batch, unknown or listed twice
+        assert!(validate_commit_graph(&[commit("c3", &["b2"]), commit("b2", &["a1"])], &existing).is_err());
+        assert!(validate_commit_graph(&[commit("b2", &["zz"])], &existing).is_err());
+        assert!(validate_commit_graph(&[commit("b2", &["a1", "a1"])], &existing).is_err());
+        assert!(validate_commit_graph(&[commit("b2", &["b2"])], &existing).is_err());
+        assert!(validate_commit_graph(&[commit("b2", &["a1"]), commit("b2", &["a1"])], &existing).is_err());
+
+        assert!(matches!(
+            validate_commit_graph(&[commit("a1", &[])], &existing),
+            Err(ServiceError::RepositoryError(RepoError::DuplicateEntry(_)))
+        ));
+    }
+}
@@ -12,6 +12,12 @@ pub enum ServiceError {
     #[error("Authorization error: {0}")]
     AuthorizationError(String),
 
+    #[error("Payment required: {0}")]
+    PaymentRequired(String),
+
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
     #[error("Database error: {0}")]
     RepositoryError(#[from] RepoError),
 
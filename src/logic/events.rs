@@ -0,0 +1,286 @@
+use crate::logic::error::ServiceError;
+use crate::logic::message::{ensure_member, DEFAULT_FETCH_LIMIT, MAX_FETCH_LIMIT};
+use crate::logic::mls_client::get_client;
+use crate::models::repository::{EventTopic, RepositoryEvent};
+use crate::models::user::MLSClientId;
+use crate::repository::broadcast_message::BroadcastMessageRepositoryTrait;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::repository::RepoRepositoryTrait;
+use crate::repository::unicast_message::UnicastMessageRepositoryTrait;
+use futures::future::ready;
+use futures::stream::{self, BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::futures::OwnedNotified;
+use tokio::sync::{Notify, broadcast};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+// Events a subscriber may fall behind by before the local bus drops it
+pub const EVENT_BUFFER: usize = 256;
+
+// Fans events out to every subscriber of a topic. Implementations that span server instances
+// go through a shared broker. A subscription ends when the subscriber fell too far behind, it is
+// expected to subscribe again and resume from the last sequence number it saw.
+pub trait EventBus {
+    fn publish(
+        &self,
+        topic: &EventTopic,
+        event: RepositoryEvent,
+    ) -> impl Future<Output = Result<(), ServiceError>> + Send;
+    fn subscribe(
+        &self,
+        topic: &EventTopic,
+    ) -> impl Future<Output = Result<BoxStream<'static, RepositoryEvent>, ServiceError>> + Send;
+}
+
+// Delivers events to subscribers of this server instance only
+#[derive(Clone, Debug, Default)]
+pub struct LocalEventBus {
+    topics: Arc<Mutex<HashMap<EventTopic, broadcast::Sender<RepositoryEvent>>>>,
+}
+
+impl EventBus for LocalEventBus {
+    async fn publish(&self, topic: &EventTopic, event: RepositoryEvent) -> Result<(), ServiceError> {
+        let mut topics = self.topics.lock().map_err(|e| ServiceError::Unknown(e.to_string()))?;
+        if let Some(sender) = topics.get(topic) {
+            // only fails once every subscriber is gone
+            if sender.send(event).is_err() {
+                topics.remove(topic);
+            }
+        }
+        Ok(())
+    }
+
+    async fn subscribe(&self, topic: &EventTopic) -> Result<BoxStream<'static, RepositoryEvent>, ServiceError> {
+        let mut topics = self.topics.lock().map_err(|e| ServiceError::Unknown(e.to_string()))?;
+        let receiver = topics
+            .entry(topic.clone())
+            .or_insert_with(|| broadcast::channel(EVENT_BUFFER).0)
+            .subscribe();
+        Ok(stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.ok().map(|event| (event, receiver))
+        })
+        .boxed())
+    }
+}
+
+// Events are only hints, so a failed publish is logged instead of failing the request that
+// caused it
+pub async fn publish_event<E: EventBus>(events: &E, topic: EventTopic, event: RepositoryEvent) {
+    if let Err(e) = events.publish(&topic, event).await {
+        tracing::warn!(topic = ?topic, error = %e, "Could not publish event");
+    }
+}
+
+// A member client's subscription to a repository's events. With `after` set, the events after
+// that sequence number are replayed first, otherwise only new events are delivered.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    pub repo: Uuid,
+    pub client: MLSClientId,
+    pub after: Option<i64>,
+}
+
+// The missed events of a subscription, then the new ones. `live` is None when more events were
+// missed than one page holds, the subscriber catches up by subscribing again after the last
+// replayed event.
+pub struct RepositorySubscription {
+    pub replay: Vec<RepositoryEvent>,
+    pub live: Option<BoxStream<'static, RepositoryEvent>>,
+}
+
+// Replays at most one page of the repository's events after the subscription's sequence number,
+// then follows new ones
+pub async fn subscribe_repository<C, R, M, E>(
+    client_repository: &C,
+    repo_repository: &R,
+    message_repository: &M,
+    events: &E,
+    uid: Uuid,
+    subscription: &Subscription,
+) -> Result<RepositorySubscription, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    M: BroadcastMessageRepositoryTrait,
+    E: EventBus,
+{
+    ensure_member(
+        client_repository,
+        repo_repository,
+        uid,
+        subscription.repo,
+        &subscription.client,
+    )
+    .await?;
+    // subscribe before reading so nothing sent in between is missed
+    let live = events.subscribe(&EventTopic::Repository(subscription.repo)).await?;
+    let Some(after) = subscription.after else {
+        let current = repo_repository.find_message_sequence(subscription.repo).await?;
+        let live = live.filter(move |event| ready(event.sequence().is_none_or(|sequence| sequence > current)));
+        return Ok(RepositorySubscription { replay: Vec::new(), live: Some(live.boxed()) });
+    };
+    let replay = message_repository
+        .find_events_after(subscription.repo, &subscription.client, after, MAX_FETCH_LIMIT)
+        .await?;
+    if replay.len() >= MAX_FETCH_LIMIT as usize {
+        return Ok(RepositorySubscription { replay, live: None });
+    }
+    let after = replay.last().and_then(RepositoryEvent::sequence).unwrap_or(after);
+    let live = live.filter(move |event| ready(event.sequence().is_none_or(|sequence| sequence > after)));
+    Ok(RepositorySubscription { replay, live: Some(live.boxed()) })
+}
+
+// Events for messages sent to the client, starting with those still pending
+pub async fn subscribe_client<C, U, E>(
+    client_repository: &C,
+    message_repository: &U,
+    events: &E,
+    uid: Uuid,
+    client: &MLSClientId,
+) -> Result<BoxStream<'static, RepositoryEvent>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    U: UnicastMessageRepositoryTrait,
+    E: EventBus,
+{
+    get_client(client_repository, uid, client).await?;
+    let live = events.subscribe(&EventTopic::Client(client.clone())).await?;
+    let pending: Vec<RepositoryEvent> = message_repository
+        .find_pending(client, DEFAULT_FETCH_LIMIT)
+        .await?
+        .iter()
+        .map(RepositoryEvent::from)
+        .collect();
+    Ok(stream::iter(pending).chain(live).boxed())
+}
+
+// Long-polling fetches on this server instance, woken when a message for their topic is stored
+#[derive(Clone, Debug, Default)]
+pub struct MessageWaiters {
+    topics: Arc<Mutex<HashMap<EventTopic, Arc<Notify>>>>,
+}
+
+impl MessageWaiters {
+    pub fn wake(&self, topic: &EventTopic) {
+        if let Ok(topics) = self.topics.lock()
+            && let Some(notify) = topics.get(topic)
+        {
+            notify.notify_waiters();
+        }
+    }
+
+    // Registers before the caller checks for messages, so one stored in between still wakes it
+    pub fn register(&self, topic: &EventTopic) -> Result<MessageWaiter, ServiceError> {
+        let mut topics = self.topics.lock().map_err(|e| ServiceError::Unknown(e.to_string()))?;
+        let notify = topics.entry(topic.clone()).or_default().clone();
+        let mut notified = Box::pin(notify.notified_owned());
+        notified.as_mut().enable();
+        Ok(MessageWaiter {
+            waiters: self.clone(),
+            topic: topic.clone(),
+            notified,
+        })
+    }
+}
+
+pub struct MessageWaiter {
+    waiters: MessageWaiters,
+    topic: EventTopic,
+    notified: Pin<Box<OwnedNotified>>,
+}
+
+impl MessageWaiter {
+    // Returns once woken or at `deadline`, whichever is first
+    pub async fn wait_until(mut self, deadline: Instant) {
+        let _ = tokio::time::timeout_at(deadline, self.notified.as_mut()).await;
+    }
+}
+
+impl Drop for MessageWaiter {
+    fn drop(&mut self) {
+        if let Ok(mut topics) = self.waiters.topics.lock() {
+            // held only by the map and this waiter
+            if topics.get(&self.topic).is_some_and(|notify| Arc::strong_count(notify) <= 2) {
+                topics.remove(&self.topic);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventBus, LocalEventBus, MessageWaiters, EVENT_BUFFER};
+    use crate::models::repository::{EventTopic, MessageType, RepositoryEvent};
+    use crate::models::user::MLSClientId;
+    use futures::StreamExt;
+    use std::time::Duration;
+    use tokio::time::Instant;
+    use uuid::Uuid;
+
+    fn broadcast_event(repo: Uuid, sequence: i64) -> RepositoryEvent {
+        RepositoryEvent::Broadcast {
+            repo,
+            id: Uuid::new_v4(),
+            sequence,
+            epoch: 0,
+            sender: MLSClientId(Uuid::new_v4()),
+            message_type: MessageType::Application,
+        }
+    }
+
+    #[tokio::test]
+    async fn local_event_bus_tests() {
+        let bus = LocalEventBus::default();
+        let repo = Uuid::new_v4();
+        let topic = EventTopic::Repository(repo);
+
+        // nobody is listening yet
+        bus.publish(&topic, broadcast_event(repo, 1)).await.unwrap();
+
+        let mut first = bus.subscribe(&topic).await.unwrap();
+        let mut second = bus.subscribe(&topic).await.unwrap();
+        let mut other = bus.subscribe(&EventTopic::Repository(Uuid::new_v4())).await.unwrap();
+        bus.publish(&topic, broadcast_event(repo, 2)).await.unwrap();
+        assert_eq!(first.next().await.unwrap().sequence(), Some(2));
+        assert_eq!(second.next().await.unwrap().sequence(), Some(2));
+        drop(bus);
+        assert!(other.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn local_event_bus_drops_lagging_subscribers() {
+        let bus = LocalEventBus::default();
+        let repo = Uuid::new_v4();
+        let topic = EventTopic::Repository(repo);
+
+        let mut lagging = bus.subscribe(&topic).await.unwrap();
+        for sequence in 0..=EVENT_BUFFER as i64 {
+            bus.publish(&topic, broadcast_event(repo, sequence)).await.unwrap();
+        }
+        assert!(lagging.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn message_waiters_tests() {
+        let waiters = MessageWaiters::default();
+        let topic = EventTopic::Client(MLSClientId(Uuid::new_v4()));
+
+        // a wake between registering and waiting is not lost
+        let waiter = waiters.register(&topic).unwrap();
+        waiters.wake(&topic);
+        let started = Instant::now();
+        waiter.wait_until(started + Duration::from_secs(10)).await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert!(waiters.topics.lock().unwrap().is_empty());
+
+        // other topics do not wake it
+        let waiter = waiters.register(&topic).unwrap();
+        waiters.wake(&EventTopic::Repository(Uuid::new_v4()));
+        let started = Instant::now();
+        waiter.wait_until(started + Duration::from_millis(50)).await;
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,246 @@
+use crate::logic::error::ServiceError;
+use crate::logic::mls_client::get_client;
+use crate::models::user::{KeyPackage, KeyPackageInventory, MLSClientId};
+use crate::repository::error::RepoError;
+use crate::repository::key_package::KeyPackageRepositoryTrait;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use chrono::{NaiveDateTime, TimeDelta};
+use uuid::Uuid;
+
+// Most packages accepted in one upload, and most clients claimed for at once
+pub const MAX_KEY_PACKAGE_BATCH: usize = 100;
+pub const MAX_KEY_PACKAGE_SIZE: usize = 64 * 1024;
+// Most one-time packages of one client a user's clients may claim per window, see
+// `claim_key_packages`
+pub const MAX_CLAIMS_PER_CLIENT: i64 = 10;
+pub const CLAIM_WINDOW: TimeDelta = TimeDelta::days(1);
+
+// Checks an upload for `client`: a bounded batch of unexpired packages, at most one last resort
+pub fn validate_key_packages(
+    client: &MLSClientId,
+    packages: &[KeyPackage],
+    now: NaiveDateTime,
+) -> Result<(), ServiceError> {
+    if packages.is_empty() || packages.len() > MAX_KEY_PACKAGE_BATCH {
+        return Err(ServiceError::InvalidInput(format!(
+            "upload 1 to {} key packages at a time",
+            MAX_KEY_PACKAGE_BATCH
+        )));
+    }
+    if packages.iter().filter(|package| package.last_resort).count() > 1 {
+        return Err(ServiceError::InvalidInput(
+            "a client has a single last resort key package".to_string(),
+        ));
+    }
+    for package in packages {
+        if package.mls_client_id.0 != client.0 {
+            return Err(ServiceError::InvalidInput(
+                "key packages must belong to the uploading client".to_string(),
+            ));
+        }
+        if package.kpkg.is_empty() || package.kpkg.len() > MAX_KEY_PACKAGE_SIZE {
+            return Err(ServiceError::InvalidInput(format!(
+                "key packages are 1 to {} bytes",
+                MAX_KEY_PACKAGE_SIZE
+            )));
+        }
+        if package.is_expired(now) {
+            return Err(ServiceError::InvalidInput("key package has already expired".to_string()));
+        }
+    }
+    Ok(())
+}
+
+fn validate_claim_targets(targets: &[MLSClientId]) -> Result<(), ServiceError> {
+    if targets.is_empty() || targets.len() > MAX_KEY_PACKAGE_BATCH {
+        return Err(ServiceError::InvalidInput(format!(
+            "claim key packages for 1 to {} clients at a time",
+            MAX_KEY_PACKAGE_BATCH
+        )));
+    }
+    for (i, target) in targets.iter().enumerate() {
+        if targets[..i].iter().any(|other| other.0 == target.0) {
+            return Err(ServiceError::InvalidInput(format!(
+                "client {} is listed more than once",
+                target.0
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Adds key packages for one of the user's clients. Returns how many were stored.
+pub async fn upload_key_packages<C, K>(
+    client_repository: &C,
+    key_package_repository: &K,
+    uid: Uuid,
+    client: &MLSClientId,
+    packages: Vec<KeyPackage>,
+) -> Result<usize, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    K: KeyPackageRepositoryTrait + TransactionalRepository,
+{
+    get_client(client_repository, uid, client).await?;
+    validate_key_packages(client, &packages, chrono::Utc::now().naive_utc())?;
+    let mut tx = key_package_repository.begin().await?;
+    key_package_repository.create_batch(&mut tx, &packages).await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(packages.len())
+}
+
+// Hands out one key package for each target so `claimer` can add them to a group. Either every
+// target gets a package or nothing is consumed.
+//
+// Any client may be claimed for, since its owner has to be added to a group before sharing a
+// repository with the claimer. To stop one user from draining someone else's one-time packages,
+// the user's clients together may consume at most MAX_CLAIMS_PER_CLIENT of each target's packages
+// per CLAIM_WINDOW. Claims past that are rate limited, even when only the last resort package
+// would be handed out.
+pub async fn claim_key_packages<C, K>(
+    client_repository: &C,
+    key_package_repository: &K,
+    uid: Uuid,
+    claimer: &MLSClientId,
+    targets: &[MLSClientId],
+) -> Result<Vec<KeyPackage>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    K: KeyPackageRepositoryTrait + TransactionalRepository,
+{
+    get_client(client_repository, uid, claimer).await?;
+    validate_claim_targets(targets)?;
+    let now = chrono::Utc::now().naive_utc();
+    let mut tx = key_package_repository.begin().await?;
+    let mut claimed = Vec::with_capacity(targets.len());
+    for target in targets {
+        let recent = key_package_repository
+            .count_claims_by_user(&mut tx, uid, target, now - CLAIM_WINDOW)
+            .await?;
+        if recent >= MAX_CLAIMS_PER_CLIENT {
+            return Err(ServiceError::RateLimited(format!(
+                "too many key packages claimed for client {}, try again later",
+                target.0
+            )));
+        }
+        let package = key_package_repository
+            .claim(&mut tx, target, claimer, now)
+            .await?
+            .ok_or_else(|| {
+                RepoError::NotFound(format!("no key package available for client {}", target.0))
+            })?;
+        claimed.push(package);
+    }
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(claimed)
+}
+
+pub async fn key_package_inventory<K: KeyPackageRepositoryTrait>(
+    key_package_repository: &K,
+    uid: Uuid,
+) -> Result<Vec<KeyPackageInventory>, ServiceError> {
+    Ok(key_package_repository
+        .inventory_by_user(uid, chrono::Utc::now().naive_utc())
+        .await?)
+}
+
+// Claimed packages are kept until they no longer count against the claim limit
+pub async fn sweep_key_packages<K: KeyPackageRepositoryTrait>(
+    key_package_repository: &K,
+) -> Result<u64, ServiceError> {
+    let now = chrono::Utc::now().naive_utc();
+    Ok(key_package_repository
+        .delete_stale(now, now - CLAIM_WINDOW)
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_claim_targets, validate_key_packages, MAX_KEY_PACKAGE_SIZE};
+    use crate::models::user::{KeyPackage, MLSClientId};
+    use chrono::TimeDelta;
+    use uuid::Uuid;
+
+    fn package(client: Uuid, expires_at: chrono::NaiveDateTime, last_resort: bool) -> KeyPackage {
+        KeyPackage {
+            id: Uuid::new_v4(),
+            mls_client_id: MLSClientId(client),
+            kpkg: vec![1, 2, 3],
+            expires_at,
+            last_resort,
+        }
+    }
+
+    #[test]
+    fn validate_key_packages_tests() {
+        let now = chrono::Utc::now().naive_utc();
+        let later = now + TimeDelta::days(30);
+        let client = Uuid::new_v4();
+        let id = MLSClientId(client);
+
+        assert!(validate_key_packages(&id, &[package(client, later, false), package(client, later, true)], now).is_ok());
+        assert!(validate_key_packages(&id, &[], now).is_err());
+        assert!(validate_key_packages(&id, &[package(client, now, false)], now).is_err());
+        assert!(validate_key_packages(&id, &[package(Uuid::new_v4(), later, false)], now).is_err());
+        assert!(validate_key_packages(&id, &[package(client, later, true), package(client, later, true)], now).is_err());
+
+        let mut oversized = package(client, later, false);
+        oversized.kpkg = vec![0; MAX_KEY_PACKAGE_SIZE + 1];
+        assert!(validate_key_packages(&id, &[oversized], now).is_err());
+        let mut empty = package(client, later, false);
+        empty.kpkg = Vec::new();
+        assert!(validate_key_packages(&id, &[empty], now).is_err());
+    }
+
+    #[test]
+    fn validate_claim_targets_tests() {
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        assert!(validate_claim_targets(&[MLSClientId(a), MLSClientId(b)]).is_ok());
+        assert!(validate_claim_targets(&[]).is_err());
+        assert!(validate_claim_targets(&[MLSClientId(a), MLSClientId(a)]).is_err());
+    }
+
+    // Needs a database, which `sqlx::test` creates from DATABASE_URL and migrates
+    #[cfg(feature = "integration-test")]
+    #[sqlx::test]
+    async fn claim_limit_tests(pool: sqlx::PgPool) {
+        use super::{claim_key_packages, MAX_CLAIMS_PER_CLIENT};
+        use crate::logic::error::ServiceError;
+        use crate::repository::key_package::{KeyPackageRepository, KeyPackageRepositoryTrait};
+        use crate::repository::mls_client::{MLSClientRepository, MLSClientRepositoryTrait};
+        use crate::repository::transaction::TransactionalRepository;
+        use crate::repository::user::{UserRepository, UserRepositoryTrait};
+
+        let users = UserRepository::new(pool.clone());
+        let clients = MLSClientRepository::new(pool.clone());
+        let key_packages = KeyPackageRepository::new(pool);
+        let owner = users.create("claimed@test.account", None).await.unwrap();
+        let claimer = users.create("claimer@test.account", None).await.unwrap();
+        let other = users.create("other@test.account", None).await.unwrap();
+        let target = clients.create(owner, None).await.unwrap().id;
+        let claimer_clients = [clients.create(claimer, None).await.unwrap().id, clients.create(claimer, None).await.unwrap().id];
+        let other_client = clients.create(other, None).await.unwrap().id;
+
+        let later = chrono::Utc::now().naive_utc() + TimeDelta::days(30);
+        let packages: Vec<KeyPackage> =
+            (0..2 * MAX_CLAIMS_PER_CLIENT).map(|_| package(target.0, later, false)).collect();
+        let mut tx = key_packages.begin().await.unwrap();
+        key_packages.create_batch(&mut tx, &packages).await.unwrap();
+        tx.commit().await.unwrap();
+
+        // the limit is shared by all of the claimer's clients
+        for i in 0..MAX_CLAIMS_PER_CLIENT {
+            let claimer_client = &claimer_clients[i as usize % 2];
+            let claimed = claim_key_packages(&clients, &key_packages, claimer, claimer_client, std::slice::from_ref(&target)).await;
+            assert!(claimed.is_ok());
+        }
+        let limited = claim_key_packages(&clients, &key_packages, claimer, &claimer_clients[1], std::slice::from_ref(&target)).await;
+        assert!(matches!(limited, Err(ServiceError::RateLimited(_))));
+
+        // other users still get the packages that are left
+        let claimed = claim_key_packages(&clients, &key_packages, other, &other_client, std::slice::from_ref(&target)).await;
+        assert!(!claimed.unwrap()[0].last_resort);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::logic::error::ServiceError;
+use crate::logic::events::{publish_event, EventBus, MessageWaiters};
+use crate::logic::mls_client::get_client;
+use crate::models::repository::{BroadcastMessage, EventTopic, MessageType, RepositoryEvent, UnicastMessage};
+use crate::models::user::MLSClientId;
+use crate::repository::broadcast_message::BroadcastMessageRepositoryTrait;
+use crate::repository::error::RepoError;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::repository::RepoRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use crate::repository::unicast_message::UnicastMessageRepositoryTrait;
+use std::time::Duration;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+// Messages returned by one fetch, unless the client asks for fewer
+pub const DEFAULT_FETCH_LIMIT: u32 = 100;
+pub const MAX_FETCH_LIMIT: u32 = 500;
+pub const MAX_ACK_BATCH: usize = 500;
+// Longest a fetch may wait for a message to arrive
+pub const MAX_WAIT: Duration = Duration::from_secs(30);
+
+// How many messages a fetch returns at most, and how many seconds it waits for one if there are
+// none yet
+#[derive(Debug, Clone, Default)]
+pub struct FetchOptions {
+    pub limit: Option<u32>,
+    pub wait: Option<u64>,
+}
+
+pub fn validate_payload(payload: &[u8]) -> Result<(), ServiceError> {
+    if payload.is_empty() || payload.len() > MAX_MESSAGE_SIZE {
+        return Err(ServiceError::InvalidInput(format!(
+            "messages are 1 to {} bytes",
+            MAX_MESSAGE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+pub fn fetch_limit(requested: Option<u32>) -> u32 {
+    requested.unwrap_or(DEFAULT_FETCH_LIMIT).clamp(1, MAX_FETCH_LIMIT)
+}
+
+// A member client reading a repository's broadcast messages
+#[derive(Debug, Clone)]
+pub struct BroadcastReader {
+    pub repo: Uuid,
+    pub client: MLSClientId,
+}
+
+pub fn wait_timeout(requested: Option<u64>) -> Duration {
+    requested.map(Duration::from_secs).unwrap_or_default().min(MAX_WAIT)
+}
+
+fn validate_ack(ids: &[Uuid]) -> Result<(), ServiceError> {
+    if ids.is_empty() || ids.len() > MAX_ACK_BATCH {
+        return Err(ServiceError::InvalidInput(format!(
+            "acknowledge 1 to {} messages at a time",
+            MAX_ACK_BATCH
+        )));
+    }
+    Ok(())
+}
+
+// Delivers a message from one of the user's clients to any other client, e.g. a Welcome
+pub async fn send_unicast<C, M, E>(
+    client_repository: &C,
+    message_repository: &M,
+    events: &E,
+    uid: Uuid,
+    message: UnicastMessage,
+) -> Result<UnicastMessage, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    M: UnicastMessageRepositoryTrait,
+    E: EventBus,
+{
+    validate_payload(&message.payload)?;
+    get_client(client_repository, uid, &message.sender).await?;
+    client_repository
+        .find(&message.recipient)
+        .await?
+        .ok_or_else(|| ServiceError::from(RepoError::NotFound("Recipient not found".to_string())))?;
+    message_repository.create(&message).await?;
+    publish_event(
+        events,
+        EventTopic::Client(message.recipient.clone()),
+        RepositoryEvent::from(&message),
+    )
+    .await;
+    Ok(message)
+}
+
+// Messages one of the user's clients has not acknowledged yet, oldest first. Waits for one if
+// there are none and the options allow it.
+pub async fn fetch_unicast<C, M>(
+    client_repository: &C,
+    message_repository: &M,
+    waiters: &MessageWaiters,
+    uid: Uuid,
+    client: &MLSClientId,
+    options: &FetchOptions,
+) -> Result<Vec<UnicastMessage>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    M: UnicastMessageRepositoryTrait,
+{
+    get_client(client_repository, uid, client).await?;
+    let deadline = Instant::now() + wait_timeout(options.wait);
+    let topic = EventTopic::Client(client.clone());
+    loop {
+        let waiter = waiters.register(&topic)?;
+        let messages = message_repository.find_pending(client, fetch_limit(options.limit)).await?;
+        if !messages.is_empty() || Instant::now() >= deadline {
+            return Ok(messages);
+        }
+        waiter.wait_until(deadline).await;
+    }
+}
+
+// Marks messages as read so they are no longer fetched. Returns how many were newly acknowledged.
+pub async fn acknowledge_unicast<C, M>(
+    client_repository: &C,
+    message_repository: &M,
+    uid: Uuid,
+    client: &MLSClientId,
+    ids: &[Uuid],
+) -> Result<u64, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    M: UnicastMessageRepositoryTrait,
+{
+    validate_ack(ids)?;
+    get_client(client_repository, uid, client).await?;
+    Ok(message_repository
+        .acknowledge(client, ids, chrono::Utc::now().naive_utc())
+        .await?)
+}
+
+// Welcomes go to the joining client alone, everything else can be broadcast to the group
+fn validate_broadcast_type(message_type: &MessageType) -> Result<(), ServiceError> {
+    if *message_type == MessageType::Welcome {
+        return Err(ServiceError::InvalidInput(
+            "welcome messages are sent to the new member's client directly".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// The client must be one of the user's and hold a grant on the repository
+pub async fn ensure_member<C, R>(
+    client_repository: &C,
+    repo_repository: &R,
+    uid: Uuid,
+    repo: Uuid,
+    client: &MLSClientId,
+) -> Result<(), ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+{
+    get_client(client_repository, uid, client).await?;
+    if !repo_repository.is_member(repo, client).await? {
+        return Err(ServiceError::AuthorizationError(
+            "client is not a member of this repository".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Messages can only be created in an epoch the group has reached
+pub fn validate_epoch(epoch: i64, current: i64) -> Result<(), ServiceError> {
+    if epoch < 0 || epoch > current {
+        return Err(ServiceError::InvalidInput(format!(
+            "epoch {} is ahead of the group's epoch {}",
+            epoch, current
+        )));
+    }
+    Ok(())
+}
+
+// MLS accepts a single commit per epoch. Whichever commit is stored first wins and moves the group
+// to the next epoch, concurrent commits for the same epoch lose and their senders must apply the
+// winner instead.
+#[derive(Debug, Clone)]
+pub enum BroadcastOutcome {
+    Accepted(BroadcastMessage),
+    // the commit already accepted for the rejected message's epoch
+    Conflict(BroadcastMessage),
+}
+
+// Posts a message from a member client to every other member of `message.repo`, giving it the
+// repository's next sequence number
+pub async fn send_broadcast<C, R, M, E>(
+    client_repository: &C,
+    repo_repository: &R,
+    message_repository: &M,
+    events: &E,
+    uid: Uuid,
+    mut message: BroadcastMessage,
+) -> Result<BroadcastOutcome, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    M: BroadcastMessageRepositoryTrait + TransactionalRepository,
+    E: EventBus,
+{
+    validate_broadcast_type(&message.message_type)?;
+    validate_payload(&message.payload)?;
+    ensure_member(client_repository, repo_repository, uid, message.repo, &message.sender).await?;
+
+    let mut tx = message_repository.begin().await?;
+    let (sequence, current_epoch) = repo_repository.next_message_sequence(&mut tx, message.repo, 1).await?;
+    validate_epoch(message.epoch, current_epoch)?;
+    if message.message_type == MessageType::Commit {
+        if message.epoch < current_epoch {
+            // dropping the transaction gives the sequence number back
+            return match message_repository.find_commit(&mut tx, message.repo, message.epoch).await? {
+                Some(winner) => Ok(BroadcastOutcome::Conflict(winner)),
+                None => Err(RepoError::DuplicateEntry(format!(
+                    "epoch {} already has a commit",
+                    message.epoch
+                ))
+                .into()),
+            };
+        }
+        repo_repository.set_epoch(&mut tx, message.repo, current_epoch + 1).await?;
+    }
+    message.sequence = sequence;
+    message_repository.create(&mut tx, &message).await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    publish_event(
+        events,
+        EventTopic::Repository(message.repo),
+        RepositoryEvent::from(&message),
+    )
+    .await;
+    Ok(BroadcastOutcome::Accepted(message))
+}
+
+// Messages other members posted that `reader` has not acknowledged yet, oldest first. Waits for
+// one if there are none and the options allow it.
+pub async fn fetch_broadcast<C, R, M>(
+    client_repository: &C,
+    repo_repository: &R,
+    message_repository: &M,
+    waiters: &MessageWaiters,
+    uid: Uuid,
+    reader: &BroadcastReader,
+    options: &FetchOptions,
+) -> Result<Vec<BroadcastMessage>, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    M: BroadcastMessageRepositoryTrait,
+{
+    ensure_member(client_repository, repo_repository, uid, reader.repo, &reader.client).await?;
+    let deadline = Instant::now() + wait_timeout(options.wait);
+    let topic = EventTopic::Repository(reader.repo);
+    loop {
+        let waiter = waiters.register(&topic)?;
+        let messages = message_repository
+            .find_unread(reader.repo, &reader.client, fetch_limit(options.limit))
+            .await?;
+        // the reader's own messages wake it too, but are never returned
+        if !messages.is_empty() || Instant::now() >= deadline {
+            return Ok(messages);
+        }
+        waiter.wait_until(deadline).await;
+    }
+}
+
+pub async fn acknowledge_broadcast<C, R, M>(
+    client_repository: &C,
+    repo_repository: &R,
+    message_repository: &M,
+    uid: Uuid,
+    repo: Uuid,
+    reader: &MLSClientId,
+    ids: &[Uuid],
+) -> Result<u64, ServiceError>
+where
+    C: MLSClientRepositoryTrait,
+    R: RepoRepositoryTrait,
+    M: BroadcastMessageRepositoryTrait,
+{
+    validate_ack(ids)?;
+    ensure_member(client_repository, repo_repository, uid, repo, reader).await?;
+    Ok(message_repository
+        .acknowledge(repo, reader, ids, chrono::Utc::now().naive_utc())
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fetch_limit, validate_ack, validate_broadcast_type, validate_epoch, validate_payload,
+        wait_timeout, DEFAULT_FETCH_LIMIT, MAX_FETCH_LIMIT, MAX_MESSAGE_SIZE, MAX_WAIT,
+    };
+    use std::time::Duration;
+    use crate::models::repository::MessageType;
+    use uuid::Uuid;
+
+    #[test]
+    fn validate_broadcast_type_tests() {
+        assert!(validate_broadcast_type(&MessageType::Commit).is_ok());
+        assert!(validate_broadcast_type(&MessageType::Proposal).is_ok());
+        assert!(validate_broadcast_type(&MessageType::Application).is_ok());
+        assert!(validate_broadcast_type(&MessageType::Welcome).is_err());
+    }
+
+    #[test]
+    fn validate_epoch_tests() {
+        assert!(validate_epoch(0, 0).is_ok());
+        assert!(validate_epoch(2, 3).is_ok());
+        assert!(validate_epoch(3, 3).is_ok());
+        assert!(validate_epoch(4, 3).is_err());
+        assert!(validate_epoch(-1, 3).is_err());
+    }
+
+    #[test]
+    fn fetch_limit_tests() {
+        assert_eq!(fetch_limit(None), DEFAULT_FETCH_LIMIT);
+        assert_eq!(fetch_limit(Some(10)), 10);
+        assert_eq!(fetch_limit(Some(0)), 1);
+        assert_eq!(fetch_limit(Some(MAX_FETCH_LIMIT + 1)), MAX_FETCH_LIMIT);
+    }
+
+    #[test]
+    fn wait_timeout_tests() {
+        assert_eq!(wait_timeout(None), Duration::ZERO);
+        assert_eq!(wait_timeout(Some(0)), Duration::ZERO);
+        assert_eq!(wait_timeout(Some(5)), Duration::from_secs(5));
+        assert_eq!(wait_timeout(Some(u64::MAX)), MAX_WAIT);
+    }
+
+    #[test]
+    fn validate_payload_tests() {
+        assert!(validate_payload(&[1]).is_ok());
+        assert!(validate_payload(&[]).is_err());
+        assert!(validate_payload(&vec![0; MAX_MESSAGE_SIZE + 1]).is_err());
+    }
+
+    #[test]
+    fn validate_ack_tests() {
+        assert!(validate_ack(&[Uuid::new_v4()]).is_ok());
+        assert!(validate_ack(&[]).is_err());
+    }
+}
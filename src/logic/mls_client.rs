@@ -0,0 +1,123 @@
+use crate::logic::error::ServiceError;
+use crate::models::user::{MLSClient, MLSClientId};
+use crate::repository::error::RepoError;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use uuid::Uuid;
+
+pub const MAX_CLIENT_NAME_LENGTH: usize = 64;
+
+fn validate_client_name(name: &str) -> Result<(), ServiceError> {
+    if name.is_empty() || name.chars().count() > MAX_CLIENT_NAME_LENGTH {
+        return Err(ServiceError::InvalidInput(format!(
+            "device names are 1 to {} characters",
+            MAX_CLIENT_NAME_LENGTH
+        )));
+    }
+    if name.trim() != name || name.chars().any(char::is_control) {
+        return Err(ServiceError::InvalidInput(
+            "device names cannot contain control characters or surrounding spaces".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Users can only see and manage the clients registered to their own account
+fn ensure_client_owner(uid: Uuid, client: &MLSClient) -> Result<(), ServiceError> {
+    if client.assoc_user != Some(uid) {
+        return Err(ServiceError::AuthorizationError(
+            "this client belongs to another user".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+pub async fn get_client<C: MLSClientRepositoryTrait>(
+    client_repository: &C,
+    uid: Uuid,
+    id: &MLSClientId,
+) -> Result<MLSClient, ServiceError> {
+    let client = client_repository
+        .find(id)
+        .await?
+        .ok_or_else(|| ServiceError::from(RepoError::NotFound("Client not found".to_string())))?;
+    ensure_client_owner(uid, &client)?;
+    Ok(client)
+}
+
+pub async fn register_client<C: MLSClientRepositoryTrait>(
+    client_repository: &C,
+    uid: Uuid,
+    name: Option<&str>,
+) -> Result<MLSClient, ServiceError> {
+    if let Some(name) = name {
+        validate_client_name(name)?;
+    }
+    Ok(client_repository.create(uid, name).await?)
+}
+
+pub async fn list_clients<C: MLSClientRepositoryTrait>(
+    client_repository: &C,
+    uid: Uuid,
+) -> Result<Vec<MLSClient>, ServiceError> {
+    Ok(client_repository.list_by_user(uid).await?)
+}
+
+// `None` clears the name
+pub async fn rename_client<C: MLSClientRepositoryTrait>(
+    client_repository: &C,
+    uid: Uuid,
+    id: &MLSClientId,
+    name: Option<&str>,
+) -> Result<MLSClient, ServiceError> {
+    if let Some(name) = name {
+        validate_client_name(name)?;
+    }
+    let client = get_client(client_repository, uid, id).await?;
+    client_repository.set_name(id, name).await?;
+    Ok(MLSClient {
+        name: name.map(str::to_string),
+        ..client
+    })
+}
+
+// The device loses access to every repository it was a member of
+pub async fn revoke_client<C: MLSClientRepositoryTrait>(
+    client_repository: &C,
+    uid: Uuid,
+    id: &MLSClientId,
+) -> Result<(), ServiceError> {
+    get_client(client_repository, uid, id).await?;
+    Ok(client_repository.revoke(id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ensure_client_owner, validate_client_name};
+    use crate::models::user::{MLSClient, MLSClientId};
+    use uuid::Uuid;
+
+    #[test]
+    fn validate_client_name_tests() {
+        assert!(validate_client_name("Work laptop").is_ok());
+        assert!(validate_client_name("Firefox 🦊").is_ok());
+        assert!(validate_client_name("").is_err());
+        assert!(validate_client_name(" laptop").is_err());
+        assert!(validate_client_name("lap\ntop").is_err());
+        assert!(validate_client_name(&"a".repeat(65)).is_err());
+    }
+
+    #[test]
+    fn ensure_client_owner_tests() {
+        let uid = Uuid::new_v4();
+        let mut client = MLSClient {
+            id: MLSClientId(Uuid::new_v4()),
+            assoc_user: Some(uid),
+            name: None,
+            created: chrono::Utc::now().naive_utc(),
+        };
+        assert!(ensure_client_owner(uid, &client).is_ok());
+        assert!(ensure_client_owner(Uuid::new_v4(), &client).is_err());
+        client.assoc_user = None;
+        assert!(ensure_client_owner(uid, &client).is_err());
+    }
+}
@@ -2,3 +2,12 @@ pub mod user;
 pub mod auth;
 pub mod error;
 pub mod payment;
+pub mod notification;
+pub mod promo;
+pub mod repository;
+pub mod mls_client;
+pub mod key_package;
+pub mod message;
+pub mod events;
+pub mod commit;
+pub mod branch;
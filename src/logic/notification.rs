@@ -0,0 +1,108 @@
+use crate::logic::error::ServiceError;
+use crate::models::account::ExpiryNotice;
+use crate::repository::error::RepoError;
+use crate::repository::subscription::SubscriptionRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use chrono::TimeDelta;
+
+// Delivers reminders to users whose plan is about to lapse. Implementations decide the channel,
+// e.g. email or push.
+pub trait ExpiryNotifier {
+    fn notify_expiring(
+        &self,
+        notice: &ExpiryNotice,
+    ) -> impl Future<Output = Result<(), ServiceError>> + Send;
+}
+
+// Only writes reminders to the log. Used until a real delivery channel is set up, and in tests.
+#[derive(Clone, Debug, Default)]
+pub struct LogNotifier;
+
+impl ExpiryNotifier for LogNotifier {
+    async fn notify_expiring(&self, notice: &ExpiryNotice) -> Result<(), ServiceError> {
+        tracing::info!(
+            user_id = %notice.user_id,
+            email = %notice.email,
+            plan = %notice.subscription.subscription_type.to_string(),
+            paid_until = %notice.subscription.paid_until,
+            days_remaining = notice.subscription.days_remaining(),
+            "Subscription expiring soon"
+        );
+        Ok(())
+    }
+}
+
+// Reminds every user whose plan lapses within `notice_period`, once per `paid_until`. A reminder
+// is only recorded as sent if the notifier succeeded, so failed ones are retried on the next run.
+// Returns the number of reminders sent.
+pub async fn notify_expiring<S, N>(
+    subscription_repository: &S,
+    notifier: &N,
+    notice_period: TimeDelta,
+) -> Result<usize, ServiceError>
+where
+    S: SubscriptionRepositoryTrait + TransactionalRepository,
+    N: ExpiryNotifier,
+{
+    let until = chrono::Utc::now().naive_utc() + notice_period;
+    let notices = subscription_repository.find_expiring(until).await?;
+    let mut sent = 0;
+    for notice in notices {
+        let mut tx = subscription_repository.begin().await?;
+        if !subscription_repository
+            .claim_expiry_notice(&mut tx, notice.user_id, notice.subscription.paid_until)
+            .await?
+        {
+            continue;
+        }
+        if let Err(e) = notifier.notify_expiring(&notice).await {
+            tracing::warn!(user_id = %notice.user_id, error = %e, "Could not send expiry notification");
+            continue;
+        }
+        tx.commit().await.map_err(RepoError::from)?;
+        sent += 1;
+    }
+    Ok(sent)
+}
+
+// Needs a database, which `sqlx::test` creates from DATABASE_URL and migrates for each test
+#[cfg(feature = "integration-test")]
+#[cfg(test)]
+mod tests {
+    use super::{notify_expiring, LogNotifier};
+    use crate::models::account::{SubscriptionInfo, SubscriptionType};
+    use crate::repository::subscription::{SubscriptionRepository, SubscriptionRepositoryTrait};
+    use crate::repository::user::{UserRepository, UserRepositoryTrait};
+    use chrono::TimeDelta;
+    use sqlx::PgPool;
+
+    #[sqlx::test]
+    async fn notify_expiring_tests(pool: PgPool) {
+        let users = UserRepository::new(pool.clone());
+        let subscriptions = SubscriptionRepository::new(pool);
+        let now = chrono::Utc::now().naive_utc();
+        let expiring = users.create("expiring@test.account", None).await.unwrap();
+        let later = users.create("later@test.account", None).await.unwrap();
+        for (uid, paid_until) in [(expiring, now + TimeDelta::days(2)), (later, now + TimeDelta::days(20))] {
+            let sub_info = SubscriptionInfo {
+                paid_until,
+                subscription_type: SubscriptionType::CloudSync,
+            };
+            subscriptions.create(uid, sub_info).await.unwrap();
+        }
+        let notice_period = TimeDelta::days(7);
+
+        // only plans lapsing within the notice period, and only once
+        assert_eq!(notify_expiring(&subscriptions, &LogNotifier, notice_period).await.unwrap(), 1);
+        assert_eq!(notify_expiring(&subscriptions, &LogNotifier, notice_period).await.unwrap(), 0);
+
+        // renewing starts a new window, which gets its own reminder
+        let renewed = SubscriptionInfo {
+            paid_until: now + TimeDelta::days(3),
+            subscription_type: SubscriptionType::CloudSync,
+        };
+        subscriptions.update(expiring, renewed).await.unwrap();
+        assert_eq!(notify_expiring(&subscriptions, &LogNotifier, notice_period).await.unwrap(), 1);
+        assert_eq!(notify_expiring(&subscriptions, &LogNotifier, notice_period).await.unwrap(), 0);
+    }
+}
@@ -1,34 +1,75 @@
 use stripe_checkout::CheckoutSessionMode;
 use stripe_checkout::checkout_session::ProductData;
 use stripe_checkout::checkout_session::{
-    CreateCheckoutSession, CreateCheckoutSessionLineItems, CreateCheckoutSessionLineItemsPriceData,
+    CreateCheckoutSession, CreateCheckoutSessionDiscounts, CreateCheckoutSessionLineItems, CreateCheckoutSessionLineItemsPriceData,
+    CreateCheckoutSessionSubscriptionData,
 };
+use stripe_client_core::{RequestBuilder, StripeMethod};
+use stripe_core::customer::CreateCustomer;
+use stripe_core::refund::CreateRefund;
+use stripe_product::price::{
+    CreatePrice, CreatePriceProductData, CreatePriceRecurring, CreatePriceRecurringInterval,
+};
+use stripe::{Client, IdempotencyKey, RequestStrategy, StripeRequest};
 use stripe_types::Currency;
+use chrono::{NaiveDateTime, TimeDelta};
+#[cfg(test)]
+use dotenvy::dotenv;
+use std::collections::HashMap;
 use uuid::Uuid;
+use crate::logic::error::ServiceError;
+use crate::models::account::Payment;
+use crate::models::account::PaymentStatus;
+use crate::models::account::PlanChange;
+use crate::models::account::SubscriptionInfo;
+use crate::models::account::SubscriptionPlan;
+use crate::models::account::SubscriptionType;
+use crate::repository::error::RepoError;
+use crate::logic::promo;
+use crate::repository::payment_log::PaymentLogRepositoryTrait;
+use crate::repository::promo_code::PromoCodeRepositoryTrait;
+use crate::repository::stripe_customer::StripeCustomerRepositoryTrait;
+use crate::repository::stripe_event::StripeEventRepositoryTrait;
+use crate::repository::subscription::SubscriptionRepositoryTrait;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+use crate::repository::user::UserRepositoryTrait;
+
+// Longest prepaid bundle that can be bought in a single checkout
+pub const MAX_CHECKOUT_MONTHS: u32 = 36;
+
+// Keys of the checkout session metadata. The webhook reads back exactly what the checkout wrote.
+const METADATA_USER_ID: &str = "uuid";
+const METADATA_TYPE: &str = "type";
+const METADATA_MONTHS: &str = "months";
+const METADATA_PROMO: &str = "promo";
+
+// Where Stripe sends the user after they finish or abandon the checkout page
+#[derive(Debug, Clone)]
+pub struct CheckoutUrls {
+    pub success_url: String,
+    pub cancel_url: String,
+}
 
 pub async fn start_checkout_session(
-    client: &stripe::Client,
-    user_id: Uuid,
-    months: u32,
-    subscription_type: SubscriptionType,
+    client: &Client,
+    urls: &CheckoutUrls,
+    purchase: &CheckoutPurchase,
+    plan: &SubscriptionPlan,
+    coupon_id: Option<String>,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let price = match subscription_type {
-        SubscriptionType::CloudSync => 200,
-        SubscriptionType::SyncCollaborate => 300,
-    };
     let line_items = vec![CreateCheckoutSessionLineItems {
-        quantity: Some(months.into()),
+        quantity: Some(purchase.months.into()),
         price_data: Some(CreateCheckoutSessionLineItemsPriceData {
             currency: Currency::USD,
             product_data: Some(ProductData {
-                name: product_name(&subscription_type),
+                name: product_name(&purchase.subscription_type),
                 tax_code: None,
                 description: None,
                 images: None,
                 metadata: None,
                 unit_label: None,
             }),
-            unit_amount: Some(price.into()),
+            unit_amount: Some(plan.price_cents_per_month.into()),
             recurring: None,
             product: None,
             tax_behavior: None,
@@ -37,24 +78,17 @@ pub async fn start_checkout_session(
         ..Default::default()
     }];
 
-    let checkout_session = CreateCheckoutSession::new()
+    let mut checkout_session = CreateCheckoutSession::new()
         .mode(CheckoutSessionMode::Payment)
         .line_items(line_items)
-        .metadata([
-            (String::from("uuid"), user_id.to_string()),
-            (
-                "type".into(),
-                match subscription_type {
-                    SubscriptionType::CloudSync => "cloud",
-                    SubscriptionType::SyncCollaborate => "collab",
-                }
-                .to_string(),
-            ),
-            (String::from("quantity"), months.to_string()),
-        ])
-        .success_url("https://deontevanterpool.com")
-        .send(client)
-        .await?;
+        .metadata(purchase.to_metadata())
+        .client_reference_id(purchase.user_id.to_string())
+        .success_url(&urls.success_url)
+        .cancel_url(&urls.cancel_url);
+    if let Some(coupon_id) = coupon_id {
+        checkout_session = checkout_session.discounts(vec![coupon_discount(coupon_id)]);
+    }
+    let checkout_session = checkout_session.send(client).await?;
 
     let url = checkout_session
         .url
@@ -62,6 +96,176 @@ pub async fn start_checkout_session(
     Ok(url)
 }
 
+fn coupon_discount(coupon_id: String) -> CreateCheckoutSessionDiscounts {
+    CreateCheckoutSessionDiscounts {
+        coupon: Some(coupon_id),
+        promotion_code: None,
+    }
+}
+
+// Prices a checkout from the `subscription_plans` table, applies the purchase's promo code if it
+// has one, and returns the Stripe checkout URL
+pub async fn checkout<S, P>(
+    subscription_repository: &S,
+    promo_code_repository: &P,
+    client: &Client,
+    urls: &CheckoutUrls,
+    purchase: CheckoutPurchase,
+) -> Result<String, ServiceError>
+where
+    S: SubscriptionRepositoryTrait,
+    P: PromoCodeRepositoryTrait,
+{
+    if purchase.months == 0 || purchase.months > MAX_CHECKOUT_MONTHS {
+        return Err(ServiceError::InvalidInput(format!(
+            "months must be between 1 and {}",
+            MAX_CHECKOUT_MONTHS
+        )));
+    }
+    let purchase = CheckoutPurchase {
+        promo_code: purchase.promo_code.as_deref().map(promo::normalize_code),
+        ..purchase
+    };
+    let coupon_id = match &purchase.promo_code {
+        Some(code) => Some(
+            promo::checkout_coupon(promo_code_repository, client, purchase.user_id, code, &purchase.subscription_type)
+                .await?,
+        ),
+        None => None,
+    };
+    let plan = subscription_repository
+        .find_plan(purchase.subscription_type.clone())
+        .await?;
+    start_checkout_session(client, urls, &purchase, &plan, coupon_id)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not start checkout session: {}", e)))
+}
+
+// Starts a Stripe checkout for an auto-renewing monthly subscription to a plan. The plan itself
+// is extended as each invoice is paid, see `renew_subscription`. A promo code discounts the
+// first invoice. Users with prepaid time left are turned away, since the first invoice would
+// charge them again for months they already paid for.
+pub async fn subscription_checkout<S, C, P>(
+    subscription_repository: &S,
+    customer_repository: &C,
+    promo_code_repository: &P,
+    client: &Client,
+    urls: &CheckoutUrls,
+    purchase: SubscriptionPurchase,
+) -> Result<String, ServiceError>
+where
+    S: SubscriptionRepositoryTrait,
+    C: StripeCustomerRepositoryTrait,
+    P: PromoCodeRepositoryTrait,
+{
+    let SubscriptionPurchase {
+        user_id,
+        subscription_type,
+        promo_code,
+    } = purchase;
+    let customer = customer_repository.find_by_user_id(user_id).await?;
+    if customer.as_ref().is_some_and(|c| c.has_live_subscription()) {
+        return Err(ServiceError::InvalidInput(
+            "user already has an auto-renewing subscription".to_string(),
+        ));
+    }
+    match subscription_repository.find_by_user_id(user_id).await {
+        Ok(sub_info) if sub_info.is_active() => {
+            return Err(ServiceError::InvalidInput(format!(
+                "user has prepaid time until {}, a subscription can start once it runs out",
+                sub_info.paid_until
+            )));
+        }
+        Ok(_) | Err(RepoError::NotFound(_)) => {}
+        Err(e) => return Err(e.into()),
+    }
+    let customer_id = match customer {
+        Some(customer) => customer.customer_id,
+        None => create_customer(customer_repository, client, user_id).await?,
+    };
+    let plan = subscription_repository
+        .find_plan(subscription_type.clone())
+        .await?;
+    let price_id = match plan.stripe_price_id {
+        Some(ref price_id) => price_id.clone(),
+        None => create_recurring_price(subscription_repository, client, &plan).await?,
+    };
+
+    let promo_code = promo_code.as_deref().map(promo::normalize_code);
+    let coupon_id = match &promo_code {
+        Some(code) => Some(
+            promo::checkout_coupon(promo_code_repository, client, user_id, code, &subscription_type).await?,
+        ),
+        None => None,
+    };
+
+    let mut metadata = plan_metadata(user_id, &subscription_type);
+    if let Some(code) = promo_code {
+        metadata.insert(METADATA_PROMO.to_string(), code);
+    }
+    let mut checkout_session = CreateCheckoutSession::new()
+        .mode(CheckoutSessionMode::Subscription)
+        .customer(customer_id)
+        .line_items(vec![CreateCheckoutSessionLineItems {
+            price: Some(price_id),
+            quantity: Some(1),
+            ..Default::default()
+        }])
+        .metadata(metadata.clone())
+        .subscription_data(CreateCheckoutSessionSubscriptionData {
+            metadata: Some(metadata),
+            ..Default::default()
+        })
+        .client_reference_id(user_id.to_string())
+        .success_url(&urls.success_url)
+        .cancel_url(&urls.cancel_url);
+    if let Some(coupon_id) = coupon_id {
+        checkout_session = checkout_session.discounts(vec![coupon_discount(coupon_id)]);
+    }
+    let checkout_session = checkout_session
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not start checkout session: {}", e)))?;
+
+    checkout_session
+        .url
+        .ok_or_else(|| ServiceError::Unknown("Missing checkout URL".to_string()))
+}
+
+async fn create_customer<C: StripeCustomerRepositoryTrait>(
+    customer_repository: &C,
+    client: &Client,
+    user_id: Uuid,
+) -> Result<String, ServiceError> {
+    let customer = CreateCustomer::new()
+        .metadata([(METADATA_USER_ID.to_string(), user_id.to_string())])
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not create Stripe customer: {}", e)))?;
+    customer_repository
+        .create(user_id, customer.id.as_str())
+        .await?;
+    Ok(customer.id.to_string())
+}
+
+// Each plan has one recurring monthly price in Stripe, created the first time anyone subscribes
+async fn create_recurring_price<S: SubscriptionRepositoryTrait>(
+    subscription_repository: &S,
+    client: &Client,
+    plan: &SubscriptionPlan,
+) -> Result<String, ServiceError> {
+    let price = CreatePrice::new(Currency::USD)
+        .unit_amount(plan.price_cents_per_month)
+        .recurring(CreatePriceRecurring::new(CreatePriceRecurringInterval::Month))
+        .product_data(CreatePriceProductData::new(product_name(&plan.subscription_type)))
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not create Stripe price: {}", e)))?;
+    Ok(subscription_repository
+        .set_stripe_price_id(plan.subscription_type.clone(), price.id.as_str())
+        .await?)
+}
+
 fn product_name(subscription_type: &SubscriptionType) -> String {
     match subscription_type {
         SubscriptionType::CloudSync => "Cloud Sync -- Monthly".to_owned(),
@@ -69,10 +273,671 @@ fn product_name(subscription_type: &SubscriptionType) -> String {
     }
 }
 
-use dotenvy::dotenv;
-use stripe::Client;
+fn checkout_type_key(subscription_type: &SubscriptionType) -> &'static str {
+    match subscription_type {
+        SubscriptionType::CloudSync => "cloud",
+        SubscriptionType::SyncCollaborate => "collab",
+    }
+}
+
+// What a completed checkout session paid for, as recorded in the session metadata
+#[derive(Debug, Clone)]
+pub struct CheckoutPurchase {
+    pub user_id: Uuid,
+    pub subscription_type: SubscriptionType,
+    pub months: u32,
+    pub promo_code: Option<String>,
+}
+
+impl CheckoutPurchase {
+    pub fn to_metadata(&self) -> HashMap<String, String> {
+        let mut meta = plan_metadata(self.user_id, &self.subscription_type);
+        meta.insert(METADATA_MONTHS.to_string(), self.months.to_string());
+        if let Some(code) = &self.promo_code {
+            meta.insert(METADATA_PROMO.to_string(), code.clone());
+        }
+        meta
+    }
+
+    pub fn from_metadata(meta: &HashMap<String, String>) -> Result<Self, ServiceError> {
+        let (user_id, subscription_type) = plan_from_metadata(meta)?;
+        let months: u32 = metadata_field(meta, METADATA_MONTHS)?
+            .parse()
+            .map_err(|_| ServiceError::InvalidInput("invalid months in checkout metadata".to_string()))?;
+        if months == 0 {
+            return Err(ServiceError::InvalidInput("months must be at least 1".to_string()));
+        }
+        Ok(Self {
+            user_id,
+            subscription_type,
+            months,
+            promo_code: promo_from_metadata(meta),
+        })
+    }
+}
+
+// An auto-renewing subscription a user is signing up for, as recorded in the checkout metadata
+#[derive(Debug, Clone)]
+pub struct SubscriptionPurchase {
+    pub user_id: Uuid,
+    pub subscription_type: SubscriptionType,
+    pub promo_code: Option<String>,
+}
+
+impl SubscriptionPurchase {
+    pub fn from_metadata(meta: &HashMap<String, String>) -> Result<Self, ServiceError> {
+        let (user_id, subscription_type) = plan_from_metadata(meta)?;
+        Ok(Self {
+            user_id,
+            subscription_type,
+            promo_code: promo_from_metadata(meta),
+        })
+    }
+}
+
+// The Stripe payment that paid for a checkout
+#[derive(Debug, Clone)]
+pub struct CheckoutPayment {
+    pub payment_id: String,
+    pub amount_cents: u32,
+}
+
+// A completed one-off checkout, with what it bought and how it was paid
+#[derive(Debug, Clone)]
+pub struct CompletedCheckout {
+    pub purchase: CheckoutPurchase,
+    pub payment: CheckoutPayment,
+}
+
+// The Stripe subscription a subscription-mode checkout created, and the invoice of its first period
+#[derive(Debug, Clone)]
+pub struct CheckoutSubscription {
+    pub subscription_id: String,
+    pub invoice_id: Option<String>,
+}
+
+// Metadata identifying who a checkout or Stripe subscription is for and which plan it grants
+fn plan_metadata(user_id: Uuid, subscription_type: &SubscriptionType) -> HashMap<String, String> {
+    HashMap::from([
+        (METADATA_USER_ID.to_string(), user_id.to_string()),
+        (
+            METADATA_TYPE.to_string(),
+            checkout_type_key(subscription_type).to_string(),
+        ),
+    ])
+}
+
+pub fn plan_from_metadata(meta: &HashMap<String, String>) -> Result<(Uuid, SubscriptionType), ServiceError> {
+    let user_id = Uuid::parse_str(metadata_field(meta, METADATA_USER_ID)?)
+        .map_err(|e| ServiceError::InvalidInput(e.to_string()))?;
+    let subscription_type = match metadata_field(meta, METADATA_TYPE)?.as_str() {
+        key if key == checkout_type_key(&SubscriptionType::CloudSync) => SubscriptionType::CloudSync,
+        key if key == checkout_type_key(&SubscriptionType::SyncCollaborate) => {
+            SubscriptionType::SyncCollaborate
+        }
+        other => {
+            return Err(ServiceError::InvalidInput(format!("unknown subscription type '{}'", other)));
+        }
+    };
+    Ok((user_id, subscription_type))
+}
+
+// The promo code a checkout was discounted with, if any
+fn promo_from_metadata(meta: &HashMap<String, String>) -> Option<String> {
+    meta.get(METADATA_PROMO).cloned()
+}
+
+fn metadata_field<'a>(meta: &'a HashMap<String, String>, key: &str) -> Result<&'a String, ServiceError> {
+    meta.get(key)
+        .ok_or_else(|| ServiceError::InvalidInput(format!("missing checkout metadata '{}'", key)))
+}
+
+// Whether a webhook event changed anything, or had already been applied by an earlier delivery
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventOutcome<T> {
+    Applied(T),
+    Duplicate,
+    // recorded as processed, but about something this server does not track
+    Ignored(String),
+}
+
+impl<T> EventOutcome<T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> EventOutcome<U> {
+        match self {
+            EventOutcome::Applied(value) => EventOutcome::Applied(f(value)),
+            EventOutcome::Duplicate => EventOutcome::Duplicate,
+            EventOutcome::Ignored(reason) => EventOutcome::Ignored(reason),
+        }
+    }
+}
+
+// Identifies a webhook delivery so that retries of the same event can be recognised
+#[derive(Debug, Clone)]
+pub struct WebhookEvent {
+    pub id: String,
+    pub event_type: String,
+}
+
+// Opens the transaction an event is applied in, recording the event id as part of it. Returns
+// `None` if the event was already applied by an earlier delivery. Since the id is only persisted
+// when the transaction commits, a delivery that failed part way through is retried in full.
+async fn begin_event<E>(event_repository: &E, event: &WebhookEvent) -> Result<Option<Transaction>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+{
+    let mut tx = event_repository.begin().await?;
+    if !event_repository.record(&mut tx, &event.id, &event.event_type).await? {
+        tx.rollback().await.map_err(RepoError::from)?;
+        return Ok(None);
+    }
+    Ok(Some(tx))
+}
+
+// Extends the purchaser's plan and records the payment, and the promo code redemption if the
+// checkout was discounted. All of it happens in one transaction, together with the event ledger
+// entry, so a payment is never logged without the plan being extended, and a redelivered event
+// never extends the plan twice. If the promo code can no longer be redeemed by the time the
+// checkout completes, the payment is refunded instead.
+pub async fn complete_checkout<E, S, P, R>(
+    event_repository: &E,
+    subscription_repository: &S,
+    payment_log_repository: &P,
+    promo_code_repository: &R,
+    client: &Client,
+    event: &WebhookEvent,
+    checkout: CompletedCheckout,
+) -> Result<EventOutcome<SubscriptionInfo>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+    P: PaymentLogRepositoryTrait,
+    R: PromoCodeRepositoryTrait,
+{
+    let CompletedCheckout { purchase, payment } = checkout;
+    let Some(mut tx) = begin_event(event_repository, event).await? else {
+        return Ok(EventOutcome::Duplicate);
+    };
+    if let Some(code) = &purchase.promo_code
+        && !promo_code_repository.redeem(&mut tx, code, purchase.user_id).await?
+    {
+        tracing::warn!(
+            event_id = %event.id,
+            user_id = %purchase.user_id,
+            payment_id = %payment.payment_id,
+            code = %code,
+            "Refunding checkout with a promo code that was already redeemed or used up"
+        );
+        if payment.amount_cents > 0 {
+            refund_payment(client, event, &payment.payment_id).await?;
+        }
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("promo code {} could not be redeemed", code)));
+    }
+    convert_remaining_plan(subscription_repository, &mut tx, purchase.user_id, &purchase.subscription_type).await?;
+    let sub_info = subscription_repository
+        .extend(&mut tx, purchase.user_id, purchase.subscription_type, purchase.months)
+        .await?;
+    payment_log_repository
+        .create(
+            &mut tx,
+            Payment {
+                payment_id: payment.payment_id,
+                user_id: purchase.user_id,
+                amount_cents: payment.amount_cents,
+                payment_date: chrono::Utc::now().naive_utc(),
+                months: purchase.months,
+                status: PaymentStatus::Paid,
+                refunded_cents: 0,
+            },
+        )
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(EventOutcome::Applied(sub_info))
+}
+
+// Refunds a payment in full. The refund is keyed by the webhook event, so a delivery that is
+// retried after its transaction failed does not refund twice.
+async fn refund_payment(client: &Client, event: &WebhookEvent, payment_id: &str) -> Result<(), ServiceError> {
+    let key = IdempotencyKey::new(format!("refund-{}", event.id))
+        .map_err(|e| ServiceError::Unknown(e.to_string()))?;
+    CreateRefund::new()
+        .payment_intent(payment_id)
+        .customize()
+        .request_strategy(RequestStrategy::Idempotent(key))
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not refund payment {}: {}", payment_id, e)))?;
+    Ok(())
+}
+
+// A refund or dispute that Stripe reported against one of our logged payments
+#[derive(Debug, Clone)]
+pub enum ChargeReversal {
+    // `refunded_cents` is the total refunded on the charge so far, not just the latest refund
+    Refund { payment_id: String, refunded_cents: u32 },
+    Dispute { payment_id: String, reason: String },
+}
+
+impl ChargeReversal {
+    pub fn payment_id(&self) -> &str {
+        match self {
+            ChargeReversal::Refund { payment_id, .. } => payment_id,
+            ChargeReversal::Dispute { payment_id, .. } => payment_id,
+        }
+    }
+}
+
+// Cents of a payment whose credit has already been taken back. A dispute takes back all of it.
+fn reversed_cents(payment: &Payment) -> u32 {
+    match payment.status {
+        PaymentStatus::Disputed => payment.amount_cents,
+        _ => payment.refunded_cents.min(payment.amount_cents),
+    }
+}
+
+// The payment as it stands after the reversal
+fn apply_reversal(payment: &Payment, reversal: &ChargeReversal) -> Payment {
+    let mut updated = payment.clone();
+    match reversal {
+        ChargeReversal::Refund { refunded_cents, .. } => {
+            updated.refunded_cents = payment.refunded_cents.max(*refunded_cents);
+            if payment.status != PaymentStatus::Disputed {
+                updated.status = if updated.refunded_cents >= payment.amount_cents {
+                    PaymentStatus::Refunded
+                } else if updated.refunded_cents > 0 {
+                    PaymentStatus::PartiallyRefunded
+                } else {
+                    PaymentStatus::Paid
+                };
+            }
+        }
+        ChargeReversal::Dispute { .. } => updated.status = PaymentStatus::Disputed,
+    }
+    updated
+}
+
+// Records a refund or dispute on the original payment and takes the matching share of the months
+// it bought back off the user's plan. A dispute also flags the account for support. Refund events
+// carry the running total, so only what was not taken back before is removed again.
+pub async fn reverse_payment<E, S, P, U>(
+    event_repository: &E,
+    subscription_repository: &S,
+    payment_log_repository: &P,
+    user_repository: &U,
+    event: &WebhookEvent,
+    reversal: ChargeReversal,
+) -> Result<EventOutcome<Payment>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+    P: PaymentLogRepositoryTrait,
+    U: UserRepositoryTrait,
+{
+    let Some(mut tx) = begin_event(event_repository, event).await? else {
+        return Ok(EventOutcome::Duplicate);
+    };
+    let Some(payment) = payment_log_repository
+        .find_for_update(&mut tx, reversal.payment_id())
+        .await?
+    else {
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("unknown payment {}", reversal.payment_id())));
+    };
+    let updated = apply_reversal(&payment, &reversal);
+    let newly_reversed = reversed_cents(&updated).saturating_sub(reversed_cents(&payment));
+    if newly_reversed > 0 && payment.months > 0 {
+        let fraction = newly_reversed as f64 / payment.amount_cents as f64;
+        subscription_repository
+            .shorten(&mut tx, payment.user_id, payment.months, fraction)
+            .await?;
+    }
+    payment_log_repository
+        .set_status(&mut tx, &payment.payment_id, updated.status.clone(), updated.refunded_cents)
+        .await?;
+    if let ChargeReversal::Dispute { reason, .. } = &reversal {
+        user_repository
+            .flag(&mut tx, payment.user_id, &format!("payment {} disputed: {}", payment.payment_id, reason))
+            .await?;
+    }
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(EventOutcome::Applied(updated))
+}
+
+// Cancels a Stripe subscription straight away and refunds its first invoice, if one was paid.
+// Refunding the payment also reverses the plan extension if the invoice was already applied.
+async fn cancel_subscription(
+    client: &Client,
+    event: &WebhookEvent,
+    subscription: &CheckoutSubscription,
+) -> Result<(), ServiceError> {
+    let key = IdempotencyKey::new(format!("cancel-{}", event.id))
+        .map_err(|e| ServiceError::Unknown(e.to_string()))?;
+    RequestBuilder::new(StripeMethod::Delete, format!("/subscriptions/{}", subscription.subscription_id))
+        .customize::<stripe_shared::Subscription>()
+        .request_strategy(RequestStrategy::Idempotent(key))
+        .send(client)
+        .await
+        .map_err(|e| {
+            ServiceError::Unknown(format!("could not cancel subscription {}: {}", subscription.subscription_id, e))
+        })?;
+    let Some(invoice_id) = &subscription.invoice_id else {
+        return Ok(());
+    };
+    let invoice = RequestBuilder::new(StripeMethod::Get, format!("/invoices/{}", invoice_id))
+        .query(&HashMap::from([("expand[]", "payments")]))
+        .customize::<stripe_shared::Invoice>()
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not read invoice {}: {}", invoice_id, e)))?;
+    let payment_id = invoice
+        .payments
+        .iter()
+        .flat_map(|payments| payments.data.iter())
+        .find_map(|payment| payment.payment.payment_intent.as_ref())
+        .map(|pi| pi.id().to_string());
+    match payment_id {
+        Some(payment_id) if invoice.amount_paid > 0 => refund_payment(client, event, &payment_id).await,
+        _ => Ok(()),
+    }
+}
+
+// Subscription statuses in which the user has paid for the current period
+fn is_paid_status(status: &str) -> bool {
+    matches!(status, "active" | "trialing")
+}
+
+// Records the Stripe subscription created by a completed subscription-mode checkout, and the
+// promo code redemption if the checkout was discounted. If the promo code can no longer be
+// redeemed, the subscription is cancelled and its first payment refunded instead.
+pub async fn link_subscription<E, C, R>(
+    event_repository: &E,
+    customer_repository: &C,
+    promo_code_repository: &R,
+    client: &Client,
+    event: &WebhookEvent,
+    purchase: SubscriptionPurchase,
+    subscription: CheckoutSubscription,
+) -> Result<EventOutcome<()>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+    C: StripeCustomerRepositoryTrait,
+    R: PromoCodeRepositoryTrait,
+{
+    let Some(mut tx) = begin_event(event_repository, event).await? else {
+        return Ok(EventOutcome::Duplicate);
+    };
+    if let Some(code) = &purchase.promo_code
+        && !promo_code_repository.redeem(&mut tx, code, purchase.user_id).await?
+    {
+        tracing::warn!(
+            event_id = %event.id,
+            user_id = %purchase.user_id,
+            subscription_id = %subscription.subscription_id,
+            code = %code,
+            "Cancelling subscription with a promo code that was already redeemed or used up"
+        );
+        cancel_subscription(client, event, &subscription).await?;
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("promo code {} could not be redeemed", code)));
+    }
+    customer_repository
+        .set_subscription(
+            &mut tx,
+            purchase.user_id,
+            &subscription.subscription_id,
+            purchase.subscription_type,
+            "active",
+        )
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(EventOutcome::Applied(()))
+}
+
+// A paid invoice of an auto-renewing subscription
+#[derive(Debug, Clone)]
+pub struct SubscriptionInvoice {
+    pub invoice_id: String,
+    pub payment_id: String, // payment intent that paid the invoice, so refunds can find it
+
+    pub customer_id: String,
+    pub subscription_id: String,
+    pub subscription_type: Option<SubscriptionType>, // from the subscription metadata, if present
+    pub amount_cents: u32,
+    pub period_end: NaiveDateTime,
+}
+
+// Moves the subscriber's `paid_until` forward to the end of the period the invoice paid for, and
+// records the payment
+pub async fn renew_subscription<E, S, P, C>(
+    event_repository: &E,
+    subscription_repository: &S,
+    payment_log_repository: &P,
+    customer_repository: &C,
+    event: &WebhookEvent,
+    invoice: SubscriptionInvoice,
+) -> Result<EventOutcome<SubscriptionInfo>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+    P: PaymentLogRepositoryTrait,
+    C: StripeCustomerRepositoryTrait,
+{
+    let Some(mut tx) = begin_event(event_repository, event).await? else {
+        return Ok(EventOutcome::Duplicate);
+    };
+    let Some(customer) = customer_repository
+        .find_by_customer_id(&mut tx, &invoice.customer_id)
+        .await?
+    else {
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("unknown customer {}", invoice.customer_id)));
+    };
+    let Some(subscription_type) = invoice.subscription_type.or(customer.subscription_type) else {
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("no plan for subscription {}", invoice.subscription_id)));
+    };
+    customer_repository
+        .set_subscription(&mut tx, customer.user_id, &invoice.subscription_id, subscription_type.clone(), "active")
+        .await?;
+    convert_remaining_plan(subscription_repository, &mut tx, customer.user_id, &subscription_type).await?;
+    let sub_info = subscription_repository
+        .extend_until(&mut tx, customer.user_id, subscription_type, invoice.period_end)
+        .await?;
+    payment_log_repository
+        .create(
+            &mut tx,
+            Payment {
+                payment_id: invoice.payment_id,
+                user_id: customer.user_id,
+                amount_cents: invoice.amount_cents,
+                payment_date: chrono::Utc::now().naive_utc(),
+                months: 1,
+                status: PaymentStatus::Paid,
+                refunded_cents: 0,
+            },
+        )
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(EventOutcome::Applied(sub_info))
+}
+
+// The state of an auto-renewing subscription after Stripe changed or deleted it
+#[derive(Debug, Clone)]
+pub struct SubscriptionChange {
+    pub customer_id: String,
+    pub subscription_id: String,
+    pub subscription_type: Option<SubscriptionType>, // from the subscription metadata, if present
+    pub status: String,
+    pub current_period_end: Option<NaiveDateTime>,
+}
+
+// Tracks the subscription's status. While it is paid up, `paid_until` follows the current period;
+// once it is canceled or unpaid, `paid_until` is left alone so the plan lapses when it runs out.
+pub async fn update_subscription<E, S, C>(
+    event_repository: &E,
+    subscription_repository: &S,
+    customer_repository: &C,
+    event: &WebhookEvent,
+    change: SubscriptionChange,
+) -> Result<EventOutcome<Option<SubscriptionInfo>>, ServiceError>
+where
+    E: StripeEventRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+    C: StripeCustomerRepositoryTrait,
+{
+    let Some(mut tx) = begin_event(event_repository, event).await? else {
+        return Ok(EventOutcome::Duplicate);
+    };
+    let Some(customer) = customer_repository
+        .find_by_customer_id(&mut tx, &change.customer_id)
+        .await?
+    else {
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("unknown customer {}", change.customer_id)));
+    };
+    let Some(subscription_type) = change.subscription_type.or(customer.subscription_type) else {
+        tx.commit().await.map_err(RepoError::from)?;
+        return Ok(EventOutcome::Ignored(format!("no plan for subscription {}", change.subscription_id)));
+    };
+    customer_repository
+        .set_subscription(&mut tx, customer.user_id, &change.subscription_id, subscription_type.clone(), &change.status)
+        .await?;
+    let sub_info = match change.current_period_end {
+        Some(period_end) if is_paid_status(&change.status) => {
+            convert_remaining_plan(subscription_repository, &mut tx, customer.user_id, &subscription_type).await?;
+            Some(
+                subscription_repository
+                    .extend_until(&mut tx, customer.user_id, subscription_type, period_end)
+                    .await?,
+            )
+        }
+        _ => None,
+    };
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(EventOutcome::Applied(sub_info))
+}
+
+// Converts the time left before `paid_until` into time on a plan with a different monthly price,
+// so the value already paid for is kept. Moving to a pricier plan shortens the remaining time and
+// moving to a cheaper one lengthens it.
+pub fn prorate_paid_until(
+    now: NaiveDateTime,
+    paid_until: NaiveDateTime,
+    old_price_cents_per_month: u32,
+    new_price_cents_per_month: u32,
+) -> Result<NaiveDateTime, ServiceError> {
+    if new_price_cents_per_month == 0 {
+        return Err(ServiceError::Unknown("target plan has no price".to_string()));
+    }
+    let remaining = (paid_until - now).max(TimeDelta::zero()).num_seconds() as i128;
+    let converted = remaining * old_price_cents_per_month as i128 / new_price_cents_per_month as i128;
+    let converted = TimeDelta::try_seconds(converted.min(i64::MAX as i128) as i64)
+        .ok_or_else(|| ServiceError::InvalidInput("remaining time is too long to convert".to_string()))?;
+    now.checked_add_signed(converted)
+        .ok_or_else(|| ServiceError::InvalidInput("remaining time is too long to convert".to_string()))
+}
+
+// Before time on `subscription_type` is added to the user's plan, converts whatever is left of a
+// different plan into time on it by price, the same way a plan change does. Without this a month
+// of the pricier plan would turn all remaining time on the cheaper one into time on the pricier.
+pub async fn convert_remaining_plan<S: SubscriptionRepositoryTrait>(
+    subscription_repository: &S,
+    tx: &mut Transaction,
+    user_id: Uuid,
+    subscription_type: &SubscriptionType,
+) -> Result<(), ServiceError> {
+    let previous = match subscription_repository.find_by_user_id_for_update(tx, user_id).await {
+        Ok(sub_info) => sub_info,
+        Err(RepoError::NotFound(_)) => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if previous.subscription_type == *subscription_type {
+        return Ok(());
+    }
+    let old_plan = subscription_repository.find_plan(previous.subscription_type.clone()).await?;
+    let new_plan = subscription_repository.find_plan(subscription_type.clone()).await?;
+    let converted = SubscriptionInfo {
+        paid_until: prorate_paid_until(
+            chrono::Utc::now().naive_utc(),
+            previous.paid_until,
+            old_plan.price_cents_per_month,
+            new_plan.price_cents_per_month,
+        )?,
+        subscription_type: subscription_type.clone(),
+    };
+    subscription_repository.set_plan(tx, user_id, &converted).await?;
+    Ok(())
+}
+
+// Switches an active prepaid plan to `subscription_type`, converting the remaining days by price.
+// With `preview` set the result is computed but nothing is stored.
+pub async fn change_plan<S, C>(
+    subscription_repository: &S,
+    customer_repository: &C,
+    user_id: Uuid,
+    subscription_type: SubscriptionType,
+    preview: bool,
+) -> Result<PlanChange, ServiceError>
+where
+    S: SubscriptionRepositoryTrait + TransactionalRepository,
+    C: StripeCustomerRepositoryTrait,
+{
+    let customer = customer_repository.find_by_user_id(user_id).await?;
+    if customer.as_ref().is_some_and(|c| c.has_live_subscription()) {
+        return Err(ServiceError::InvalidInput(
+            "plans of auto-renewing subscriptions are changed through Stripe".to_string(),
+        ));
+    }
+    let mut tx = subscription_repository.begin().await?;
+    let previous = match subscription_repository
+        .find_by_user_id_for_update(&mut tx, user_id)
+        .await
+    {
+        Ok(sub_info) if sub_info.is_active() => sub_info,
+        Ok(_) | Err(RepoError::NotFound(_)) => {
+            return Err(ServiceError::InvalidInput(
+                "there is no active plan to change, start a checkout instead".to_string(),
+            ));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if previous.subscription_type == subscription_type {
+        return Err(ServiceError::InvalidInput(format!(
+            "already on the {} plan",
+            subscription_type.to_string()
+        )));
+    }
+    let old_plan = subscription_repository.find_plan(previous.subscription_type.clone()).await?;
+    let new_plan = subscription_repository.find_plan(subscription_type.clone()).await?;
+    let paid_until = prorate_paid_until(
+        chrono::Utc::now().naive_utc(),
+        previous.paid_until,
+        old_plan.price_cents_per_month,
+        new_plan.price_cents_per_month,
+    )?;
+    let updated = SubscriptionInfo {
+        paid_until,
+        subscription_type,
+    };
+    if !preview {
+        subscription_repository.set_plan(&mut tx, user_id, &updated).await?;
+        tx.commit().await.map_err(RepoError::from)?;
+    }
+    Ok(PlanChange {
+        previous,
+        updated,
+        applied: !preview,
+    })
+}
+
+#[cfg(test)]
+fn test_checkout_urls() -> CheckoutUrls {
+    CheckoutUrls {
+        success_url: "https://deontevanterpool.com".to_string(),
+        cancel_url: "https://deontevanterpool.com".to_string(),
+    }
+}
 
-use crate::models::account::SubscriptionType;
 #[tokio::test]
 pub async fn test_start_checkout_session() {
     if dotenv().is_err() {
@@ -82,9 +947,25 @@ pub async fn test_start_checkout_session() {
     let client = Client::new(secret_key);
     println!(
         "{}",
-        start_checkout_session(&client, Uuid::new_v4(), 12, SubscriptionType::CloudSync)
-            .await
-            .unwrap()
+        start_checkout_session(
+            &client,
+            &test_checkout_urls(),
+            &CheckoutPurchase {
+                user_id: Uuid::new_v4(),
+                subscription_type: SubscriptionType::CloudSync,
+                months: 12,
+                promo_code: None,
+            },
+            &SubscriptionPlan {
+                subscription_type: SubscriptionType::CloudSync,
+                description: None,
+                price_cents_per_month: 200,
+                stripe_price_id: None,
+            },
+            None,
+        )
+        .await
+        .unwrap()
     );
 }
 
@@ -99,11 +980,182 @@ pub async fn test_start_checkout_session_2() {
         "{}",
         start_checkout_session(
             &client,
-            Uuid::new_v4(),
-            6,
-            SubscriptionType::SyncCollaborate
+            &test_checkout_urls(),
+            &CheckoutPurchase {
+                user_id: Uuid::new_v4(),
+                subscription_type: SubscriptionType::SyncCollaborate,
+                months: 6,
+                promo_code: None,
+            },
+            &SubscriptionPlan {
+                subscription_type: SubscriptionType::SyncCollaborate,
+                description: None,
+                price_cents_per_month: 300,
+                stripe_price_id: None,
+            },
+            None,
         )
         .await
         .unwrap()
     );
 }
+
+#[test]
+fn checkout_purchase_from_metadata() {
+    let user_id = Uuid::new_v4();
+    let meta: HashMap<String, String> = [
+        ("uuid".to_string(), user_id.to_string()),
+        ("type".to_string(), "collab".to_string()),
+        ("months".to_string(), "3".to_string()),
+    ]
+    .into_iter()
+    .collect();
+    let purchase = CheckoutPurchase::from_metadata(&meta).unwrap();
+    assert_eq!(purchase.user_id, user_id);
+    assert_eq!(purchase.months, 3);
+    assert!(matches!(purchase.subscription_type, SubscriptionType::SyncCollaborate));
+    assert_eq!(purchase.promo_code, None);
+
+    let mut zero_months = meta.clone();
+    zero_months.insert("months".to_string(), "0".to_string());
+    assert!(CheckoutPurchase::from_metadata(&zero_months).is_err());
+
+    let mut bad_type = meta.clone();
+    bad_type.insert("type".to_string(), "enterprise".to_string());
+    assert!(CheckoutPurchase::from_metadata(&bad_type).is_err());
+
+    let mut missing = meta;
+    missing.remove("uuid");
+    assert!(CheckoutPurchase::from_metadata(&missing).is_err());
+}
+
+#[test]
+fn checkout_purchase_metadata_round_trip() {
+    let purchase = CheckoutPurchase {
+        user_id: Uuid::new_v4(),
+        subscription_type: SubscriptionType::CloudSync,
+        months: 12,
+        promo_code: Some("LAUNCH".to_string()),
+    };
+    let parsed = CheckoutPurchase::from_metadata(&purchase.to_metadata()).unwrap();
+    assert_eq!(parsed.user_id, purchase.user_id);
+    assert_eq!(parsed.months, 12);
+    assert_eq!(parsed.promo_code.as_deref(), Some("LAUNCH"));
+    assert!(matches!(parsed.subscription_type, SubscriptionType::CloudSync));
+}
+
+#[test]
+fn prorate_paid_until_tests() {
+    let now = chrono::Utc::now().naive_utc();
+    // 30 days at 5 EUR are worth 15 days at 10 EUR, and the other way round
+    assert_eq!(prorate_paid_until(now, now + TimeDelta::days(30), 500, 1000).unwrap(), now + TimeDelta::days(15));
+    assert_eq!(prorate_paid_until(now, now + TimeDelta::days(15), 1000, 500).unwrap(), now + TimeDelta::days(30));
+    assert_eq!(prorate_paid_until(now, now + TimeDelta::days(10), 700, 700).unwrap(), now + TimeDelta::days(10));
+    // lapsed plans have nothing left to convert
+    assert_eq!(prorate_paid_until(now, now - TimeDelta::days(3), 500, 1000).unwrap(), now);
+    assert!(prorate_paid_until(now, now + TimeDelta::days(1), 500, 0).is_err());
+}
+
+// Needs a database, which `sqlx::test` creates from DATABASE_URL and migrates
+#[cfg(feature = "integration-test")]
+#[sqlx::test]
+async fn convert_remaining_plan_tests(pool: sqlx::PgPool) {
+    use crate::repository::subscription::SubscriptionRepository;
+    use crate::repository::user::UserRepository;
+
+    let users = UserRepository::new(pool.clone());
+    let subscriptions = SubscriptionRepository::new(pool);
+    let uid = users.create("converted@test.account", None).await.unwrap();
+    let now = chrono::Utc::now().naive_utc();
+    let sub_info = SubscriptionInfo {
+        paid_until: now + TimeDelta::days(330),
+        subscription_type: SubscriptionType::CloudSync,
+    };
+    subscriptions.create(uid, sub_info).await.unwrap();
+
+    // 330 days of Cloud Sync at 2 EUR are worth 220 days of Sync Collaborate at 3 EUR, and the
+    // month bought comes on top
+    let mut tx = subscriptions.begin().await.unwrap();
+    convert_remaining_plan(&subscriptions, &mut tx, uid, &SubscriptionType::SyncCollaborate).await.unwrap();
+    let extended = subscriptions.extend(&mut tx, uid, SubscriptionType::SyncCollaborate, 1).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(matches!(extended.subscription_type, SubscriptionType::SyncCollaborate));
+    let converted = now + TimeDelta::days(220);
+    assert!(extended.paid_until >= converted + TimeDelta::days(28));
+    assert!(extended.paid_until <= converted + TimeDelta::days(32));
+
+    // more of the same plan is simply added
+    let mut tx = subscriptions.begin().await.unwrap();
+    convert_remaining_plan(&subscriptions, &mut tx, uid, &SubscriptionType::SyncCollaborate).await.unwrap();
+    let extended_again = subscriptions.extend(&mut tx, uid, SubscriptionType::SyncCollaborate, 1).await.unwrap();
+    tx.commit().await.unwrap();
+    assert!(extended_again.paid_until >= extended.paid_until + TimeDelta::days(28));
+}
+
+#[cfg(feature = "integration-test")]
+#[sqlx::test]
+async fn subscription_checkout_with_prepaid_time_tests(pool: sqlx::PgPool) {
+    use crate::repository::promo_code::PromoCodeRepository;
+    use crate::repository::stripe_customer::StripeCustomerRepository;
+    use crate::repository::subscription::SubscriptionRepository;
+    use crate::repository::user::UserRepository;
+
+    let users = UserRepository::new(pool.clone());
+    let subscriptions = SubscriptionRepository::new(pool.clone());
+    let customers = StripeCustomerRepository::new(pool.clone());
+    let promo_codes = PromoCodeRepository::new(pool);
+    let uid = users.create("prepaid@test.account", None).await.unwrap();
+    let paid_until = chrono::Utc::now().naive_utc() + TimeDelta::days(40);
+    subscriptions
+        .create(uid, SubscriptionInfo { paid_until, subscription_type: SubscriptionType::CloudSync })
+        .await
+        .unwrap();
+    let before = subscriptions.find_by_user_id(uid).await.unwrap();
+
+    // turned away before anything is sent to Stripe, so the key is never used
+    let client = Client::new("sk_test_unused");
+    let purchase = SubscriptionPurchase {
+        user_id: uid,
+        subscription_type: SubscriptionType::CloudSync,
+        promo_code: None,
+    };
+    let result =
+        subscription_checkout(&subscriptions, &customers, &promo_codes, &client, &test_checkout_urls(), purchase).await;
+    assert!(matches!(result, Err(ServiceError::InvalidInput(_))));
+    assert!(customers.find_by_user_id(uid).await.unwrap().is_none());
+    assert_eq!(subscriptions.find_by_user_id(uid).await.unwrap().paid_until, before.paid_until);
+}
+
+#[test]
+fn apply_reversal_tests() {
+    let payment = Payment {
+        payment_id: "pi_123".to_string(),
+        user_id: Uuid::new_v4(),
+        amount_cents: 600,
+        payment_date: chrono::Utc::now().naive_utc(),
+        months: 3,
+        status: PaymentStatus::Paid,
+        refunded_cents: 0,
+    };
+    let refund = |refunded_cents| ChargeReversal::Refund { payment_id: "pi_123".to_string(), refunded_cents };
+    let dispute = ChargeReversal::Dispute { payment_id: "pi_123".to_string(), reason: "fraudulent".to_string() };
+
+    let partial = apply_reversal(&payment, &refund(200));
+    assert_eq!(partial.status, PaymentStatus::PartiallyRefunded);
+    assert_eq!(reversed_cents(&partial), 200);
+
+    // refund totals are cumulative, so a second refund only reverses the difference
+    let full = apply_reversal(&partial, &refund(600));
+    assert_eq!(full.status, PaymentStatus::Refunded);
+    assert_eq!(reversed_cents(&full) - reversed_cents(&partial), 400);
+
+    // a dispute takes back whatever was not refunded yet
+    let disputed = apply_reversal(&partial, &dispute);
+    assert_eq!(disputed.status, PaymentStatus::Disputed);
+    assert_eq!(reversed_cents(&disputed) - reversed_cents(&partial), 400);
+
+    // refunding a disputed payment takes nothing back twice
+    let refunded_after_dispute = apply_reversal(&disputed, &refund(600));
+    assert_eq!(refunded_after_dispute.status, PaymentStatus::Disputed);
+    assert_eq!(reversed_cents(&refunded_after_dispute), reversed_cents(&disputed));
+}
@@ -0,0 +1,251 @@
+use crate::logic::error::ServiceError;
+use crate::logic::payment::{self, MAX_CHECKOUT_MONTHS};
+use crate::models::account::PromoCode;
+use crate::models::account::PromoReward;
+use crate::models::account::SubscriptionInfo;
+use crate::models::account::SubscriptionType;
+use crate::repository::error::RepoError;
+use crate::repository::promo_code::PromoCodeRepositoryTrait;
+use crate::repository::subscription::SubscriptionRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use stripe_product::coupon::CreateCoupon;
+use stripe_shared::CouponDuration;
+use uuid::Uuid;
+
+// Codes are matched case-insensitively and stored in upper case
+pub fn normalize_code(code: &str) -> String {
+    code.trim().to_uppercase()
+}
+
+fn is_valid_code(code: &str) -> bool {
+    (4..=32).contains(&code.len())
+        && code
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+fn validate_promo_code(promo_code: &PromoCode) -> Result<(), ServiceError> {
+    if !is_valid_code(&promo_code.code) {
+        return Err(ServiceError::InvalidInput(
+            "codes are 4 to 32 letters, digits, '-' or '_'".to_string(),
+        ));
+    }
+    match promo_code.reward {
+        PromoReward::PercentOff(percent) if percent == 0 || percent > 100 => {
+            return Err(ServiceError::InvalidInput(
+                "percent_off must be between 1 and 100".to_string(),
+            ));
+        }
+        PromoReward::FreeMonths(months) if months == 0 || months > MAX_CHECKOUT_MONTHS => {
+            return Err(ServiceError::InvalidInput(format!(
+                "free_months must be between 1 and {}",
+                MAX_CHECKOUT_MONTHS
+            )));
+        }
+        PromoReward::FreeMonths(_) if promo_code.subscription_type.is_none() => {
+            return Err(ServiceError::InvalidInput(
+                "free month codes need a plan".to_string(),
+            ));
+        }
+        _ => {}
+    }
+    if promo_code.max_redemptions == Some(0) {
+        return Err(ServiceError::InvalidInput(
+            "max_redemptions must be at least 1".to_string(),
+        ));
+    }
+    if promo_code.is_expired() {
+        return Err(ServiceError::InvalidInput("expiry must be in the future".to_string()));
+    }
+    Ok(())
+}
+
+// Checks that a code can still be used by anyone
+fn ensure_redeemable(promo_code: &PromoCode) -> Result<(), ServiceError> {
+    if promo_code.is_expired() {
+        return Err(ServiceError::InvalidInput("promo code has expired".to_string()));
+    }
+    if promo_code.is_exhausted() {
+        return Err(ServiceError::InvalidInput("promo code has been used up".to_string()));
+    }
+    Ok(())
+}
+
+fn promo_code_not_found() -> RepoError {
+    RepoError::NotFound("Promo code not found".to_string())
+}
+
+// Creates a new code. Only admins may call this.
+pub async fn mint_promo_code<P: PromoCodeRepositoryTrait>(
+    promo_code_repository: &P,
+    created_by: Uuid,
+    promo_code: PromoCode,
+) -> Result<PromoCode, ServiceError> {
+    let promo_code = PromoCode {
+        code: normalize_code(&promo_code.code),
+        redemptions: 0,
+        stripe_coupon_id: None,
+        ..promo_code
+    };
+    validate_promo_code(&promo_code)?;
+    promo_code_repository.create(&promo_code, created_by).await?;
+    Ok(promo_code)
+}
+
+// Grants the free months of a code to the user. Discount codes are rejected here, they are
+// passed to the checkout instead. Each user can redeem a code once.
+pub async fn redeem_promo_code<P, S>(
+    promo_code_repository: &P,
+    subscription_repository: &S,
+    user_id: Uuid,
+    code: &str,
+) -> Result<SubscriptionInfo, ServiceError>
+where
+    P: PromoCodeRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+{
+    let code = normalize_code(code);
+    let mut tx = promo_code_repository.begin().await?;
+    let promo_code = promo_code_repository
+        .find_for_update(&mut tx, &code)
+        .await?
+        .ok_or_else(promo_code_not_found)?;
+    ensure_redeemable(&promo_code)?;
+    let (PromoReward::FreeMonths(months), Some(subscription_type)) =
+        (&promo_code.reward, promo_code.subscription_type)
+    else {
+        return Err(ServiceError::InvalidInput(
+            "discount codes are applied at checkout".to_string(),
+        ));
+    };
+    if !promo_code_repository.redeem(&mut tx, &code, user_id).await? {
+        return Err(ServiceError::InvalidInput(
+            "promo code was already redeemed or has been used up".to_string(),
+        ));
+    }
+    payment::convert_remaining_plan(subscription_repository, &mut tx, user_id, &subscription_type).await?;
+    let sub_info = subscription_repository
+        .extend(&mut tx, user_id, subscription_type, *months)
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(sub_info)
+}
+
+// Checks that the user may apply a discount code to a checkout of `subscription_type` and returns
+// the Stripe coupon to attach to the session. The redemption itself is recorded once the checkout
+// is paid.
+pub async fn checkout_coupon<P: PromoCodeRepositoryTrait>(
+    promo_code_repository: &P,
+    client: &stripe::Client,
+    user_id: Uuid,
+    code: &str,
+    subscription_type: &SubscriptionType,
+) -> Result<String, ServiceError> {
+    let code = normalize_code(code);
+    let promo_code = promo_code_repository
+        .find(&code)
+        .await?
+        .ok_or_else(promo_code_not_found)?;
+    ensure_redeemable(&promo_code)?;
+    let PromoReward::PercentOff(percent) = promo_code.reward else {
+        return Err(ServiceError::InvalidInput(
+            "free month codes are redeemed without a checkout".to_string(),
+        ));
+    };
+    if !promo_code.applies_to(subscription_type) {
+        return Err(ServiceError::InvalidInput(format!(
+            "promo code does not apply to the {} plan",
+            subscription_type.to_string()
+        )));
+    }
+    if promo_code_repository.has_redeemed(&code, user_id).await? {
+        return Err(ServiceError::InvalidInput("promo code was already redeemed".to_string()));
+    }
+    match promo_code.stripe_coupon_id {
+        Some(coupon_id) => Ok(coupon_id),
+        None => create_coupon(promo_code_repository, client, &promo_code, percent).await,
+    }
+}
+
+// Each discount code is backed by one Stripe coupon, created the first time the code is used. The
+// coupon carries the code's limits too, so Stripe stops applying it once the code is used up.
+async fn create_coupon<P: PromoCodeRepositoryTrait>(
+    promo_code_repository: &P,
+    client: &stripe::Client,
+    promo_code: &PromoCode,
+    percent: u32,
+) -> Result<String, ServiceError> {
+    let mut coupon = CreateCoupon::new()
+        .name(promo_code.code.as_str())
+        .percent_off(percent)
+        .duration(CouponDuration::Once);
+    if let Some(max) = promo_code.max_redemptions {
+        coupon = coupon.max_redemptions(max);
+    }
+    if let Some(expires) = promo_code.expires {
+        coupon = coupon.redeem_by(expires.and_utc().timestamp());
+    }
+    let coupon = coupon
+        .send(client)
+        .await
+        .map_err(|e| ServiceError::Unknown(format!("could not create Stripe coupon: {}", e)))?;
+    Ok(promo_code_repository
+        .set_stripe_coupon_id(&promo_code.code, coupon.id.as_str())
+        .await?)
+}
+
+#[test]
+fn validate_promo_code_tests() {
+    let code = PromoCode {
+        code: "LAUNCH-2026".to_string(),
+        reward: PromoReward::FreeMonths(3),
+        subscription_type: Some(SubscriptionType::CloudSync),
+        max_redemptions: Some(100),
+        redemptions: 0,
+        expires: None,
+        stripe_coupon_id: None,
+    };
+    assert!(validate_promo_code(&code).is_ok());
+    assert!(validate_promo_code(&PromoCode { code: "ab".to_string(), ..code.clone() }).is_err());
+    assert!(validate_promo_code(&PromoCode { code: "HAS SPACE".to_string(), ..code.clone() }).is_err());
+    assert!(validate_promo_code(&PromoCode { subscription_type: None, ..code.clone() }).is_err());
+    assert!(validate_promo_code(&PromoCode { reward: PromoReward::FreeMonths(0), ..code.clone() }).is_err());
+    assert!(validate_promo_code(&PromoCode { max_redemptions: Some(0), ..code.clone() }).is_err());
+    let discount = PromoCode { reward: PromoReward::PercentOff(25), subscription_type: None, ..code };
+    assert!(validate_promo_code(&discount).is_ok());
+    assert!(validate_promo_code(&PromoCode { reward: PromoReward::PercentOff(101), ..discount }).is_err());
+    assert_eq!(normalize_code(" launch-2026 "), "LAUNCH-2026");
+}
+
+#[cfg(feature = "integration-test")]
+#[sqlx::test]
+async fn redeem_limit_tests(pool: sqlx::PgPool) {
+    use crate::repository::promo_code::PromoCodeRepository;
+    use crate::repository::user::{UserRepository, UserRepositoryTrait};
+
+    let users = UserRepository::new(pool.clone());
+    let promo_codes = PromoCodeRepository::new(pool);
+    let admin = users.create("admin@test.account", None).await.unwrap();
+    let first = users.create("first@test.account", None).await.unwrap();
+    let second = users.create("second@test.account", None).await.unwrap();
+    let code = PromoCode {
+        code: "ONLY-ONE".to_string(),
+        reward: PromoReward::PercentOff(50),
+        subscription_type: None,
+        max_redemptions: Some(1),
+        redemptions: 0,
+        expires: None,
+        stripe_coupon_id: None,
+    };
+    promo_codes.create(&code, admin).await.unwrap();
+
+    let mut tx = promo_codes.begin().await.unwrap();
+    assert!(promo_codes.redeem(&mut tx, &code.code, first).await.unwrap());
+    // the same user again, and another user once the limit is reached
+    assert!(!promo_codes.redeem(&mut tx, &code.code, first).await.unwrap());
+    assert!(!promo_codes.redeem(&mut tx, &code.code, second).await.unwrap());
+    tx.commit().await.unwrap();
+    let redeemed = promo_codes.find(&code.code).await.unwrap().unwrap();
+    assert_eq!(redeemed.redemptions, 1);
+    assert!(!promo_codes.has_redeemed(&code.code, second).await.unwrap());
+}
@@ -0,0 +1,357 @@
+use crate::logic::error::ServiceError;
+use crate::logic::mls_client::get_client;
+use crate::logic::user::require_entitlement;
+use crate::models::account::SubscriptionType;
+use crate::models::repository::{Repository, RepositoryAuditAction, RepositoryTransfer};
+use crate::models::user::{MLSClientId, UserProfile};
+use crate::repository::error::RepoError;
+use crate::repository::mls_client::MLSClientRepositoryTrait;
+use crate::repository::repo_transfer::RepoTransferRepositoryTrait;
+use crate::repository::repository::RepoRepositoryTrait;
+use crate::repository::subscription::SubscriptionRepositoryTrait;
+use crate::repository::transaction::TransactionalRepository;
+use crate::repository::user::UserRepositoryTrait;
+use chrono::TimeDelta;
+use uuid::Uuid;
+
+pub const MAX_REPOSITORY_NAME_LENGTH: usize = 100;
+// How long the previous owner's "owner/name" slug keeps redirecting after a transfer
+pub const TRANSFER_REDIRECT_PERIOD: TimeDelta = TimeDelta::days(90);
+
+fn validate_name(name: &str) -> Result<(), ServiceError> {
+    if name.is_empty() || name.chars().count() > MAX_REPOSITORY_NAME_LENGTH {
+        return Err(ServiceError::InvalidInput(format!(
+            "repository names are 1 to {} characters",
+            MAX_REPOSITORY_NAME_LENGTH
+        )));
+    }
+    if name.contains('/') || name.trim() != name || name.chars().any(char::is_control) {
+        return Err(ServiceError::InvalidInput(
+            "repository names cannot contain '/', control characters or surrounding spaces".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// Only the owner may see or change a repository through these operations
+fn ensure_owner(uid: Uuid, owner_id: Uuid) -> Result<(), ServiceError> {
+    if uid != owner_id {
+        return Err(ServiceError::AuthorizationError(
+            "only the owner can access this repository".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn repository_not_found() -> ServiceError {
+    RepoError::NotFound("Repository not found".to_string()).into()
+}
+
+pub async fn list_repositories<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    uid: Uuid,
+) -> Result<Vec<Repository>, ServiceError> {
+    Ok(repo_repository.list_by_owner(uid).await?)
+}
+
+// Resolves an "owner/name" slug to a repository the user owns
+pub async fn get_repository<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    uid: Uuid,
+    owner: &str,
+    name: &str,
+) -> Result<Repository, ServiceError> {
+    let repository = find_repository(repo_repository, owner, name).await?;
+    ensure_owner(uid, repository.owner_id)?;
+    Ok(repository)
+}
+
+// Resolves an "owner/name" slug for anyone, e.g. members who do not own the repository
+pub async fn find_repository<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    owner: &str,
+    name: &str,
+) -> Result<Repository, ServiceError> {
+    repo_repository
+        .find_by_slug(owner, name)
+        .await?
+        .ok_or_else(repository_not_found)
+}
+
+// `founder` is the user's client that starts the repository's MLS group, if it is known yet
+pub async fn create_repository<R: RepoRepositoryTrait, C: MLSClientRepositoryTrait>(
+    repo_repository: &R,
+    client_repository: &C,
+    uid: Uuid,
+    name: &str,
+    founder: Option<&MLSClientId>,
+) -> Result<Repository, ServiceError> {
+    validate_name(name)?;
+    if let Some(founder) = founder {
+        get_client(client_repository, uid, founder).await?;
+    }
+    Ok(repo_repository.create(uid, name, founder).await?)
+}
+
+pub async fn rename_repository<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    uid: Uuid,
+    owner: &str,
+    name: &str,
+    new_name: &str,
+) -> Result<Repository, ServiceError> {
+    validate_name(new_name)?;
+    let repository = get_repository(repo_repository, uid, owner, name).await?;
+    repo_repository.rename(repository.id, new_name).await?;
+    Ok(Repository {
+        name: new_name.to_string(),
+        ..repository
+    })
+}
+
+pub async fn delete_repository<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    uid: Uuid,
+    owner: &str,
+    name: &str,
+) -> Result<(), ServiceError> {
+    let repository = get_repository(repo_repository, uid, owner, name).await?;
+    Ok(repo_repository.delete(repository.id).await?)
+}
+
+// The repository an old "owner/name" slug used to name, if it was transferred recently
+pub async fn find_moved_repository<R: RepoRepositoryTrait>(
+    repo_repository: &R,
+    owner: &str,
+    name: &str,
+) -> Result<Option<Repository>, ServiceError> {
+    Ok(repo_repository.find_redirect(owner, name).await?)
+}
+
+fn transfer_not_found() -> ServiceError {
+    RepoError::NotFound("Transfer not found".to_string()).into()
+}
+
+// Recipients need a plan of their own to take over a repository
+async fn require_recipient_plan<S: SubscriptionRepositoryTrait>(
+    subscription_repository: &S,
+    recipient: Uuid,
+) -> Result<(), ServiceError> {
+    require_entitlement(subscription_repository, recipient, SubscriptionType::CloudSync, TimeDelta::zero())
+        .await
+        .map(|_| ())
+}
+
+// `to` is the recipient's username, or their user id
+async fn find_recipient<U: UserRepositoryTrait>(
+    user_repository: &U,
+    to: &str,
+) -> Result<UserProfile, ServiceError> {
+    let recipient = match Uuid::parse_str(to) {
+        Ok(id) => user_repository.find_profile(id).await?,
+        Err(_) => user_repository.find_by_username(to).await?,
+    };
+    recipient.ok_or_else(|| RepoError::NotFound("User not found".to_string()).into())
+}
+
+// Offers the repository to another user. Nothing changes until they accept.
+pub async fn offer_transfer<R, T, U, S>(
+    repo_repository: &R,
+    transfer_repository: &T,
+    user_repository: &U,
+    subscription_repository: &S,
+    uid: Uuid,
+    repository: &Repository,
+    to: &str,
+) -> Result<RepositoryTransfer, ServiceError>
+where
+    R: RepoRepositoryTrait,
+    T: RepoTransferRepositoryTrait + TransactionalRepository,
+    U: UserRepositoryTrait,
+    S: SubscriptionRepositoryTrait,
+{
+    ensure_owner(uid, repository.owner_id)?;
+    let recipient = find_recipient(user_repository, to).await?;
+    if recipient.id == uid {
+        return Err(ServiceError::InvalidInput("cannot transfer a repository to yourself".to_string()));
+    }
+    require_recipient_plan(subscription_repository, recipient.id)
+        .await
+        .map_err(|e| match e {
+            ServiceError::PaymentRequired(_) => {
+                ServiceError::PaymentRequired("the recipient needs an active plan".to_string())
+            }
+            e => e,
+        })?;
+
+    let mut tx = transfer_repository.begin().await?;
+    let id = transfer_repository
+        .create(&mut tx, repository.id, uid, recipient.id)
+        .await?;
+    repo_repository
+        .record_audit(
+            &mut tx,
+            repository.id,
+            uid,
+            RepositoryAuditAction::TransferOffered,
+            &format!("to {}", recipient.id),
+        )
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(RepositoryTransfer {
+        id,
+        repo_id: repository.id,
+        repo_name: repository.name.clone(),
+        from_user: uid,
+        from_name: repository.owner_name.clone(),
+        to_user: recipient.id,
+        to_name: recipient.username.unwrap_or_else(|| recipient.id.to_string()),
+        created: chrono::Utc::now().naive_utc(),
+    })
+}
+
+pub async fn list_transfers<T: RepoTransferRepositoryTrait>(
+    transfer_repository: &T,
+    uid: Uuid,
+) -> Result<Vec<RepositoryTransfer>, ServiceError> {
+    Ok(transfer_repository.list_pending(uid).await?)
+}
+
+// Moves the repository to the recipient, leaving a redirect from its old slug
+pub async fn accept_transfer<R, T, S>(
+    repo_repository: &R,
+    transfer_repository: &T,
+    subscription_repository: &S,
+    uid: Uuid,
+    id: Uuid,
+) -> Result<Repository, ServiceError>
+where
+    R: RepoRepositoryTrait,
+    T: RepoTransferRepositoryTrait + TransactionalRepository,
+    S: SubscriptionRepositoryTrait,
+{
+    let mut tx = transfer_repository.begin().await?;
+    let transfer = transfer_repository
+        .find_pending_for_update(&mut tx, id)
+        .await?
+        .ok_or_else(transfer_not_found)?;
+    if transfer.from_user == uid {
+        return Err(ServiceError::AuthorizationError(
+            "only the recipient can accept a transfer".to_string(),
+        ));
+    }
+    if transfer.to_user != uid {
+        return Err(transfer_not_found());
+    }
+    require_recipient_plan(subscription_repository, uid).await?;
+    let redirect_until = chrono::Utc::now().naive_utc() + TRANSFER_REDIRECT_PERIOD;
+    repo_repository
+        .transfer_owner(&mut tx, transfer.repo_id, transfer.from_user, uid, redirect_until)
+        .await?;
+    transfer_repository.set_accepted(&mut tx, id).await?;
+    repo_repository
+        .record_audit(
+            &mut tx,
+            transfer.repo_id,
+            uid,
+            RepositoryAuditAction::TransferAccepted,
+            &format!("from {} to {}", transfer.from_user, uid),
+        )
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    repo_repository
+        .find_by_id(transfer.repo_id)
+        .await?
+        .ok_or_else(repository_not_found)
+}
+
+// Either side can call off a pending transfer: the recipient declines, the sender withdraws
+pub async fn cancel_transfer<R, T>(
+    repo_repository: &R,
+    transfer_repository: &T,
+    uid: Uuid,
+    id: Uuid,
+) -> Result<(), ServiceError>
+where
+    R: RepoRepositoryTrait,
+    T: RepoTransferRepositoryTrait + TransactionalRepository,
+{
+    let mut tx = transfer_repository.begin().await?;
+    let transfer = transfer_repository
+        .find_pending_for_update(&mut tx, id)
+        .await?
+        .ok_or_else(transfer_not_found)?;
+    let detail = if uid == transfer.to_user {
+        "declined by the recipient"
+    } else if uid == transfer.from_user {
+        "withdrawn by the sender"
+    } else {
+        return Err(transfer_not_found());
+    };
+    transfer_repository.set_cancelled(&mut tx, id).await?;
+    repo_repository
+        .record_audit(&mut tx, transfer.repo_id, uid, RepositoryAuditAction::TransferCancelled, detail)
+        .await?;
+    tx.commit().await.map_err(RepoError::from)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_REPOSITORY_NAME_LENGTH, ensure_owner, validate_name};
+    use uuid::Uuid;
+
+    #[test]
+    fn validate_name_tests() {
+        assert!(validate_name("work").is_ok());
+        assert!(validate_name("my tabs").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a/b").is_err());
+        assert!(validate_name(" padded ").is_err());
+        assert!(validate_name("tab\there").is_err());
+        assert!(validate_name(&"x".repeat(MAX_REPOSITORY_NAME_LENGTH)).is_ok());
+        assert!(validate_name(&"x".repeat(MAX_REPOSITORY_NAME_LENGTH + 1)).is_err());
+        // the limit counts characters, not bytes
+        assert!(validate_name("Übungen").is_ok());
+        assert!(validate_name(&"é".repeat(MAX_REPOSITORY_NAME_LENGTH)).is_ok());
+        assert!(validate_name(&"é".repeat(MAX_REPOSITORY_NAME_LENGTH + 1)).is_err());
+    }
+
+    #[test]
+    fn ensure_owner_tests() {
+        let owner = Uuid::new_v4();
+        assert!(ensure_owner(owner, owner).is_ok());
+        assert!(ensure_owner(Uuid::new_v4(), owner).is_err());
+    }
+
+    // Needs a database, which `sqlx::test` creates from DATABASE_URL and migrates
+    #[cfg(feature = "integration-test")]
+    #[sqlx::test]
+    async fn deleted_members_tests(pool: sqlx::PgPool) {
+        use super::{get_repository, list_repositories};
+        use crate::repository::mls_client::{MLSClientRepository, MLSClientRepositoryTrait};
+        use crate::repository::repository::{RepoRepository, RepoRepositoryTrait};
+        use crate::repository::user::{UserRepository, UserRepositoryTrait};
+
+        let users = UserRepository::new(pool.clone());
+        let clients = MLSClientRepository::new(pool.clone());
+        let repositories = RepoRepository::new(pool.clone());
+        let owner = users.create("members@test.account", None).await.unwrap();
+        let founder = clients.create(owner, None).await.unwrap().id;
+        let created = repositories.create(owner, "tabs", Some(&founder)).await.unwrap();
+        assert_eq!(created.members.len(), 1);
+
+        // a deleted client is no longer listed, even while its grant is still in place
+        sqlx::query("UPDATE mls_clients SET deleted = timezone('utc', NOW()) WHERE id = $1")
+            .bind(founder.0)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let owner_name = owner.to_string();
+        let found = get_repository(&repositories, owner, &owner_name, "tabs").await.unwrap();
+        assert!(found.members.is_empty());
+        assert!(repositories.find_by_id(created.id).await.unwrap().unwrap().members.is_empty());
+        let listed = list_repositories(&repositories, owner).await.unwrap();
+        assert!(listed[0].members.is_empty());
+    }
+}
@@ -1,8 +1,27 @@
+use crate::models::account::PaymentInfo;
 use crate::models::account::Settings;
+use crate::models::account::SubscriptionInfo;
+use crate::models::account::SubscriptionType;
+use crate::models::user::UserProfile;
 use crate::logic::error::ServiceError;
+use crate::repository::error::RepoError;
+use chrono::{NaiveDateTime, TimeDelta};
 use uuid::Uuid;
 
+use crate::repository::payment_log::PaymentLogRepositoryTrait;
 use crate::repository::settings::SettingsRepositoryTrait;
+use crate::repository::subscription::SubscriptionRepositoryTrait;
+use crate::repository::user::UserRepositoryTrait;
+
+// Number of payments shown alongside the user's plan
+pub const RECENT_PAYMENTS_LIMIT: u32 = 10;
+
+pub const MIN_USERNAME_LENGTH: usize = 3;
+pub const MAX_USERNAME_LENGTH: usize = 39;
+// How long a user has to wait after setting a username before they can change it
+pub const USERNAME_CHANGE_COOLDOWN: TimeDelta = TimeDelta::days(30);
+// Names that would be confusing as the owner part of an "owner/name" slug
+const RESERVED_USERNAMES: [&str; 6] = ["admin", "api", "me", "root", "support", "system"];
 
 pub async fn get_settings<T: SettingsRepositoryTrait>(
     settings_repository: T,
@@ -19,6 +38,147 @@ pub async fn update_settings<T: SettingsRepositoryTrait>(
     return settings_repository.update(uid, Settings::from(settings)).await.map_err(|e| e.into());
 }
 
+pub async fn get_payment_info<S: SubscriptionRepositoryTrait, P: PaymentLogRepositoryTrait>(
+    subscription_repository: &S,
+    payment_log_repository: &P,
+    uid: Uuid,
+) -> Result<PaymentInfo, ServiceError> {
+    let subscription = match subscription_repository.find_by_user_id(uid).await {
+        Ok(sub_info) => Some(sub_info),
+        Err(RepoError::NotFound(_)) => None,
+        Err(e) => return Err(e.into()),
+    };
+    let recent_payments = payment_log_repository
+        .find_by_user_id(uid, RECENT_PAYMENTS_LIMIT)
+        .await?;
+    Ok(PaymentInfo {
+        subscription,
+        recent_payments,
+    })
+}
+
+// Succeeds with the user's plan if it grants `required` and is active, or lapsed less than
+// `grace_period` ago
+pub async fn require_entitlement<S: SubscriptionRepositoryTrait>(
+    subscription_repository: &S,
+    uid: Uuid,
+    required: SubscriptionType,
+    grace_period: TimeDelta,
+) -> Result<SubscriptionInfo, ServiceError> {
+    let sub_info = match subscription_repository.find_by_user_id(uid).await {
+        Ok(sub_info) => sub_info,
+        Err(RepoError::NotFound(_)) => {
+            return Err(ServiceError::PaymentRequired(format!(
+                "a {} plan is required",
+                required.to_string()
+            )));
+        }
+        Err(e) => return Err(e.into()),
+    };
+    if !sub_info.is_active_with_grace(grace_period) || !sub_info.subscription_type.includes(&required) {
+        return Err(ServiceError::PaymentRequired(format!(
+            "a {} plan is required",
+            required.to_string()
+        )));
+    }
+    Ok(sub_info)
+}
+
+pub async fn require_admin<U: UserRepositoryTrait>(
+    user_repository: &U,
+    uid: Uuid,
+) -> Result<(), ServiceError> {
+    if !user_repository.is_admin(uid).await? {
+        return Err(ServiceError::AuthorizationError("admin access required".to_string()));
+    }
+    Ok(())
+}
+
+// Usernames are ASCII letters, digits and single hyphens, not starting or ending with a hyphen.
+// A username that parses as a UUID would be ambiguous with owners addressed by id.
+pub fn validate_username(username: &str) -> Result<(), ServiceError> {
+    if username.len() < MIN_USERNAME_LENGTH || username.len() > MAX_USERNAME_LENGTH {
+        return Err(ServiceError::InvalidInput(format!(
+            "username must be between {} and {} characters",
+            MIN_USERNAME_LENGTH, MAX_USERNAME_LENGTH
+        )));
+    }
+    if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        || username.starts_with('-')
+        || username.ends_with('-')
+        || username.contains("--")
+    {
+        return Err(ServiceError::InvalidInput(
+            "username may only contain letters, digits and single hyphens between them".to_string(),
+        ));
+    }
+    if Uuid::parse_str(username).is_ok() {
+        return Err(ServiceError::InvalidInput("username cannot be a uuid".to_string()));
+    }
+    if RESERVED_USERNAMES.contains(&username.to_ascii_lowercase().as_str()) {
+        return Err(ServiceError::InvalidInput("username is reserved".to_string()));
+    }
+    Ok(())
+}
+
+// Claiming a first username is always allowed, changing it only once the cooldown has passed
+pub fn ensure_username_change_allowed(
+    profile: &UserProfile,
+    now: NaiveDateTime,
+    cooldown: TimeDelta,
+) -> Result<(), ServiceError> {
+    if profile.username.is_none() {
+        return Ok(());
+    }
+    match profile.username_changed {
+        Some(changed) if now < changed + cooldown => Err(ServiceError::RateLimited(format!(
+            "username can be changed again after {}",
+            (changed + cooldown).and_utc().to_rfc3339()
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub async fn get_profile<U: UserRepositoryTrait>(
+    user_repository: &U,
+    uid: Uuid,
+) -> Result<UserProfile, ServiceError> {
+    user_repository
+        .find_profile(uid)
+        .await?
+        .ok_or_else(|| ServiceError::RepositoryError(RepoError::NotFound("User not found".to_string())))
+}
+
+pub async fn find_by_username<U: UserRepositoryTrait>(
+    user_repository: &U,
+    username: &str,
+) -> Result<UserProfile, ServiceError> {
+    user_repository
+        .find_by_username(username)
+        .await?
+        .ok_or_else(|| ServiceError::RepositoryError(RepoError::NotFound("User not found".to_string())))
+}
+
+// Claims or changes the user's username. Fails with DuplicateEntry if it is taken.
+pub async fn set_username<U: UserRepositoryTrait>(
+    user_repository: &U,
+    uid: Uuid,
+    username: &str,
+) -> Result<UserProfile, ServiceError> {
+    validate_username(username)?;
+    let profile = get_profile(user_repository, uid).await?;
+    if profile.username.as_deref() == Some(username) {
+        return Ok(profile);
+    }
+    let now = chrono::Utc::now().naive_utc();
+    ensure_username_change_allowed(&profile, now, USERNAME_CHANGE_COOLDOWN)?;
+    // a concurrent change can still win between the check above and the update
+    if !user_repository.set_username(uid, username, now - USERNAME_CHANGE_COOLDOWN).await? {
+        return Err(ServiceError::RateLimited("username was changed recently".to_string()));
+    }
+    get_profile(user_repository, uid).await
+}
+
 pub fn verify_email(env: crate::state::Environment, email: &str, email_verified: bool) -> bool {
     return email_verified
         || (email.ends_with("@test.account") && env != crate::state::Environment::Production);
@@ -26,7 +186,45 @@ pub fn verify_email(env: crate::state::Environment, email: &str, email_verified:
 
 #[cfg(test)]
 mod tests {
-    use super::verify_email;
+    use super::{ensure_username_change_allowed, validate_username, verify_email};
+    use crate::models::user::UserProfile;
+    use chrono::{NaiveDate, TimeDelta};
+    use uuid::Uuid;
+
+    #[test]
+    fn validate_username_tests() {
+        assert!(validate_username("alice").is_ok());
+        assert!(validate_username("Alice-42").is_ok());
+        assert!(validate_username("ab").is_err());
+        assert!(validate_username(&"a".repeat(40)).is_err());
+        assert!(validate_username("-alice").is_err());
+        assert!(validate_username("alice-").is_err());
+        assert!(validate_username("al--ice").is_err());
+        assert!(validate_username("al ice").is_err());
+        assert!(validate_username("al/ice").is_err());
+        assert!(validate_username("élise").is_err());
+        assert!(validate_username("Admin").is_err());
+        assert!(validate_username(&Uuid::new_v4().to_string()).is_err());
+    }
+
+    #[test]
+    fn ensure_username_change_allowed_tests() {
+        let changed = NaiveDate::from_ymd_opt(2026, 1, 1).unwrap().and_hms_opt(0, 0, 0).unwrap();
+        let cooldown = TimeDelta::days(30);
+        let mut profile = UserProfile {
+            id: Uuid::new_v4(),
+            username: None,
+            username_changed: None,
+            created: changed,
+        };
+        assert!(ensure_username_change_allowed(&profile, changed, cooldown).is_ok());
+        profile.username = Some("alice".to_string());
+        assert!(ensure_username_change_allowed(&profile, changed, cooldown).is_ok());
+        profile.username_changed = Some(changed);
+        assert!(ensure_username_change_allowed(&profile, changed + TimeDelta::days(29), cooldown).is_err());
+        assert!(ensure_username_change_allowed(&profile, changed + cooldown, cooldown).is_ok());
+    }
+
     #[test]
     fn verify_email_tests() {
         assert!(!verify_email(
@@ -65,4 +263,24 @@ mod tests {
             true
         ));
     }
+
+    // Needs a database, which `sqlx::test` creates from DATABASE_URL and migrates
+    #[cfg(feature = "integration-test")]
+    #[sqlx::test]
+    async fn username_cooldown_tests(pool: sqlx::PgPool) {
+        use super::{set_username, USERNAME_CHANGE_COOLDOWN};
+        use crate::logic::error::ServiceError;
+        use crate::repository::user::{UserRepository, UserRepositoryTrait};
+
+        let users = UserRepository::new(pool);
+        let uid = users.create("renamed@test.account", None).await.unwrap();
+        assert_eq!(set_username(&users, uid, "first-name").await.unwrap().username.as_deref(), Some("first-name"));
+        let changed = set_username(&users, uid, "second-name").await;
+        assert!(matches!(changed, Err(ServiceError::RateLimited(_))));
+
+        // the update enforces the cooldown itself, for changes racing past the check before it
+        let now = chrono::Utc::now().naive_utc();
+        assert!(!users.set_username(uid, "second-name", now - USERNAME_CHANGE_COOLDOWN).await.unwrap());
+        assert!(users.set_username(uid, "second-name", now + TimeDelta::seconds(1)).await.unwrap());
+    }
 }
@@ -1,38 +1,31 @@
-use crate::models::account::SubscriptionType;
 use crate::{
-    handlers::middleware::{with_authenticated, with_logging},
-    state::AppState,
+    handlers::middleware::{require_plan, with_admin, with_authenticated, with_logging},
+    logic::{notification::LogNotifier, payment::CheckoutUrls},
+    models::account::SubscriptionType,
+    state::{AppState, BillingConfig, MessagingConfig},
 };
 use aws_config::BehaviorVersion;
-use axum::body::Body;
-use axum::extract::FromRequest;
-use axum::extract::Request;
-use axum::response::IntoResponse;
-use axum::response::Response;
 use axum::{
     Router, middleware,
     response::Redirect,
-    routing::{get, post},
+    routing::{delete, get, patch, post, put},
 };
+use chrono::TimeDelta;
 use core::panic;
 use dotenvy::dotenv;
 use firebase_auth::{FirebaseAuth, FirebaseAuthState};
-use reqwest::StatusCode;
 use serde_json::Value;
 use sqlx::pool::PoolOptions;
 use std::fs::OpenOptions;
 use std::{collections::HashMap, future::IntoFuture, net::SocketAddr};
 use std::{env, sync::Arc};
-use stripe_webhook::Event;
-use stripe_webhook::EventObject;
-use stripe_webhook::Webhook;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::prelude::__tracing_subscriber_SubscriberExt;
 use tracing_subscriber::util::SubscriberInitExt;
 use urlencoding::encode;
-use uuid::Uuid;
 
 pub mod handlers;
+pub mod jobs;
 pub mod logic;
 pub mod models;
 pub mod repository;
@@ -47,6 +40,13 @@ struct Environment {
     database_port: String,
     database_name: String,
     firebase_project_id: String,
+    stripe_api_key: String,
+    stripe_webhook_secret: String,
+    checkout_success_url: String,
+    checkout_cancel_url: String,
+    grace_period_days: i64,
+    expiry_notice_days: i64,
+    key_package_target: u32,
     environment: state::Environment,
 }
 
@@ -77,6 +77,27 @@ impl Environment {
             firebase_project_id: env::var("FIREBASE_PROJECT_ID").expect(
                 "Could not find FIREBASE_PROJECT_ID environment variable anywhere. Try putting it in .env",
             ),
+            stripe_api_key: env::var("STRIPE_API_KEY").expect(
+                "Could not find STRIPE_API_KEY environment variable anywhere. Try putting it in .env",
+            ),
+            stripe_webhook_secret: env::var("STRIPE_WEBHOOK_SECRET").expect(
+                "Could not find STRIPE_WEBHOOK_SECRET environment variable anywhere. Try putting it in .env",
+            ),
+            checkout_success_url: env::var("CHECKOUT_SUCCESS_URL").expect(
+                "Could not find CHECKOUT_SUCCESS_URL environment variable anywhere. Try putting it in .env",
+            ),
+            checkout_cancel_url: env::var("CHECKOUT_CANCEL_URL").expect(
+                "Could not find CHECKOUT_CANCEL_URL environment variable anywhere. Try putting it in .env",
+            ),
+            grace_period_days: env::var("GRACE_PERIOD_DAYS").expect(
+                "Could not find GRACE_PERIOD_DAYS environment variable anywhere. Try putting it in .env",
+            ).parse().expect("GRACE_PERIOD_DAYS must be a whole number of days"),
+            expiry_notice_days: env::var("EXPIRY_NOTICE_DAYS").expect(
+                "Could not find EXPIRY_NOTICE_DAYS environment variable anywhere. Try putting it in .env",
+            ).parse().expect("EXPIRY_NOTICE_DAYS must be a whole number of days"),
+            key_package_target: env::var("KEY_PACKAGE_TARGET").expect(
+                "Could not find KEY_PACKAGE_TARGET environment variable anywhere. Try putting it in .env",
+            ).parse().expect("KEY_PACKAGE_TARGET must be a whole number of key packages"),
             environment: match env::var("ENVIRONMENT").expect("Could not find ENVIRONMENT environment variable anywhere. Try putting it in .env").as_str() {
                 "prod" => state::Environment::Production,
                 "staging" => state::Environment::Staging,
@@ -170,17 +191,110 @@ async fn main() {
         .expect("Could not run database migrations");
     let firebase_auth = Arc::new(FirebaseAuth::new(&env.firebase_project_id).await);
 
-    let state = AppState::new(pool, firebase_auth, env.environment);
+    let billing = BillingConfig {
+        stripe_client: stripe::Client::new(env.stripe_api_key.clone()),
+        webhook_secret: env.stripe_webhook_secret.clone(),
+        checkout_urls: CheckoutUrls {
+            success_url: env.checkout_success_url.clone(),
+            cancel_url: env.checkout_cancel_url.clone(),
+        },
+        grace_period: TimeDelta::days(env.grace_period_days),
+    };
+    let messaging = MessagingConfig {
+        key_package_target: env.key_package_target,
+    };
+    let state = AppState::new(pool, firebase_auth, billing, messaging, env.environment);
+    tokio::spawn(jobs::expiry::run(
+        state.subscription_repository.clone(),
+        LogNotifier,
+        TimeDelta::days(env.expiry_notice_days),
+    ));
+    tokio::spawn(jobs::message_wakeups::run(
+        state.message_notification_repository.clone(),
+        state.waiters.clone(),
+    ));
+    tokio::spawn(jobs::key_packages::run(state.key_package_repository.clone()));
+    // routes that sync a user's repositories between their devices
+    let sync = Router::new()
+        .route(
+            "/repositories",
+            get(handlers::repository::list).post(handlers::repository::create),
+        )
+        .route("/repositories/push", post(handlers::commit::push))
+        .route(
+            "/repositories/{owner}/{name}",
+            get(handlers::repository::get)
+                .patch(handlers::repository::rename)
+                .delete(handlers::repository::delete),
+        )
+        .route(
+            "/repositories/{owner}/{name}/messages",
+            get(handlers::message::fetch_broadcast).post(handlers::message::send_broadcast),
+        )
+        .route(
+            "/repositories/{owner}/{name}/events",
+            get(handlers::events::stream_repository),
+        )
+        .route(
+            "/repositories/{owner}/{name}/branches",
+            get(handlers::branch::list),
+        )
+        .route(
+            "/repositories/{owner}/{name}/branches/{branch}",
+            get(handlers::branch::get).put(handlers::branch::update),
+        )
+        .route(
+            "/repositories/{owner}/{name}/messages/ack",
+            post(handlers::message::acknowledge_broadcast),
+        )
+        .route("/clients/{id}/key-packages", post(handlers::key_package::upload))
+        .route(
+            "/clients/{id}/messages",
+            get(handlers::message::fetch_unicast).post(handlers::message::send_unicast),
+        )
+        .route("/clients/{id}/messages/ack", post(handlers::message::acknowledge_unicast))
+        .route("/clients/{id}/events", get(handlers::events::connect))
+        .route("/key-packages/claim", post(handlers::key_package::claim))
+        .route("/key-packages/inventory", get(handlers::key_package::inventory));
+    // routes that share a repository with other users
+    let collaborate = Router::new()
+        .route(
+            "/repositories/{owner}/{name}/transfer",
+            post(handlers::repository::offer_transfer),
+        );
     // build our application with a route
     let app = Router::new()
+        .route("/admin/promo-codes", post(handlers::promo::mint))
+        .route_layer(middleware::from_fn_with_state(state.clone(), with_admin))
+        .merge(require_plan(sync, &state, SubscriptionType::CloudSync))
+        .merge(require_plan(collaborate, &state, SubscriptionType::SyncCollaborate))
         .route("/auth/me", get(handlers::auth::me))
         .route("/account/settings", get(handlers::account::get_settings))
+        .route("/account/payment-info", get(handlers::account::payment_info))
         .route("/account/settings", post(handlers::account::post_settings))
+        .route(
+            "/clients",
+            get(handlers::mls_client::list).post(handlers::mls_client::register),
+        )
+        .route(
+            "/clients/{id}",
+            patch(handlers::mls_client::rename).delete(handlers::mls_client::revoke),
+        )
+        .route("/transfers", get(handlers::repository::list_transfers))
+        .route("/transfers/{id}", delete(handlers::repository::cancel_transfer))
+        .route("/transfers/{id}/accept", post(handlers::repository::accept_transfer))
+        .route("/account/profile", get(handlers::account::get_profile))
+        .route("/account/username", put(handlers::account::set_username))
+        .route("/users/{username}", get(handlers::account::lookup))
+        .route("/billing/checkout", post(handlers::billing::checkout))
+        .route("/billing/change-plan", post(handlers::billing::change_plan))
+        .route("/billing/promo/redeem", post(handlers::promo::redeem))
         .route_layer(middleware::from_fn_with_state(
             state.clone(),
             with_authenticated,
         ))
         .route("/auth/init", post(handlers::auth::init))
+        .route("/billing/webhook", post(handlers::billing::webhook))
         .route("/ping", get(handlers::status::ping))
         .route_layer(middleware::from_fn_with_state(state.clone(), with_logging))
         .layer(TraceLayer::new_for_http())
@@ -207,68 +321,3 @@ async fn main() {
 
     tracing::info!("Shutting down the server...");
 }
-
-struct StripeEvent(Event);
-
-impl<S> FromRequest<S> for StripeEvent
-where
-    String: FromRequest<S>,
-    S: Send + Sync,
-{
-    type Rejection = Response;
-
-    async fn from_request(req: Request<Body>, state: &S) -> Result<Self, Self::Rejection> {
-        let signature = if let Some(sig) = req.headers().get("stripe-signature") {
-            sig.to_owned()
-        } else {
-            return Err(StatusCode::BAD_REQUEST.into_response());
-        };
-
-        let payload = String::from_request(req, state)
-            .await
-            .map_err(IntoResponse::into_response)?;
-
-        Ok(Self(
-            Webhook::construct_event(&payload, signature.to_str().unwrap(), "whsec_xxxxx")
-                .map_err(|_| StatusCode::BAD_REQUEST.into_response())?,
-        ))
-    }
-}
-
-#[axum::debug_handler]
-async fn handle_webhook(StripeEvent(event): StripeEvent) -> Result<(), StatusCode> {
-    match event.data.object {
-        EventObject::CheckoutSessionCompleted(session) => {
-            let meta = session.metadata.ok_or(StatusCode::BAD_REQUEST)?;
-            let user_id = Uuid::parse_str(meta.get("uuid").ok_or(StatusCode::BAD_REQUEST)?)
-                .map_err(|_| StatusCode::BAD_REQUEST)?;
-            let months: u32 = meta
-                .get("months")
-                .ok_or(StatusCode::BAD_REQUEST)?
-                .parse()
-                .map_err(|_| StatusCode::BAD_REQUEST)?;
-            let sub_type = match meta.get("type").ok_or(StatusCode::BAD_REQUEST)?.as_str() {
-                "cloud" => SubscriptionType::CloudSync,
-                "collab" => SubscriptionType::SyncCollaborate,
-                _ => return Err(StatusCode::BAD_REQUEST),
-            };
-            let session_id = session.id;
-            let payment_intent_id = session.payment_intent.as_ref().map(|pi| pi.id());
-            // Extend user's account by `months` of `sub_type`
-            // ...
-            Ok(())
-            // TODO: do something
-        }
-        EventObject::AccountUpdated(account) => {
-            println!(
-                "Received account updated webhook for account: {:?}",
-                account.id
-            );
-            Ok(())
-        }
-        _ => {
-            println!("Unknown event encountered in webhook: {:?}", event.type_);
-            Ok(())
-        }
-    }
-}
@@ -59,7 +59,7 @@ pub struct Settings {
     pub auto_push_behaviour: AutoPushBehaviour,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SubscriptionType {
     CloudSync,
     SyncCollaborate,
@@ -79,6 +79,22 @@ impl SubscriptionType {
             _ => None,
         }
     }
+    // Whether this plan grants everything `other` does. Sync Collaborate includes Cloud Sync.
+    pub fn includes(&self, other: &SubscriptionType) -> bool {
+        match (self, other) {
+            (SubscriptionType::SyncCollaborate, _) => true,
+            (SubscriptionType::CloudSync, SubscriptionType::CloudSync) => true,
+            (SubscriptionType::CloudSync, SubscriptionType::SyncCollaborate) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SubscriptionPlan {
+    pub subscription_type: SubscriptionType,
+    pub description: Option<String>,
+    pub price_cents_per_month: u32,
+    pub stripe_price_id: Option<String>, // recurring monthly price, created on first use
 }
 
 #[derive(Debug, Clone)]
@@ -92,6 +108,90 @@ impl SubscriptionInfo {
         let now = chrono::Utc::now().naive_utc();
         return self.paid_until > now;
     }
+    // Whether access continues, allowing `grace` after `paid_until` for the user to renew
+    pub fn is_active_with_grace(&self, grace: TimeDelta) -> bool {
+        let now = chrono::Utc::now().naive_utc();
+        self.paid_until + grace > now
+    }
+    // Whole days left before the plan lapses, counting a partial day as a full one
+    pub fn days_remaining(&self) -> i64 {
+        let now = chrono::Utc::now().naive_utc();
+        let remaining = self.paid_until - now;
+        if remaining <= TimeDelta::zero() {
+            return 0;
+        }
+        let days = remaining.num_days();
+        if remaining > TimeDelta::days(days) { days + 1 } else { days }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentStatus {
+    Paid,
+    PartiallyRefunded,
+    Refunded,
+    Disputed,
+}
+
+impl PaymentStatus {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            PaymentStatus::Paid => "paid",
+            PaymentStatus::PartiallyRefunded => "partially refunded",
+            PaymentStatus::Refunded => "refunded",
+            PaymentStatus::Disputed => "disputed",
+        }
+    }
+    pub fn from_string(s: &str) -> Option<PaymentStatus> {
+        match s {
+            "paid" => Some(PaymentStatus::Paid),
+            "partially refunded" => Some(PaymentStatus::PartiallyRefunded),
+            "refunded" => Some(PaymentStatus::Refunded),
+            "disputed" => Some(PaymentStatus::Disputed),
+            _ => None,
+        }
+    }
+}
+
+// What redeeming a promo code gets the user
+#[derive(Debug, Clone, PartialEq)]
+pub enum PromoReward {
+    // percentage taken off a checkout, applied through a Stripe coupon
+    PercentOff(u32),
+    // months of the code's plan granted directly, without a checkout
+    FreeMonths(u32),
+}
+
+#[derive(Debug, Clone)]
+pub struct PromoCode {
+    pub code: String,
+    pub reward: PromoReward,
+    pub subscription_type: Option<SubscriptionType>, // None if the code applies to every plan
+    pub max_redemptions: Option<u32>,                // None if unlimited
+    pub redemptions: u32,
+    pub expires: Option<NaiveDateTime>, // None if the code never expires
+    pub stripe_coupon_id: Option<String>,
+}
+
+impl PromoCode {
+    pub fn is_expired(&self) -> bool {
+        let now = chrono::Utc::now().naive_utc();
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+    pub fn is_exhausted(&self) -> bool {
+        self.max_redemptions.is_some_and(|max| self.redemptions >= max)
+    }
+    pub fn applies_to(&self, subscription_type: &SubscriptionType) -> bool {
+        self.subscription_type.as_ref().is_none_or(|t| t == subscription_type)
+    }
+}
+
+// A plan that is about to lapse, for reminding its owner to renew
+#[derive(Debug, Clone)]
+pub struct ExpiryNotice {
+    pub user_id: Uuid,
+    pub email: String,
+    pub subscription: SubscriptionInfo,
 }
 
 #[derive(Debug, Clone)]
@@ -100,5 +200,97 @@ pub struct Payment {
     pub user_id: Uuid,
     pub amount_cents: u32,
     pub payment_date: NaiveDateTime,
+    pub months: u32, // months of credit the payment bought
+    pub status: PaymentStatus,
+    pub refunded_cents: u32,
 }
 
+// Links a user to their Stripe customer and, if they auto-renew, their Stripe subscription
+#[derive(Debug, Clone)]
+pub struct StripeCustomer {
+    pub user_id: Uuid,
+    pub customer_id: String,
+    pub subscription_id: Option<String>,
+    pub subscription_status: Option<String>,
+    pub subscription_type: Option<SubscriptionType>,
+}
+
+impl StripeCustomer {
+    pub fn has_live_subscription(&self) -> bool {
+        matches!(
+            self.subscription_status.as_deref(),
+            Some("active") | Some("trialing") | Some("past_due") | Some("incomplete")
+        )
+    }
+}
+
+// A switch between plans. `applied` is false when the change was only previewed.
+#[derive(Debug, Clone)]
+pub struct PlanChange {
+    pub previous: SubscriptionInfo,
+    pub updated: SubscriptionInfo,
+    pub applied: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PaymentInfo {
+    pub subscription: Option<SubscriptionInfo>, // None if the user has never paid
+    pub recent_payments: Vec<Payment>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PromoCode, PromoReward, SubscriptionInfo, SubscriptionType};
+    use chrono::TimeDelta;
+
+    #[test]
+    fn days_remaining_tests() {
+        let now = chrono::Utc::now().naive_utc();
+        let info = |paid_until| SubscriptionInfo {
+            paid_until,
+            subscription_type: SubscriptionType::CloudSync,
+        };
+        assert_eq!(info(now - TimeDelta::days(3)).days_remaining(), 0);
+        assert_eq!(info(now + TimeDelta::hours(1)).days_remaining(), 1);
+        assert_eq!(info(now + TimeDelta::days(30) + TimeDelta::minutes(1)).days_remaining(), 31);
+        assert!(!info(now - TimeDelta::seconds(1)).is_active());
+        assert!(info(now + TimeDelta::days(1)).is_active());
+        assert!(info(now - TimeDelta::days(2)).is_active_with_grace(TimeDelta::days(3)));
+        assert!(!info(now - TimeDelta::days(4)).is_active_with_grace(TimeDelta::days(3)));
+        assert!(!info(now - TimeDelta::seconds(1)).is_active_with_grace(TimeDelta::zero()));
+    }
+
+    #[test]
+    fn promo_code_tests() {
+        let now = chrono::Utc::now().naive_utc();
+        let code = PromoCode {
+            code: "LAUNCH".to_string(),
+            reward: PromoReward::PercentOff(20),
+            subscription_type: Some(SubscriptionType::CloudSync),
+            max_redemptions: Some(2),
+            redemptions: 1,
+            expires: Some(now + TimeDelta::days(1)),
+            stripe_coupon_id: None,
+        };
+        assert!(!code.is_expired());
+        assert!(!code.is_exhausted());
+        assert!(code.applies_to(&SubscriptionType::CloudSync));
+        assert!(!code.applies_to(&SubscriptionType::SyncCollaborate));
+
+        let used_up = PromoCode { redemptions: 2, ..code.clone() };
+        assert!(used_up.is_exhausted());
+        let expired = PromoCode { expires: Some(now - TimeDelta::seconds(1)), ..code.clone() };
+        assert!(expired.is_expired());
+        let open = PromoCode { subscription_type: None, max_redemptions: None, expires: None, ..code };
+        assert!(!open.is_expired() && !open.is_exhausted());
+        assert!(open.applies_to(&SubscriptionType::SyncCollaborate));
+    }
+
+    #[test]
+    fn includes_tests() {
+        assert!(SubscriptionType::CloudSync.includes(&SubscriptionType::CloudSync));
+        assert!(!SubscriptionType::CloudSync.includes(&SubscriptionType::SyncCollaborate));
+        assert!(SubscriptionType::SyncCollaborate.includes(&SubscriptionType::CloudSync));
+        assert!(SubscriptionType::SyncCollaborate.includes(&SubscriptionType::SyncCollaborate));
+    }
+}
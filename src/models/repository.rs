@@ -3,17 +3,51 @@ use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct Repository {
+    pub id: Uuid,
     pub owner_id: Uuid,
+    pub owner_name: String, // the owner's username, or their id if they have not picked one
     pub name: String,
     pub members: Vec<MLSClientId>,
 }
 
 impl Repository {
+    // Human readable "owner/name" slug. Unlike `id` it changes when the repository is renamed.
     pub fn identifier(&self) -> String {
-        format!("{}/{}", self.owner_id, self.name)
+        format!("{}/{}", self.owner_name, self.name)
+    }
+}
+
+// A pending offer to hand a repository over to another user
+#[derive(Debug, Clone)]
+pub struct RepositoryTransfer {
+    pub id: Uuid,
+    pub repo_id: Uuid,
+    pub repo_name: String,
+    pub from_user: Uuid,
+    pub from_name: String, // username, or user id if unset
+    pub to_user: Uuid,
+    pub to_name: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepositoryAuditAction {
+    TransferOffered,
+    TransferAccepted,
+    TransferCancelled,
+}
+
+impl RepositoryAuditAction {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            RepositoryAuditAction::TransferOffered => "transfer offered",
+            RepositoryAuditAction::TransferAccepted => "transfer accepted",
+            RepositoryAuditAction::TransferCancelled => "transfer cancelled",
+        }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum RepositoryPermission {
     Viewer = 250,
     Contributor = 500,
@@ -21,6 +55,30 @@ pub enum RepositoryPermission {
     Admin = 1000,
 }
 
+impl RepositoryPermission {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            RepositoryPermission::Viewer => "viewer",
+            RepositoryPermission::Contributor => "contributor",
+            RepositoryPermission::Editor => "editor",
+            RepositoryPermission::Admin => "admin",
+        }
+    }
+    pub fn from_string(s: &str) -> Option<RepositoryPermission> {
+        match s {
+            "viewer" => Some(RepositoryPermission::Viewer),
+            "contributor" => Some(RepositoryPermission::Contributor),
+            "editor" => Some(RepositoryPermission::Editor),
+            "admin" => Some(RepositoryPermission::Admin),
+            _ => None,
+        }
+    }
+    // Whether this level grants everything `other` does
+    pub fn includes(&self, other: RepositoryPermission) -> bool {
+        *self as i32 >= other as i32
+    }
+}
+
 pub struct RepositoryAccess {
     pub repository: Repository,
     pub permission: RepositoryPermission,
@@ -33,7 +91,7 @@ impl RepositoryAccess {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CommitHash(pub String);
 
 #[derive(Debug, Clone)]
@@ -53,7 +111,16 @@ pub struct Commit {
     pub created_at: chrono::NaiveDateTime,
 }
 
+// A named head of the repository's commit graph
 #[derive(Debug, Clone)]
+pub struct Branch {
+    pub repo: Uuid,
+    pub name: String,
+    pub head: CommitHash,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum MessageType {
     Proposal,
     Commit,
@@ -61,6 +128,26 @@ pub enum MessageType {
     Application,
 }
 
+impl MessageType {
+    pub fn to_string(&self) -> &'static str {
+        match self {
+            MessageType::Proposal => "proposal",
+            MessageType::Commit => "commit",
+            MessageType::Welcome => "welcome",
+            MessageType::Application => "application",
+        }
+    }
+    pub fn from_string(s: &str) -> Option<MessageType> {
+        match s {
+            "proposal" => Some(MessageType::Proposal),
+            "commit" => Some(MessageType::Commit),
+            "welcome" => Some(MessageType::Welcome),
+            "application" => Some(MessageType::Application),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct BroadcastMessage {
     pub id: Uuid,
@@ -68,6 +155,8 @@ pub struct BroadcastMessage {
     pub sender: MLSClientId,
     pub message_type: MessageType,
     pub payload: Vec<u8>, // encrypted payload
+    pub sequence: i64,    // position in the repository's message order, assigned when stored
+    pub epoch: i64,       // MLS epoch the message was created in
     pub created_at: chrono::NaiveDateTime,
 }
 
@@ -94,4 +183,109 @@ pub struct UnicastMessageReadReceipt {
     pub read_at: chrono::NaiveDateTime,
 }
 
+// Where events are published. Clients listen to their own topic and to those of the repositories
+// they are members of.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EventTopic {
+    Repository(Uuid),
+    Client(MLSClientId),
+}
+
+// Tells listening clients that something new can be fetched. Payloads are not included.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RepositoryEvent {
+    Broadcast {
+        repo: Uuid,
+        id: Uuid,
+        sequence: i64,
+        epoch: i64,
+        sender: MLSClientId,
+        message_type: MessageType,
+    },
+    Unicast {
+        recipient: MLSClientId,
+        id: Uuid,
+        sender: MLSClientId,
+        message_type: MessageType,
+    },
+    Commit {
+        repo: Uuid,
+        hash: CommitHash,
+        author: MLSClientId,
+    },
+    Branch {
+        repo: Uuid,
+        name: String,
+        head: CommitHash,
+    },
+}
+
+impl RepositoryEvent {
+    // Position in the repository's sequence, for events that have one
+    pub fn sequence(&self) -> Option<i64> {
+        match self {
+            RepositoryEvent::Broadcast { sequence, .. } => Some(*sequence),
+            RepositoryEvent::Unicast { .. } | RepositoryEvent::Commit { .. } | RepositoryEvent::Branch { .. } => None,
+        }
+    }
+}
+
+impl From<&BroadcastMessage> for RepositoryEvent {
+    fn from(message: &BroadcastMessage) -> Self {
+        RepositoryEvent::Broadcast {
+            repo: message.repo,
+            id: message.id,
+            sequence: message.sequence,
+            epoch: message.epoch,
+            sender: message.sender.clone(),
+            message_type: message.message_type.clone(),
+        }
+    }
+}
+
+impl From<&Commit> for RepositoryEvent {
+    fn from(commit: &Commit) -> Self {
+        RepositoryEvent::Commit {
+            repo: commit.repo,
+            hash: commit.hash.clone(),
+            author: commit.author.clone(),
+        }
+    }
+}
+
+impl From<&Branch> for RepositoryEvent {
+    fn from(branch: &Branch) -> Self {
+        RepositoryEvent::Branch {
+            repo: branch.repo,
+            name: branch.name.clone(),
+            head: branch.head.clone(),
+        }
+    }
+}
+
+impl From<&UnicastMessage> for RepositoryEvent {
+    fn from(message: &UnicastMessage) -> Self {
+        RepositoryEvent::Unicast {
+            recipient: message.recipient.clone(),
+            id: message.id,
+            sender: message.sender.clone(),
+            message_type: message.message_type.clone(),
+        }
+    }
+}
+
 pub struct BlobServerId(pub Uuid);
+
+#[cfg(test)]
+mod tests {
+    use super::RepositoryPermission;
+
+    #[test]
+    fn includes_tests() {
+        assert!(RepositoryPermission::Admin.includes(RepositoryPermission::Contributor));
+        assert!(RepositoryPermission::Editor.includes(RepositoryPermission::Contributor));
+        assert!(RepositoryPermission::Contributor.includes(RepositoryPermission::Contributor));
+        assert!(!RepositoryPermission::Viewer.includes(RepositoryPermission::Contributor));
+        assert!(!RepositoryPermission::Contributor.includes(RepositoryPermission::Admin));
+    }
+}
@@ -9,12 +9,22 @@ pub struct User {
 }
 
 #[derive(Debug, Clone)]
+pub struct UserProfile {
+    pub id: Uuid,
+    pub username: Option<String>,
+    pub username_changed: Option<chrono::NaiveDateTime>,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct MLSClientId(pub Uuid);
 
 #[derive(Debug, Clone)]
 pub struct MLSClient {
     pub id: MLSClientId,
     pub assoc_user: Option<Uuid>, // associated user account, if any
+    pub name: Option<String>,     // chosen by the user, e.g. "Work laptop"
+    pub created: chrono::NaiveDateTime,
 }
 
 impl MLSClient {
@@ -25,7 +35,48 @@ impl MLSClient {
 
 #[derive(Debug, Clone)]
 pub struct KeyPackage {
+    pub id: Uuid,
     pub mls_client_id: MLSClientId,
     pub kpkg: Vec<u8>, // encrypted with user's public key
     pub expires_at: chrono::NaiveDateTime,
+    pub last_resort: bool, // reused when the client has no other package left
+}
+
+impl KeyPackage {
+    pub fn is_expired(&self, now: chrono::NaiveDateTime) -> bool {
+        self.expires_at <= now
+    }
+}
+
+// How many key packages a client has left to hand out
+#[derive(Debug, Clone)]
+pub struct KeyPackageInventory {
+    pub mls_client_id: MLSClientId,
+    pub available: u32, // unexpired, unclaimed one-time packages
+    pub has_last_resort: bool,
+}
+
+impl KeyPackageInventory {
+    // One-time packages to upload to get back to `target`
+    pub fn to_upload(&self, target: u32) -> u32 {
+        target.saturating_sub(self.available)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{KeyPackageInventory, MLSClientId};
+    use uuid::Uuid;
+
+    #[test]
+    fn to_upload_tests() {
+        let inventory = KeyPackageInventory {
+            mls_client_id: MLSClientId(Uuid::new_v4()),
+            available: 30,
+            has_last_resort: true,
+        };
+        assert_eq!(inventory.to_upload(100), 70);
+        assert_eq!(inventory.to_upload(30), 0);
+        assert_eq!(inventory.to_upload(10), 0);
+    }
 }
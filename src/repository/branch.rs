@@ -0,0 +1,111 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::repository::{Branch, CommitHash};
+use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+
+#[derive(Clone, Debug)]
+pub struct BranchRepository {
+    conn: PgPool,
+}
+
+pub trait BranchRepositoryTrait {
+    // Ordered by name
+    fn list(&self, repo: Uuid) -> impl Future<Output = Result<Vec<Branch>, RepoError>>;
+    fn find(&self, repo: Uuid, name: &str) -> impl Future<Output = Result<Option<Branch>, RepoError>>;
+    // False if the branch already exists
+    fn create(&self, tx: &mut Transaction, branch: &Branch) -> impl Future<Output = Result<bool, RepoError>>;
+    // Moves the branch to `branch.head` only if its head is still `expected`. False if it moved
+    // since or does not exist.
+    fn compare_and_swap(
+        &self,
+        tx: &mut Transaction,
+        branch: &Branch,
+        expected: &CommitHash,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+}
+
+impl BranchRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for BranchRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+impl BranchRepositoryTrait for BranchRepository {
+    async fn list(&self, repo: Uuid) -> Result<Vec<Branch>, RepoError> {
+        let rows = sqlx::query!(
+            "SELECT repo_id, name, head, updated FROM branches WHERE repo_id = $1 ORDER BY name",
+            repo
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| Branch {
+                repo: row.repo_id,
+                name: row.name,
+                head: CommitHash(row.head),
+                updated_at: row.updated,
+            })
+            .collect())
+    }
+
+    async fn find(&self, repo: Uuid, name: &str) -> Result<Option<Branch>, RepoError> {
+        let row = sqlx::query!(
+            "SELECT repo_id, name, head, updated FROM branches WHERE repo_id = $1 AND name = $2",
+            repo,
+            name
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(row.map(|row| Branch {
+            repo: row.repo_id,
+            name: row.name,
+            head: CommitHash(row.head),
+            updated_at: row.updated,
+        }))
+    }
+
+    async fn create(&self, tx: &mut Transaction, branch: &Branch) -> Result<bool, RepoError> {
+        let result = sqlx::query!(
+            "INSERT INTO branches (repo_id, name, head, created, updated) VALUES ($1, $2, $3, $4, $4)
+ON CONFLICT (repo_id, name) DO NOTHING",
+            branch.repo,
+            branch.name,
+            branch.head.0,
+            branch.updated_at
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn compare_and_swap(
+        &self,
+        tx: &mut Transaction,
+        branch: &Branch,
+        expected: &CommitHash,
+    ) -> Result<bool, RepoError> {
+        let result = sqlx::query!(
+            "UPDATE branches SET head = $3, updated = $4 WHERE repo_id = $1 AND name = $2 AND head = $5",
+            branch.repo,
+            branch.name,
+            branch.head.0,
+            branch.updated_at,
+            expected.0
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,221 @@
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::repository::{BroadcastMessage, MessageType, RepositoryEvent};
+use crate::models::user::MLSClientId;
+use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+
+#[derive(Clone, Debug)]
+pub struct BroadcastMessageRepository {
+    conn: PgPool,
+}
+
+pub trait BroadcastMessageRepositoryTrait {
+    fn create(&self, tx: &mut Transaction, message: &BroadcastMessage) -> impl Future<Output = Result<(), RepoError>>;
+    // The commit that was accepted for `epoch`, if any
+    fn find_commit(
+        &self,
+        tx: &mut Transaction,
+        repo: Uuid,
+        epoch: i64,
+    ) -> impl Future<Output = Result<Option<BroadcastMessage>, RepoError>>;
+    // Messages of the repository sent by other clients since `reader` joined that it has not
+    // acknowledged yet, in sequence order
+    fn find_unread(
+        &self,
+        repo: Uuid,
+        reader: &MLSClientId,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<BroadcastMessage>, RepoError>>;
+    // Events for the messages after sequence number `after` that `reader` can decrypt, i.e. those
+    // sent since it joined, in sequence order
+    fn find_events_after(
+        &self,
+        repo: Uuid,
+        reader: &MLSClientId,
+        after: i64,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<RepositoryEvent>, RepoError>>;
+    // Writes `reader`'s read receipts for those of `ids` in the repository. Returns how many were
+    // newly acknowledged.
+    fn acknowledge(
+        &self,
+        repo: Uuid,
+        reader: &MLSClientId,
+        ids: &[Uuid],
+        read_at: NaiveDateTime,
+    ) -> impl Future<Output = Result<u64, RepoError>>;
+}
+
+impl BroadcastMessageRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for BroadcastMessageRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+struct BroadcastMessageRow {
+    id: Uuid,
+    repo_id: Uuid,
+    sender_id: Uuid,
+    message_type: String,
+    mls_data: Vec<u8>,
+    sequence: i64,
+    epoch: i64,
+    created: NaiveDateTime,
+}
+
+impl TryFrom<BroadcastMessageRow> for BroadcastMessage {
+    type Error = RepoError;
+
+    fn try_from(row: BroadcastMessageRow) -> Result<Self, Self::Error> {
+        Ok(BroadcastMessage {
+            id: row.id,
+            repo: row.repo_id,
+            sender: MLSClientId(row.sender_id),
+            message_type: MessageType::from_string(&row.message_type)
+                .ok_or_else(|| RepoError::NotFound("Invalid message type".to_string()))?,
+            payload: row.mls_data,
+            sequence: row.sequence,
+            epoch: row.epoch,
+            created_at: row.created,
+        })
+    }
+}
+
+struct BroadcastEventRow {
+    id: Uuid,
+    repo_id: Uuid,
+    sender_id: Uuid,
+    message_type: String,
+    sequence: i64,
+    epoch: i64,
+}
+
+impl TryFrom<BroadcastEventRow> for RepositoryEvent {
+    type Error = RepoError;
+
+    fn try_from(row: BroadcastEventRow) -> Result<Self, Self::Error> {
+        Ok(RepositoryEvent::Broadcast {
+            repo: row.repo_id,
+            id: row.id,
+            sequence: row.sequence,
+            epoch: row.epoch,
+            sender: MLSClientId(row.sender_id),
+            message_type: MessageType::from_string(&row.message_type)
+                .ok_or_else(|| RepoError::NotFound("Invalid message type".to_string()))?,
+        })
+    }
+}
+
+impl BroadcastMessageRepositoryTrait for BroadcastMessageRepository {
+    async fn create(&self, tx: &mut Transaction, message: &BroadcastMessage) -> Result<(), RepoError> {
+        sqlx::query!(
+            "INSERT INTO broadcast_messages (id, mls_data, message_type, created, sender_id, repo_id, sequence, epoch)
+VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            message.id,
+            message.payload,
+            message.message_type.to_string(),
+            message.created_at,
+            message.sender.0,
+            message.repo,
+            message.sequence,
+            message.epoch
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find_commit(&self, tx: &mut Transaction, repo: Uuid, epoch: i64) -> Result<Option<BroadcastMessage>, RepoError> {
+        let row = sqlx::query_as!(
+            BroadcastMessageRow,
+            "SELECT id, repo_id, sender_id, message_type, mls_data, sequence, epoch, created FROM broadcast_messages
+WHERE repo_id = $1 AND epoch = $2 AND message_type = 'commit' AND deleted IS NULL",
+            repo,
+            epoch
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        row.map(BroadcastMessage::try_from).transpose()
+    }
+
+    async fn find_unread(&self, repo: Uuid, reader: &MLSClientId, limit: u32) -> Result<Vec<BroadcastMessage>, RepoError> {
+        let rows = sqlx::query_as!(
+            BroadcastMessageRow,
+            "SELECT broadcast_messages.id, broadcast_messages.repo_id, sender_id, message_type, mls_data, sequence, epoch, created
+FROM broadcast_messages
+JOIN client_repos ON client_repos.repo_id = broadcast_messages.repo_id AND client_repos.client_id = $2 AND client_repos.deleted IS NULL
+WHERE broadcast_messages.repo_id = $1 AND broadcast_messages.deleted IS NULL
+AND sender_id <> $2 AND created >= client_repos.joined
+AND NOT EXISTS (
+    SELECT 1 FROM broadcast_messages_read_receipts
+    WHERE broadcast_messages_read_receipts.message_id = broadcast_messages.id AND broadcast_messages_read_receipts.reader_id = $2
+)
+ORDER BY sequence LIMIT $3",
+            repo,
+            reader.0,
+            limit as i64
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rows.into_iter().map(BroadcastMessage::try_from).collect()
+    }
+
+    async fn find_events_after(
+        &self,
+        repo: Uuid,
+        reader: &MLSClientId,
+        after: i64,
+        limit: u32,
+    ) -> Result<Vec<RepositoryEvent>, RepoError> {
+        let rows = sqlx::query_as!(
+            BroadcastEventRow,
+            "SELECT broadcast_messages.id, broadcast_messages.repo_id, sender_id, message_type, sequence, epoch
+FROM broadcast_messages
+JOIN client_repos ON client_repos.repo_id = broadcast_messages.repo_id AND client_repos.client_id = $2 AND client_repos.deleted IS NULL
+WHERE broadcast_messages.repo_id = $1 AND broadcast_messages.deleted IS NULL
+AND sequence > $3 AND created >= client_repos.joined
+ORDER BY sequence LIMIT $4",
+            repo,
+            reader.0,
+            after,
+            limit as i64
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rows.into_iter().map(RepositoryEvent::try_from).collect()
+    }
+
+    async fn acknowledge(
+        &self,
+        repo: Uuid,
+        reader: &MLSClientId,
+        ids: &[Uuid],
+        read_at: NaiveDateTime,
+    ) -> Result<u64, RepoError> {
+        let result = sqlx::query!(
+            "INSERT INTO broadcast_messages_read_receipts (message_id, reader_id, read_at)
+SELECT id, $3, $4 FROM broadcast_messages WHERE id = ANY($1) AND repo_id = $2 AND deleted IS NULL
+ON CONFLICT (message_id, reader_id) DO NOTHING",
+            ids,
+            repo,
+            reader.0,
+            read_at
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,78 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::repository::{Commit, CommitHash};
+use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+
+#[derive(Clone, Debug)]
+pub struct CommitRepository {
+    conn: PgPool,
+}
+
+pub trait CommitRepositoryTrait {
+    // Those of `hashes` that are already commits of the repository
+    fn find_existing(
+        &self,
+        tx: &mut Transaction,
+        repo: Uuid,
+        hashes: &[CommitHash],
+    ) -> impl Future<Output = Result<Vec<CommitHash>, RepoError>>;
+    // `changes` and `message` are the broadcast messages carrying the commit's encrypted contents
+    fn create(
+        &self,
+        tx: &mut Transaction,
+        commit: &Commit,
+        changes: Uuid,
+        message: Uuid,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+}
+
+impl CommitRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for CommitRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+impl CommitRepositoryTrait for CommitRepository {
+    async fn find_existing(
+        &self,
+        tx: &mut Transaction,
+        repo: Uuid,
+        hashes: &[CommitHash],
+    ) -> Result<Vec<CommitHash>, RepoError> {
+        let hashes: Vec<String> = hashes.iter().map(|hash| hash.0.clone()).collect();
+        let rows = sqlx::query!(
+            "SELECT id FROM commits WHERE repo_id = $1 AND id = ANY($2)",
+            repo,
+            &hashes
+        )
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rows.into_iter().map(|row| CommitHash(row.id)).collect())
+    }
+
+    async fn create(&self, tx: &mut Transaction, commit: &Commit, changes: Uuid, message: Uuid) -> Result<(), RepoError> {
+        let parents: Vec<String> = commit.parents.iter().map(|parent| parent.0.clone()).collect();
+        sqlx::query!(
+            "INSERT INTO commits (id, changes, message, created, parents, author, repo_id) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            commit.hash.0,
+            changes,
+            message,
+            commit.created_at,
+            &parents,
+            commit.author.0,
+            commit.repo
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+}
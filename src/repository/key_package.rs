@@ -0,0 +1,215 @@
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::user::{KeyPackage, KeyPackageInventory, MLSClientId};
+use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+
+#[derive(Clone, Debug)]
+pub struct KeyPackageRepository {
+    conn: PgPool,
+}
+
+pub trait KeyPackageRepositoryTrait {
+    // One-time packages. A last resort package in `packages` replaces the client's previous one.
+    fn create_batch(
+        &self,
+        tx: &mut Transaction,
+        packages: &[KeyPackage],
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    // Consumes one unexpired one-time package of `client`, soonest to expire first, recording
+    // `claimed_by`. Falls back to the client's unexpired last resort package, which is not
+    // consumed. None if the client has neither.
+    fn claim(
+        &self,
+        tx: &mut Transaction,
+        client: &MLSClientId,
+        claimed_by: &MLSClientId,
+        now: NaiveDateTime,
+    ) -> impl Future<Output = Result<Option<KeyPackage>, RepoError>>;
+    // How many one-time packages of `client` were claimed since `since` by any client of the user
+    fn count_claims_by_user(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        client: &MLSClientId,
+        since: NaiveDateTime,
+    ) -> impl Future<Output = Result<i64, RepoError>>;
+    // Counts for each of the user's live clients, oldest client first
+    fn inventory_by_user(
+        &self,
+        uid: Uuid,
+        now: NaiveDateTime,
+    ) -> impl Future<Output = Result<Vec<KeyPackageInventory>, RepoError>>;
+    // Hard deletes expired and soft deleted packages, and those claimed before `claimed_before`.
+    // Returns how many were removed.
+    fn delete_stale(
+        &self,
+        now: NaiveDateTime,
+        claimed_before: NaiveDateTime,
+    ) -> impl Future<Output = Result<u64, RepoError>>;
+}
+
+impl KeyPackageRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for KeyPackageRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+struct KeyPackageRow {
+    id: Uuid,
+    client_id: Uuid,
+    key_package: Vec<u8>,
+    expiration_date: NaiveDateTime,
+    last_resort: bool,
+}
+
+impl From<KeyPackageRow> for KeyPackage {
+    fn from(row: KeyPackageRow) -> Self {
+        KeyPackage {
+            id: row.id,
+            mls_client_id: MLSClientId(row.client_id),
+            kpkg: row.key_package,
+            expires_at: row.expiration_date,
+            last_resort: row.last_resort,
+        }
+    }
+}
+
+impl KeyPackageRepositoryTrait for KeyPackageRepository {
+    async fn create_batch(&self, tx: &mut Transaction, packages: &[KeyPackage]) -> Result<(), RepoError> {
+        for client in packages.iter().filter(|package| package.last_resort).map(|package| package.mls_client_id.0) {
+            sqlx::query!(
+                "UPDATE key_packages SET deleted = timezone('utc', NOW()) WHERE client_id = $1 AND last_resort AND deleted IS NULL",
+                client
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(RepoError::from)?;
+        }
+        let ids: Vec<Uuid> = packages.iter().map(|package| package.id).collect();
+        let clients: Vec<Uuid> = packages.iter().map(|package| package.mls_client_id.0).collect();
+        let data: Vec<Vec<u8>> = packages.iter().map(|package| package.kpkg.clone()).collect();
+        let expires: Vec<NaiveDateTime> = packages.iter().map(|package| package.expires_at).collect();
+        let last_resort: Vec<bool> = packages.iter().map(|package| package.last_resort).collect();
+        sqlx::query!(
+            "INSERT INTO key_packages (id, client_id, key_package, expiration_date, last_resort, created)
+SELECT *, timezone('utc', NOW()) FROM UNNEST($1::uuid[], $2::uuid[], $3::bytea[], $4::timestamp[], $5::bool[])",
+            &ids,
+            &clients,
+            &data,
+            &expires,
+            &last_resort
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn claim(
+        &self,
+        tx: &mut Transaction,
+        client: &MLSClientId,
+        claimed_by: &MLSClientId,
+        now: NaiveDateTime,
+    ) -> Result<Option<KeyPackage>, RepoError> {
+        // SKIP LOCKED lets concurrent claims for the same client each take a different package
+        let claimed = sqlx::query_as!(
+            KeyPackageRow,
+            r#"UPDATE key_packages SET claimed = $3, claimed_by = $2
+WHERE id = (
+    SELECT id FROM key_packages
+    WHERE client_id = $1 AND deleted IS NULL AND claimed IS NULL AND NOT last_resort AND expiration_date > $3
+    ORDER BY expiration_date LIMIT 1 FOR UPDATE SKIP LOCKED
+)
+RETURNING id, client_id, key_package, expiration_date AS "expiration_date!", last_resort"#,
+            client.0,
+            claimed_by.0,
+            now
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        if claimed.is_some() {
+            return Ok(claimed.map(KeyPackage::from));
+        }
+        let last_resort = sqlx::query_as!(
+            KeyPackageRow,
+            r#"SELECT id, client_id, key_package, expiration_date AS "expiration_date!", last_resort FROM key_packages
+WHERE client_id = $1 AND deleted IS NULL AND last_resort AND expiration_date > $2"#,
+            client.0,
+            now
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(last_resort.map(KeyPackage::from))
+    }
+
+    async fn count_claims_by_user(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        client: &MLSClientId,
+        since: NaiveDateTime,
+    ) -> Result<i64, RepoError> {
+        let rec = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM key_packages
+JOIN mls_clients ON mls_clients.id = key_packages.claimed_by
+WHERE key_packages.client_id = $2 AND key_packages.claimed > $3 AND mls_clients.user_id = $1"#,
+            uid,
+            client.0,
+            since
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rec.count)
+    }
+
+    async fn inventory_by_user(&self, uid: Uuid, now: NaiveDateTime) -> Result<Vec<KeyPackageInventory>, RepoError> {
+        let rows = sqlx::query!(
+            r#"SELECT mls_clients.id,
+COUNT(key_packages.id) FILTER (WHERE NOT key_packages.last_resort) AS "available!",
+COALESCE(bool_or(key_packages.last_resort), FALSE) AS "has_last_resort!"
+FROM mls_clients
+LEFT JOIN key_packages ON key_packages.client_id = mls_clients.id AND key_packages.deleted IS NULL
+    AND key_packages.claimed IS NULL AND key_packages.expiration_date > $2
+WHERE mls_clients.user_id = $1 AND mls_clients.deleted IS NULL
+GROUP BY mls_clients.id ORDER BY mls_clients.created"#,
+            uid,
+            now
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rows
+            .into_iter()
+            .map(|row| KeyPackageInventory {
+                mls_client_id: MLSClientId(row.id),
+                available: row.available as u32,
+                has_last_resort: row.has_last_resort,
+            })
+            .collect())
+    }
+
+    async fn delete_stale(&self, now: NaiveDateTime, claimed_before: NaiveDateTime) -> Result<u64, RepoError> {
+        // packages without an expiry predate the directory and are never handed out
+        let result = sqlx::query!(
+            "DELETE FROM key_packages WHERE claimed <= $2 OR deleted IS NOT NULL OR expiration_date IS NULL OR expiration_date <= $1",
+            now,
+            claimed_before
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected())
+    }
+}
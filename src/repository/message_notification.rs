@@ -0,0 +1,54 @@
+use sqlx::PgPool;
+use sqlx::postgres::PgListener;
+use uuid::Uuid;
+use crate::models::repository::EventTopic;
+use crate::models::user::MLSClientId;
+use crate::repository::error::RepoError;
+
+// Channels the message triggers notify on, with the recipient client or repository id as payload
+const UNICAST_CHANNEL: &str = "unicast_messages";
+const BROADCAST_CHANNEL: &str = "broadcast_messages";
+
+#[derive(Clone, Debug)]
+pub struct MessageNotificationRepository {
+    conn: PgPool,
+}
+
+pub trait MessageNotificationRepositoryTrait {
+    // Starts listening for stored messages on a dedicated connection
+    fn listen(&self) -> impl Future<Output = Result<MessageListener, RepoError>>;
+}
+
+impl MessageNotificationRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl MessageNotificationRepositoryTrait for MessageNotificationRepository {
+    async fn listen(&self) -> Result<MessageListener, RepoError> {
+        let mut listener = PgListener::connect_with(&self.conn).await?;
+        listener.listen_all([UNICAST_CHANNEL, BROADCAST_CHANNEL]).await?;
+        Ok(MessageListener { listener })
+    }
+}
+
+pub struct MessageListener {
+    listener: PgListener,
+}
+
+impl MessageListener {
+    // The topic of the next stored message. Reconnects on its own, notifications sent while the
+    // connection was down are lost.
+    pub async fn recv(&mut self) -> Result<Option<EventTopic>, RepoError> {
+        let notification = self.listener.recv().await?;
+        let Ok(id) = Uuid::parse_str(notification.payload()) else {
+            return Ok(None);
+        };
+        Ok(match notification.channel() {
+            UNICAST_CHANNEL => Some(EventTopic::Client(MLSClientId(id))),
+            BROADCAST_CHANNEL => Some(EventTopic::Repository(id)),
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::user::{MLSClient, MLSClientId};
+use crate::repository::error::RepoError;
+
+#[derive(Clone, Debug)]
+pub struct MLSClientRepository {
+    conn: PgPool,
+}
+
+pub trait MLSClientRepositoryTrait {
+    fn create(&self, uid: Uuid, name: Option<&str>) -> impl Future<Output = Result<MLSClient, RepoError>>;
+    // The user's clients that have not been revoked, oldest first
+    fn list_by_user(&self, uid: Uuid) -> impl Future<Output = Result<Vec<MLSClient>, RepoError>>;
+    fn find(&self, id: &MLSClientId) -> impl Future<Output = Result<Option<MLSClient>, RepoError>>;
+    fn set_name(&self, id: &MLSClientId, name: Option<&str>) -> impl Future<Output = Result<(), RepoError>>;
+    // Soft deletes the client along with its repository grants and unclaimed key packages
+    fn revoke(&self, id: &MLSClientId) -> impl Future<Output = Result<(), RepoError>>;
+}
+
+impl MLSClientRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+struct MLSClientRow {
+    id: Uuid,
+    user_id: Option<Uuid>,
+    name: Option<String>,
+    created: chrono::NaiveDateTime,
+}
+
+impl From<MLSClientRow> for MLSClient {
+    fn from(row: MLSClientRow) -> Self {
+        MLSClient {
+            id: MLSClientId(row.id),
+            assoc_user: row.user_id,
+            name: row.name,
+            created: row.created,
+        }
+    }
+}
+
+impl MLSClientRepositoryTrait for MLSClientRepository {
+    async fn create(&self, uid: Uuid, name: Option<&str>) -> Result<MLSClient, RepoError> {
+        let row = sqlx::query_as!(
+            MLSClientRow,
+            "INSERT INTO mls_clients (id, user_id, name, created) VALUES ($1, $2, $3, timezone('utc', NOW())) RETURNING id, user_id, name, created",
+            Uuid::new_v4(),
+            uid,
+            name
+        )
+        .fetch_one(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(MLSClient::from(row))
+    }
+
+    async fn list_by_user(&self, uid: Uuid) -> Result<Vec<MLSClient>, RepoError> {
+        let rows = sqlx::query_as!(
+            MLSClientRow,
+            "SELECT id, user_id, name, created FROM mls_clients WHERE user_id = $1 AND deleted IS NULL ORDER BY created",
+            uid
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rows.into_iter().map(MLSClient::from).collect())
+    }
+
+    async fn find(&self, id: &MLSClientId) -> Result<Option<MLSClient>, RepoError> {
+        let row = sqlx::query_as!(
+            MLSClientRow,
+            "SELECT id, user_id, name, created FROM mls_clients WHERE id = $1 AND deleted IS NULL",
+            id.0
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(row.map(MLSClient::from))
+    }
+
+    async fn set_name(&self, id: &MLSClientId, name: Option<&str>) -> Result<(), RepoError> {
+        let result = sqlx::query!(
+            "UPDATE mls_clients SET name = $2 WHERE id = $1 AND deleted IS NULL",
+            id.0,
+            name
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound("Client not found".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn revoke(&self, id: &MLSClientId) -> Result<(), RepoError> {
+        let mut tx = self.conn.begin().await?;
+        let result = sqlx::query!(
+            "UPDATE mls_clients SET deleted = timezone('utc', NOW()) WHERE id = $1 AND deleted IS NULL",
+            id.0
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(RepoError::from)?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound("Client not found".to_string()));
+        }
+        sqlx::query!(
+            "UPDATE client_repos SET deleted = timezone('utc', NOW()) WHERE client_id = $1 AND deleted IS NULL",
+            id.0
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(RepoError::from)?;
+        sqlx::query!(
+            "UPDATE key_packages SET deleted = timezone('utc', NOW()) WHERE client_id = $1 AND deleted IS NULL",
+            id.0
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(RepoError::from)?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,18 @@
 pub mod payment_log;
+pub mod promo_code;
+pub mod stripe_customer;
+pub mod stripe_event;
 pub mod subscription;
 pub mod user;
 pub mod settings;
 pub mod error;
 pub mod repository;
+pub mod repo_transfer;
+pub mod mls_client;
+pub mod key_package;
+pub mod unicast_message;
+pub mod broadcast_message;
+pub mod message_notification;
+pub mod commit;
+pub mod branch;
+pub mod transaction;
@@ -1,5 +1,7 @@
 use super::error::RepoError;
+use super::transaction::Transaction;
 use crate::models::account::Payment;
+use crate::models::account::PaymentStatus;
 use uuid::Uuid;
 use sqlx::PgPool;
 
@@ -11,11 +13,27 @@ pub struct PaymentLogRepository {
 pub trait PaymentLogRepositoryTrait {
     fn create(
         &self,
+        tx: &mut Transaction,
         sub_info: Payment,
     ) -> impl Future<Output = Result<(), RepoError>>;
+    // Reads a payment and locks it until `tx` ends. None if no such payment was logged.
+    fn find_for_update(
+        &self,
+        tx: &mut Transaction,
+        payment_id: &str,
+    ) -> impl Future<Output = Result<Option<Payment>, RepoError>>;
+    fn set_status(
+        &self,
+        tx: &mut Transaction,
+        payment_id: &str,
+        status: PaymentStatus,
+        refunded_cents: u32,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    // The user's `limit` most recent payments, newest first
     fn find_by_user_id(
         &self,
         uid: Uuid,
+        limit: u32,
     ) -> impl Future<Output = Result<Vec<Payment>, RepoError>>;
 }
 
@@ -26,24 +44,79 @@ impl PaymentLogRepository {
 }
 
 impl PaymentLogRepositoryTrait for PaymentLogRepository {
-    async fn create(&self, payment_info: Payment) -> Result<(), RepoError> {
-        sqlx::query!("INSERT INTO payment_log (payment_id, user_id, amount_cents, payment_date) VALUES ($1, $2, $3, $4)", payment_info.payment_id, payment_info.user_id, payment_info.amount_cents as i32, payment_info.payment_date)
-            .execute(&self.conn)
+    async fn create(&self, tx: &mut Transaction, payment_info: Payment) -> Result<(), RepoError> {
+        sqlx::query!(
+            "INSERT INTO payment_log (payment_id, user_id, amount_cents, payment_date, months, status, refunded_cents) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            payment_info.payment_id,
+            payment_info.user_id,
+            payment_info.amount_cents as i32,
+            payment_info.payment_date,
+            payment_info.months as i32,
+            payment_info.status.to_string(),
+            payment_info.refunded_cents as i32
+        )
+            .execute(&mut **tx)
             .await.map_err(|e| RepoError::from(e))?;
         Ok(())
     }
 
-    async fn find_by_user_id(&self, uid: Uuid) -> Result<Vec<Payment>, RepoError> {
-        let record = sqlx::query!("SELECT * FROM payment_log WHERE user_id = $1", uid)
+    async fn find_for_update(&self, tx: &mut Transaction, payment_id: &str) -> Result<Option<Payment>, RepoError> {
+        let rec = sqlx::query!("SELECT * FROM payment_log WHERE payment_id = $1 FOR UPDATE", payment_id)
+            .fetch_optional(&mut **tx)
+            .await
+            .map_err(RepoError::from)?;
+        let Some(rec) = rec else {
+            return Ok(None);
+        };
+        Ok(Some(Payment {
+            amount_cents: rec.amount_cents as u32,
+            payment_id: rec.payment_id,
+            payment_date: rec.payment_date,
+            user_id: rec.user_id,
+            months: rec.months as u32,
+            status: PaymentStatus::from_string(&rec.status)
+                .ok_or_else(|| RepoError::NotFound("Invalid payment status".to_string()))?,
+            refunded_cents: rec.refunded_cents as u32,
+        }))
+    }
+
+    async fn set_status(
+        &self,
+        tx: &mut Transaction,
+        payment_id: &str,
+        status: PaymentStatus,
+        refunded_cents: u32,
+    ) -> Result<(), RepoError> {
+        sqlx::query!(
+            "UPDATE payment_log SET status = $2, refunded_cents = $3 WHERE payment_id = $1",
+            payment_id,
+            status.to_string(),
+            refunded_cents as i32
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find_by_user_id(&self, uid: Uuid, limit: u32) -> Result<Vec<Payment>, RepoError> {
+        let record = sqlx::query!(
+            "SELECT * FROM payment_log WHERE user_id = $1 ORDER BY payment_date DESC LIMIT $2",
+            uid,
+            limit as i64
+        )
             .fetch_all(&self.conn)
             .await.map_err(|e| RepoError::from(e))?;
-        let payments = record.into_iter().map(|rec| Payment {
+        record.into_iter().map(|rec| Ok(Payment {
             amount_cents: rec.amount_cents as u32,
             payment_id: rec.payment_id,
             payment_date: rec.payment_date,
             user_id: rec.user_id,
-        }).collect();
-        Ok(payments)
+            months: rec.months as u32,
+            status: PaymentStatus::from_string(&rec.status)
+                .ok_or_else(|| RepoError::NotFound("Invalid payment status".to_string()))?,
+            refunded_cents: rec.refunded_cents as u32,
+        })).collect()
     }
 }
 
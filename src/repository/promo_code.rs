@@ -0,0 +1,206 @@
+use super::error::RepoError;
+use super::transaction::{Transaction, TransactionalRepository};
+use crate::models::account::PromoCode;
+use crate::models::account::PromoReward;
+use crate::models::account::SubscriptionType;
+use chrono::NaiveDateTime;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct PromoCodeRepository {
+    conn: PgPool,
+}
+
+pub trait PromoCodeRepositoryTrait {
+    fn create(
+        &self,
+        promo_code: &PromoCode,
+        created_by: Uuid,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    fn find(&self, code: &str) -> impl Future<Output = Result<Option<PromoCode>, RepoError>>;
+    // Reads a code and locks it until `tx` ends, so it can be redeemed without exceeding its limit
+    fn find_for_update(
+        &self,
+        tx: &mut Transaction,
+        code: &str,
+    ) -> impl Future<Output = Result<Option<PromoCode>, RepoError>>;
+    fn has_redeemed(
+        &self,
+        code: &str,
+        uid: Uuid,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+    // Records that the user redeemed the code and counts it towards its limit. Returns false if
+    // the user had already redeemed it, or the code has reached its limit.
+    fn redeem(
+        &self,
+        tx: &mut Transaction,
+        code: &str,
+        uid: Uuid,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+    // Stores the Stripe coupon for a discount code unless another request stored one first.
+    // Returns whichever coupon id ends up stored.
+    fn set_stripe_coupon_id(
+        &self,
+        code: &str,
+        coupon_id: &str,
+    ) -> impl Future<Output = Result<String, RepoError>>;
+}
+
+impl PromoCodeRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for PromoCodeRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+struct PromoCodeRow {
+    code: String,
+    percent_off: Option<i32>,
+    free_months: Option<i32>,
+    plan_id: Option<String>,
+    max_redemptions: Option<i32>,
+    redemptions: i32,
+    expires: Option<NaiveDateTime>,
+    stripe_coupon_id: Option<String>,
+}
+
+impl TryFrom<PromoCodeRow> for PromoCode {
+    type Error = RepoError;
+
+    fn try_from(row: PromoCodeRow) -> Result<Self, RepoError> {
+        let reward = match (row.percent_off, row.free_months) {
+            (Some(percent), None) => PromoReward::PercentOff(percent as u32),
+            (None, Some(months)) => PromoReward::FreeMonths(months as u32),
+            _ => return Err(RepoError::QueryError("Promo code has no single reward".to_string())),
+        };
+        let subscription_type = match row.plan_id {
+            Some(plan_id) => Some(
+                SubscriptionType::from_string(&plan_id)
+                    .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?,
+            ),
+            None => None,
+        };
+        Ok(PromoCode {
+            code: row.code,
+            reward,
+            subscription_type,
+            max_redemptions: row.max_redemptions.map(|max| max as u32),
+            redemptions: row.redemptions as u32,
+            expires: row.expires,
+            stripe_coupon_id: row.stripe_coupon_id,
+        })
+    }
+}
+
+impl PromoCodeRepositoryTrait for PromoCodeRepository {
+    async fn create(&self, promo_code: &PromoCode, created_by: Uuid) -> Result<(), RepoError> {
+        let (percent_off, free_months) = match promo_code.reward {
+            PromoReward::PercentOff(percent) => (Some(percent as i32), None),
+            PromoReward::FreeMonths(months) => (None, Some(months as i32)),
+        };
+        sqlx::query!(
+            "INSERT INTO promo_codes (code, percent_off, free_months, plan_id, max_redemptions, expires, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            promo_code.code,
+            percent_off,
+            free_months,
+            promo_code.subscription_type.as_ref().map(|t| t.to_string()),
+            promo_code.max_redemptions.map(|max| max as i32),
+            promo_code.expires,
+            created_by
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find(&self, code: &str) -> Result<Option<PromoCode>, RepoError> {
+        let row = sqlx::query_as!(
+            PromoCodeRow,
+            "SELECT code, percent_off, free_months, plan_id, max_redemptions, redemptions, expires, stripe_coupon_id FROM promo_codes WHERE code = $1",
+            code
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        row.map(PromoCode::try_from).transpose()
+    }
+
+    async fn find_for_update(&self, tx: &mut Transaction, code: &str) -> Result<Option<PromoCode>, RepoError> {
+        let row = sqlx::query_as!(
+            PromoCodeRow,
+            "SELECT code, percent_off, free_months, plan_id, max_redemptions, redemptions, expires, stripe_coupon_id FROM promo_codes WHERE code = $1 FOR UPDATE",
+            code
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        row.map(PromoCode::try_from).transpose()
+    }
+
+    async fn has_redeemed(&self, code: &str, uid: Uuid) -> Result<bool, RepoError> {
+        let rec = sqlx::query!(
+            "SELECT EXISTS (SELECT 1 FROM promo_redemptions WHERE code = $1 AND user_id = $2) AS redeemed",
+            code,
+            uid
+        )
+        .fetch_one(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rec.redeemed.unwrap_or(false))
+    }
+
+    async fn redeem(&self, tx: &mut Transaction, code: &str, uid: Uuid) -> Result<bool, RepoError> {
+        // Counting first takes the row lock, so concurrent redemptions cannot both pass the limit
+        let counted = sqlx::query!(
+            "UPDATE promo_codes SET redemptions = redemptions + 1 WHERE code = $1 AND (max_redemptions IS NULL OR redemptions < max_redemptions)",
+            code
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .rows_affected();
+        if counted == 0 {
+            return Ok(false);
+        }
+        let inserted = sqlx::query!(
+            "INSERT INTO promo_redemptions (code, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            code,
+            uid
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .rows_affected();
+        if inserted == 0 {
+            sqlx::query!(
+                "UPDATE promo_codes SET redemptions = redemptions - 1 WHERE code = $1",
+                code
+            )
+            .execute(&mut **tx)
+            .await
+            .map_err(RepoError::from)?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    async fn set_stripe_coupon_id(&self, code: &str, coupon_id: &str) -> Result<String, RepoError> {
+        let rec = sqlx::query!(
+            "UPDATE promo_codes SET stripe_coupon_id = COALESCE(stripe_coupon_id, $2) WHERE code = $1 RETURNING stripe_coupon_id",
+            code,
+            coupon_id
+        )
+        .fetch_one(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rec.stripe_coupon_id
+            .ok_or_else(|| RepoError::QueryError("Stripe coupon id was not stored".to_string()))
+    }
+}
@@ -0,0 +1,130 @@
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::repository::RepositoryTransfer;
+use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
+
+#[derive(Clone, Debug)]
+pub struct RepoTransferRepository {
+    conn: PgPool,
+}
+
+pub trait RepoTransferRepositoryTrait {
+    // DuplicateEntry if the repository already has a pending transfer
+    fn create(
+        &self,
+        tx: &mut Transaction,
+        repo_id: Uuid,
+        from_user: Uuid,
+        to_user: Uuid,
+    ) -> impl Future<Output = Result<Uuid, RepoError>>;
+    // Pending transfers the user is sending or receiving, oldest first
+    fn list_pending(&self, uid: Uuid) -> impl Future<Output = Result<Vec<RepositoryTransfer>, RepoError>>;
+    fn find_pending_for_update(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+    ) -> impl Future<Output = Result<Option<RepositoryTransfer>, RepoError>>;
+    fn set_accepted(&self, tx: &mut Transaction, id: Uuid) -> impl Future<Output = Result<(), RepoError>>;
+    fn set_cancelled(&self, tx: &mut Transaction, id: Uuid) -> impl Future<Output = Result<(), RepoError>>;
+}
+
+impl RepoTransferRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for RepoTransferRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+impl RepoTransferRepositoryTrait for RepoTransferRepository {
+    async fn create(
+        &self,
+        tx: &mut Transaction,
+        repo_id: Uuid,
+        from_user: Uuid,
+        to_user: Uuid,
+    ) -> Result<Uuid, RepoError> {
+        let id = Uuid::new_v4();
+        sqlx::query!(
+            "INSERT INTO repo_transfers (id, repo_id, from_user, to_user, created) VALUES ($1, $2, $3, $4, timezone('utc', NOW()))",
+            id,
+            repo_id,
+            from_user,
+            to_user
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(id)
+    }
+
+    async fn list_pending(&self, uid: Uuid) -> Result<Vec<RepositoryTransfer>, RepoError> {
+        sqlx::query_as!(
+            RepositoryTransfer,
+            r#"SELECT repo_transfers.id, repo_transfers.repo_id, repos.name AS repo_name,
+repo_transfers.from_user, COALESCE(senders.username, senders.id::text) AS "from_name!",
+repo_transfers.to_user, COALESCE(recipients.username, recipients.id::text) AS "to_name!",
+repo_transfers.created
+FROM repo_transfers JOIN repos ON repos.id = repo_transfers.repo_id
+JOIN users senders ON senders.id = repo_transfers.from_user
+JOIN users recipients ON recipients.id = repo_transfers.to_user
+WHERE (repo_transfers.from_user = $1 OR repo_transfers.to_user = $1)
+AND repo_transfers.accepted IS NULL AND repo_transfers.cancelled IS NULL AND repos.deleted IS NULL
+ORDER BY repo_transfers.created"#,
+            uid
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)
+    }
+
+    async fn find_pending_for_update(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+    ) -> Result<Option<RepositoryTransfer>, RepoError> {
+        sqlx::query_as!(
+            RepositoryTransfer,
+            r#"SELECT repo_transfers.id, repo_transfers.repo_id, repos.name AS repo_name,
+repo_transfers.from_user, COALESCE(senders.username, senders.id::text) AS "from_name!",
+repo_transfers.to_user, COALESCE(recipients.username, recipients.id::text) AS "to_name!",
+repo_transfers.created
+FROM repo_transfers JOIN repos ON repos.id = repo_transfers.repo_id
+JOIN users senders ON senders.id = repo_transfers.from_user
+JOIN users recipients ON recipients.id = repo_transfers.to_user
+WHERE repo_transfers.id = $1 AND repo_transfers.accepted IS NULL AND repo_transfers.cancelled IS NULL
+FOR UPDATE OF repo_transfers"#,
+            id
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)
+    }
+
+    async fn set_accepted(&self, tx: &mut Transaction, id: Uuid) -> Result<(), RepoError> {
+        sqlx::query!(
+            "UPDATE repo_transfers SET accepted = timezone('utc', NOW()) WHERE id = $1",
+            id
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn set_cancelled(&self, tx: &mut Transaction, id: Uuid) -> Result<(), RepoError> {
+        sqlx::query!(
+            "UPDATE repo_transfers SET cancelled = timezone('utc', NOW()) WHERE id = $1",
+            id
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+}
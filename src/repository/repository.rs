@@ -1,6 +1,10 @@
+use chrono::NaiveDateTime;
 use uuid::Uuid;
 use sqlx::PgPool;
+use crate::models::repository::{Repository, RepositoryAuditAction, RepositoryPermission};
+use crate::models::user::MLSClientId;
 use crate::repository::error::RepoError;
+use crate::repository::transaction::{Transaction, TransactionalRepository};
 
 #[derive(Clone, Debug)]
 pub struct RepoRepository {
@@ -8,7 +12,72 @@ pub struct RepoRepository {
 }
 
 pub trait RepoRepositoryTrait {
-    fn create(&self, owner_id: Uuid, name: String, owner_name: String) -> impl Future<Output = Result<(), RepoError>>;
+    // `founder`, if any, becomes the first member with admin permission
+    fn create(
+        &self,
+        owner_id: Uuid,
+        name: &str,
+        founder: Option<&MLSClientId>,
+    ) -> impl Future<Output = Result<Repository, RepoError>>;
+    // Repositories the user owns that have not been deleted, by name
+    fn list_by_owner(&self, owner_id: Uuid) -> impl Future<Output = Result<Vec<Repository>, RepoError>>;
+    fn find_by_id(&self, id: Uuid) -> impl Future<Output = Result<Option<Repository>, RepoError>>;
+    // Resolves an "owner/name" slug. `owner` is matched against the owner's username, ignoring
+    // case, or against their id for owners without a username.
+    fn find_by_slug(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<Repository>, RepoError>>;
+    // Whether the client currently has a grant on the repository
+    fn is_member(&self, id: Uuid, client: &MLSClientId) -> impl Future<Output = Result<bool, RepoError>>;
+    // The client's permission level in the repository, None if it is not a member
+    fn find_permission(
+        &self,
+        id: Uuid,
+        client: &MLSClientId,
+    ) -> impl Future<Output = Result<Option<RepositoryPermission>, RepoError>>;
+    fn rename(&self, id: Uuid, new_name: &str) -> impl Future<Output = Result<(), RepoError>>;
+    // Soft delete, the row is kept with `deleted` set
+    fn delete(&self, id: Uuid) -> impl Future<Output = Result<(), RepoError>>;
+    // Hands the repository from `from_user` to `to_user` and keeps the old "owner/name" slug
+    // resolving until `redirect_until`. Member grants are keyed by repo id so they carry over.
+    // NotFound if `from_user` no longer owns it, DuplicateEntry if `to_user` has a repository
+    // with the same name.
+    fn transfer_owner(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+        from_user: Uuid,
+        to_user: Uuid,
+        redirect_until: NaiveDateTime,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    // The live repository an old slug redirects to, if the redirect has not expired
+    fn find_redirect(
+        &self,
+        owner: &str,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<Repository>, RepoError>>;
+    fn record_audit(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+        actor: Uuid,
+        action: RepositoryAuditAction,
+        detail: &str,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    // Claims the repository's next `count` broadcast sequence numbers and returns the first with the
+    // group's current epoch. The repository row stays locked until `tx` ends, so sends are
+    // serialised.
+    fn next_message_sequence(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+        count: i64,
+    ) -> impl Future<Output = Result<(i64, i64), RepoError>>;
+    fn set_epoch(&self, tx: &mut Transaction, id: Uuid, epoch: i64) -> impl Future<Output = Result<(), RepoError>>;
+    // The last broadcast sequence number claimed in the repository, 0 before its first message
+    fn find_message_sequence(&self, id: Uuid) -> impl Future<Output = Result<i64, RepoError>>;
 }
 
 impl RepoRepository {
@@ -19,17 +88,290 @@ impl RepoRepository {
     }
 }
 
+impl TransactionalRepository for RepoRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+// Columns of `repos` joined with its owner and live members, see `Repository`
+struct RepositoryRow {
+    id: Uuid,
+    owner: Uuid,
+    owner_name: String,
+    name: String,
+    members: Vec<Uuid>,
+}
+
+impl From<RepositoryRow> for Repository {
+    fn from(row: RepositoryRow) -> Self {
+        Repository {
+            id: row.id,
+            owner_id: row.owner,
+            owner_name: row.owner_name,
+            name: row.name,
+            members: row.members.into_iter().map(MLSClientId).collect(),
+        }
+    }
+}
+
 impl RepoRepositoryTrait for RepoRepository {
-    async fn create(&self, owner_id: Uuid, name: String, owner_name: String) -> Result<(), RepoError> {
-        let tx = self.conn.begin().await?;
-        let id = sqlx::query!(
-            "INSERT INTO repos (id, owner, name) VALUES ($1, $2, $3);",
-            owner_name + "/" + &name,
+    async fn create(&self, owner_id: Uuid, name: &str, founder: Option<&MLSClientId>) -> Result<Repository, RepoError> {
+        let id = Uuid::new_v4();
+        let mut tx = self.conn.begin().await?;
+        let owner_name = sqlx::query!(
+            r#"WITH created AS (INSERT INTO repos (id, owner, name) VALUES ($1, $2, $3) RETURNING owner)
+SELECT COALESCE(users.username, users.id::text) AS "owner_name!" FROM created JOIN users ON users.id = created.owner"#,
+            id,
             owner_id,
             name,
-        ).execute(&self.conn)
-        .await.map_err(|e: sqlx::Error| RepoError::from(e))?;
+        )
+        .fetch_one(&mut *tx)
+        .await
+        .map_err(RepoError::from)?
+        .owner_name;
+        if let Some(founder) = founder {
+            sqlx::query!(
+                "INSERT INTO client_repos (client_id, repo_id, permission_level, joined) VALUES ($1, $2, 'admin', timezone('utc', NOW()))",
+                founder.0,
+                id
+            )
+            .execute(&mut *tx)
+            .await
+            .map_err(RepoError::from)?;
+        }
         tx.commit().await?;
+        Ok(Repository {
+            id,
+            owner_id,
+            owner_name,
+            name: name.to_string(),
+            members: founder.into_iter().cloned().collect(),
+        })
+    }
+
+    async fn is_member(&self, id: Uuid, client: &MLSClientId) -> Result<bool, RepoError> {
+        let rec = sqlx::query!(
+            r#"SELECT EXISTS (
+    SELECT 1 FROM client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id
+    WHERE client_repos.repo_id = $1 AND client_repos.client_id = $2
+    AND client_repos.deleted IS NULL AND mls_clients.deleted IS NULL
+) AS "member!""#,
+            id,
+            client.0
+        )
+        .fetch_one(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rec.member)
+    }
+
+    async fn find_permission(&self, id: Uuid, client: &MLSClientId) -> Result<Option<RepositoryPermission>, RepoError> {
+        let rec = sqlx::query!(
+            "SELECT permission_level FROM client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id
+WHERE client_repos.repo_id = $1 AND client_repos.client_id = $2
+AND client_repos.deleted IS NULL AND mls_clients.deleted IS NULL",
+            id,
+            client.0
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rec.map(|rec| {
+            RepositoryPermission::from_string(&rec.permission_level)
+                .ok_or_else(|| RepoError::NotFound("Invalid permission level".to_string()))
+        })
+        .transpose()
+    }
+
+    async fn list_by_owner(&self, owner_id: Uuid) -> Result<Vec<Repository>, RepoError> {
+        let rows = sqlx::query_as!(
+            RepositoryRow,
+            r#"SELECT repos.id, repos.owner, COALESCE(users.username, users.id::text) AS "owner_name!", repos.name,
+COALESCE(array_agg(client_repos.client_id) FILTER (WHERE client_repos.client_id IS NOT NULL AND client_repos.deleted IS NULL), '{}') AS "members!"
+FROM repos JOIN users ON users.id = repos.owner
+LEFT JOIN (client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id AND mls_clients.deleted IS NULL)
+    ON client_repos.repo_id = repos.id
+WHERE repos.owner = $1 AND repos.deleted IS NULL
+GROUP BY repos.id, users.id ORDER BY repos.name"#,
+            owner_id
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(rows.into_iter().map(Repository::from).collect())
+    }
+
+    async fn find_by_id(&self, id: Uuid) -> Result<Option<Repository>, RepoError> {
+        let row = sqlx::query_as!(
+            RepositoryRow,
+            r#"SELECT repos.id, repos.owner, COALESCE(users.username, users.id::text) AS "owner_name!", repos.name,
+COALESCE(array_agg(client_repos.client_id) FILTER (WHERE client_repos.client_id IS NOT NULL AND client_repos.deleted IS NULL), '{}') AS "members!"
+FROM repos JOIN users ON users.id = repos.owner
+LEFT JOIN (client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id AND mls_clients.deleted IS NULL)
+    ON client_repos.repo_id = repos.id
+WHERE repos.id = $1 AND repos.deleted IS NULL
+GROUP BY repos.id, users.id"#,
+            id
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(row.map(Repository::from))
+    }
+
+    async fn find_by_slug(&self, owner: &str, name: &str) -> Result<Option<Repository>, RepoError> {
+        let row = sqlx::query_as!(
+            RepositoryRow,
+            r#"SELECT repos.id, repos.owner, COALESCE(users.username, users.id::text) AS "owner_name!", repos.name,
+COALESCE(array_agg(client_repos.client_id) FILTER (WHERE client_repos.client_id IS NOT NULL AND client_repos.deleted IS NULL), '{}') AS "members!"
+FROM repos JOIN users ON users.id = repos.owner
+LEFT JOIN (client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id AND mls_clients.deleted IS NULL)
+    ON client_repos.repo_id = repos.id
+WHERE (lower(users.username) = lower($1) OR (users.username IS NULL AND users.id::text = $1))
+AND repos.name = $2 AND repos.deleted IS NULL
+GROUP BY repos.id, users.id"#,
+            owner,
+            name
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(row.map(Repository::from))
+    }
+
+    async fn rename(&self, id: Uuid, new_name: &str) -> Result<(), RepoError> {
+        let result = sqlx::query!(
+            "UPDATE repos SET name = $2 WHERE id = $1 AND deleted IS NULL",
+            id,
+            new_name
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound("Repository not found".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn delete(&self, id: Uuid) -> Result<(), RepoError> {
+        let result = sqlx::query!(
+            "UPDATE repos SET deleted = timezone('utc', NOW()) WHERE id = $1 AND deleted IS NULL",
+            id
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound("Repository not found".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn transfer_owner(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+        from_user: Uuid,
+        to_user: Uuid,
+        redirect_until: NaiveDateTime,
+    ) -> Result<(), RepoError> {
+        let previous = sqlx::query!(
+            r#"UPDATE repos SET owner = $3 FROM users
+WHERE repos.id = $1 AND repos.owner = $2 AND repos.deleted IS NULL AND users.id = $2
+RETURNING repos.name, COALESCE(users.username, users.id::text) AS "owner_name!""#,
+            id,
+            from_user,
+            to_user
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .ok_or_else(|| RepoError::NotFound("Repository not found".to_string()))?;
+        sqlx::query!(
+            "INSERT INTO repo_redirects (owner_name, name, repo_id, expires) VALUES (lower($1), $2, $3, $4)
+ON CONFLICT (owner_name, name) DO UPDATE SET repo_id = EXCLUDED.repo_id, expires = EXCLUDED.expires",
+            previous.owner_name,
+            previous.name,
+            id,
+            redirect_until
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
         Ok(())
     }
+
+    async fn find_redirect(&self, owner: &str, name: &str) -> Result<Option<Repository>, RepoError> {
+        let row = sqlx::query_as!(
+            RepositoryRow,
+            r#"SELECT repos.id, repos.owner, COALESCE(users.username, users.id::text) AS "owner_name!", repos.name,
+COALESCE(array_agg(client_repos.client_id) FILTER (WHERE client_repos.client_id IS NOT NULL AND client_repos.deleted IS NULL), '{}') AS "members!"
+FROM repo_redirects JOIN repos ON repos.id = repo_redirects.repo_id JOIN users ON users.id = repos.owner
+LEFT JOIN (client_repos JOIN mls_clients ON mls_clients.id = client_repos.client_id AND mls_clients.deleted IS NULL)
+    ON client_repos.repo_id = repos.id
+WHERE repo_redirects.owner_name = lower($1) AND repo_redirects.name = $2
+AND repo_redirects.expires > timezone('utc', NOW()) AND repos.deleted IS NULL
+GROUP BY repos.id, users.id"#,
+            owner,
+            name
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(row.map(Repository::from))
+    }
+
+    async fn record_audit(
+        &self,
+        tx: &mut Transaction,
+        id: Uuid,
+        actor: Uuid,
+        action: RepositoryAuditAction,
+        detail: &str,
+    ) -> Result<(), RepoError> {
+        sqlx::query!(
+            "INSERT INTO repo_audit_log (repo_id, actor, action, detail) VALUES ($1, $2, $3, $4)",
+            id,
+            actor,
+            action.to_string(),
+            detail
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn next_message_sequence(&self, tx: &mut Transaction, id: Uuid, count: i64) -> Result<(i64, i64), RepoError> {
+        let rec = sqlx::query!(
+            r#"UPDATE repos SET message_sequence = message_sequence + $2 WHERE id = $1 AND deleted IS NULL
+RETURNING message_sequence - $2 + 1 AS "first!", epoch"#,
+            id,
+            count
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .ok_or_else(|| RepoError::NotFound("Repository not found".to_string()))?;
+        Ok((rec.first, rec.epoch))
+    }
+
+    async fn set_epoch(&self, tx: &mut Transaction, id: Uuid, epoch: i64) -> Result<(), RepoError> {
+        sqlx::query!("UPDATE repos SET epoch = $2 WHERE id = $1", id, epoch)
+            .execute(&mut **tx)
+            .await
+            .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find_message_sequence(&self, id: Uuid) -> Result<i64, RepoError> {
+        let rec = sqlx::query!("SELECT message_sequence FROM repos WHERE id = $1 AND deleted IS NULL", id)
+            .fetch_optional(&self.conn)
+            .await
+            .map_err(RepoError::from)?
+            .ok_or_else(|| RepoError::NotFound("Repository not found".to_string()))?;
+        Ok(rec.message_sequence)
+    }
 }
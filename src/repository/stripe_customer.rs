@@ -0,0 +1,119 @@
+use super::error::RepoError;
+use super::transaction::Transaction;
+use crate::models::account::StripeCustomer;
+use crate::models::account::SubscriptionType;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Clone, Debug)]
+pub struct StripeCustomerRepository {
+    conn: PgPool,
+}
+
+pub trait StripeCustomerRepositoryTrait {
+    fn create(
+        &self,
+        uid: Uuid,
+        customer_id: &str,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    fn find_by_user_id(
+        &self,
+        uid: Uuid,
+    ) -> impl Future<Output = Result<Option<StripeCustomer>, RepoError>>;
+    fn find_by_customer_id(
+        &self,
+        tx: &mut Transaction,
+        customer_id: &str,
+    ) -> impl Future<Output = Result<Option<StripeCustomer>, RepoError>>;
+    fn set_subscription(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        subscription_id: &str,
+        subscription_type: SubscriptionType,
+        status: &str,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+}
+
+impl StripeCustomerRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl StripeCustomerRepositoryTrait for StripeCustomerRepository {
+    async fn create(&self, user_id: Uuid, customer_id: &str) -> Result<(), RepoError> {
+        sqlx::query!(
+            "INSERT INTO stripe_customers (user_id, customer_id) VALUES ($1, $2)",
+            user_id,
+            customer_id
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find_by_user_id(&self, uid: Uuid) -> Result<Option<StripeCustomer>, RepoError> {
+        let record = sqlx::query!(
+            "SELECT user_id, customer_id, subscription_id, subscription_status, plan_id FROM stripe_customers WHERE user_id = $1",
+            uid
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(record.map(|rec| StripeCustomer {
+            user_id: rec.user_id,
+            customer_id: rec.customer_id,
+            subscription_id: rec.subscription_id,
+            subscription_status: rec.subscription_status,
+            subscription_type: rec.plan_id.as_deref().and_then(SubscriptionType::from_string),
+        }))
+    }
+
+    async fn find_by_customer_id(
+        &self,
+        tx: &mut Transaction,
+        customer_id: &str,
+    ) -> Result<Option<StripeCustomer>, RepoError> {
+        let record = sqlx::query!(
+            "SELECT user_id, customer_id, subscription_id, subscription_status, plan_id FROM stripe_customers WHERE customer_id = $1",
+            customer_id
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(record.map(|rec| StripeCustomer {
+            user_id: rec.user_id,
+            customer_id: rec.customer_id,
+            subscription_id: rec.subscription_id,
+            subscription_status: rec.subscription_status,
+            subscription_type: rec.plan_id.as_deref().and_then(SubscriptionType::from_string),
+        }))
+    }
+
+    async fn set_subscription(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        subscription_id: &str,
+        subscription_type: SubscriptionType,
+        status: &str,
+    ) -> Result<(), RepoError> {
+        let updated = sqlx::query!(
+            "UPDATE stripe_customers SET subscription_id = $2, plan_id = $3, subscription_status = $4 WHERE user_id = $1",
+            user_id,
+            subscription_id,
+            subscription_type.to_string(),
+            status
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .rows_affected();
+        if updated == 0 {
+            return Err(RepoError::NotFound("Stripe customer not found".to_string()));
+        }
+        Ok(())
+    }
+}
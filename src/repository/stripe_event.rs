@@ -0,0 +1,46 @@
+use super::error::RepoError;
+use super::transaction::{Transaction, TransactionalRepository};
+use sqlx::PgPool;
+
+#[derive(Clone, Debug)]
+pub struct StripeEventRepository {
+    conn: PgPool,
+}
+
+pub trait StripeEventRepositoryTrait {
+    // Records that an event has been processed. Returns false if it was already recorded, in
+    // which case the event must not be applied again.
+    fn record(
+        &self,
+        tx: &mut Transaction,
+        event_id: &str,
+        event_type: &str,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+}
+
+impl StripeEventRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+impl TransactionalRepository for StripeEventRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
+impl StripeEventRepositoryTrait for StripeEventRepository {
+    async fn record(&self, tx: &mut Transaction, event_id: &str, event_type: &str) -> Result<bool, RepoError> {
+        let inserted = sqlx::query!(
+            "INSERT INTO processed_stripe_events (event_id, event_type) VALUES ($1, $2) ON CONFLICT (event_id) DO NOTHING",
+            event_id,
+            event_type
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .rows_affected();
+        Ok(inserted == 1)
+    }
+}
@@ -1,11 +1,16 @@
+use crate::models::account::ExpiryNotice;
 use crate::models::account::SubscriptionType;
 use crate::models::account::SubscriptionInfo;
+use crate::models::account::SubscriptionPlan;
 use crate::repository::error::RepoError;
+use crate::repository::transaction::Transaction;
+use crate::repository::transaction::TransactionalRepository;
 use crate::models::account::AutoCommitBehaviour;
 use crate::models::account::AutoPullBehaviour;
 use crate::models::account::AutoPushBehaviour;
 use crate::models::account::CommandStyle;
 use crate::models::account::Settings;
+use chrono::NaiveDateTime;
 use sqlx::PgPool;
 use sqlx::postgres::types::PgInterval;
 use uuid::Uuid;
@@ -30,6 +35,72 @@ pub trait SubscriptionRepositoryTrait {
         &self,
         uid: Uuid,
     ) -> impl Future<Output = Result<SubscriptionInfo, RepoError>>;
+    fn find_plan(
+        &self,
+        subscription_type: SubscriptionType,
+    ) -> impl Future<Output = Result<SubscriptionPlan, RepoError>>;
+    // Stores the recurring Stripe price for a plan unless another request stored one first.
+    // Returns whichever price id ends up stored.
+    fn set_stripe_price_id(
+        &self,
+        subscription_type: SubscriptionType,
+        price_id: &str,
+    ) -> impl Future<Output = Result<String, RepoError>>;
+    // Adds `months` to the user's plan, starting from now if it has already lapsed. Creates the
+    // credit row if the user has never paid before. Remaining time on a different plan has to be
+    // converted first, it is kept as is.
+    fn extend(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        subscription_type: SubscriptionType,
+        months: u32,
+    ) -> impl Future<Output = Result<SubscriptionInfo, RepoError>>;
+    // Reads the user's plan and locks the row until `tx` ends, so the plan can be rewritten
+    // based on what was read.
+    fn find_by_user_id_for_update(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+    ) -> impl Future<Output = Result<SubscriptionInfo, RepoError>>;
+    fn set_plan(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        sub_info: &SubscriptionInfo,
+    ) -> impl Future<Output = Result<(), RepoError>>;
+    // Takes `fraction` of `months` back off the user's plan, e.g. after a refund. None if the user
+    // has no plan.
+    fn shorten(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        months: u32,
+        fraction: f64,
+    ) -> impl Future<Output = Result<Option<SubscriptionInfo>, RepoError>>;
+    // Plans that are still active but lapse before `until` and whose owner was not reminded of the
+    // current `paid_until` yet. Auto-renewing subscribers are left out, they renew by themselves.
+    fn find_expiring(
+        &self,
+        until: NaiveDateTime,
+    ) -> impl Future<Output = Result<Vec<ExpiryNotice>, RepoError>>;
+    // Records that the user is being reminded about `paid_until`. Returns false if someone else
+    // already claimed that reminder.
+    fn claim_expiry_notice(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        paid_until: NaiveDateTime,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+    // Moves the user's plan forward to `until`. Never shortens time that was already paid for.
+    // Like `extend`, it expects remaining time on a different plan to be converted first.
+    fn extend_until(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        subscription_type: SubscriptionType,
+        until: NaiveDateTime,
+    ) -> impl Future<Output = Result<SubscriptionInfo, RepoError>>;
 }
 
 impl SubscriptionRepository {
@@ -38,6 +109,12 @@ impl SubscriptionRepository {
     }
 }
 
+impl TransactionalRepository for SubscriptionRepository {
+    async fn begin(&self) -> Result<Transaction, RepoError> {
+        Ok(self.conn.begin().await?)
+    }
+}
+
 impl SubscriptionRepositoryTrait for SubscriptionRepository {
     async fn create(&self, user_id: Uuid, sub_info: SubscriptionInfo) -> Result<(), RepoError> {
         sqlx::query!("INSERT INTO credit (user_id, plan_id, paid_until) VALUES ($1, $2, $3)", user_id, sub_info.subscription_type.to_string(), sub_info.paid_until)
@@ -69,4 +146,213 @@ impl SubscriptionRepositoryTrait for SubscriptionRepository {
             return Err(RepoError::NotFound("Settings not found".to_string()));
         }
     }
+
+    async fn find_plan(&self, subscription_type: SubscriptionType) -> Result<SubscriptionPlan, RepoError> {
+        let rec = sqlx::query!(
+            "SELECT description, price_cents_per_month, stripe_price_id FROM subscription_plans WHERE id = $1",
+            subscription_type.to_string()
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)?
+        .ok_or_else(|| RepoError::NotFound("Subscription plan not found".to_string()))?;
+        Ok(SubscriptionPlan {
+            subscription_type,
+            description: rec.description,
+            price_cents_per_month: rec.price_cents_per_month as u32,
+            stripe_price_id: rec.stripe_price_id,
+        })
+    }
+
+    async fn set_stripe_price_id(
+        &self,
+        subscription_type: SubscriptionType,
+        price_id: &str,
+    ) -> Result<String, RepoError> {
+        let rec = sqlx::query!(
+            "UPDATE subscription_plans SET stripe_price_id = COALESCE(stripe_price_id, $2) WHERE id = $1 RETURNING stripe_price_id",
+            subscription_type.to_string(),
+            price_id
+        )
+        .fetch_one(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rec.stripe_price_id
+            .ok_or_else(|| RepoError::QueryError("Stripe price id was not stored".to_string()))
+    }
+
+    async fn find_by_user_id_for_update(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+    ) -> Result<SubscriptionInfo, RepoError> {
+        let rec = sqlx::query!(
+            "SELECT plan_id, paid_until FROM credit WHERE user_id = $1 FOR UPDATE",
+            user_id
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?
+        .ok_or_else(|| RepoError::NotFound("Subscription not found".to_string()))?;
+        let subscription_type = SubscriptionType::from_string(&rec.plan_id)
+            .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?;
+        Ok(SubscriptionInfo {
+            paid_until: rec.paid_until,
+            subscription_type,
+        })
+    }
+
+    async fn set_plan(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        sub_info: &SubscriptionInfo,
+    ) -> Result<(), RepoError> {
+        let result = sqlx::query!(
+            "UPDATE credit SET plan_id = $2, paid_until = $3 WHERE user_id = $1",
+            user_id,
+            sub_info.subscription_type.to_string(),
+            sub_info.paid_until
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        if result.rows_affected() == 0 {
+            return Err(RepoError::NotFound("Subscription not found".to_string()));
+        }
+        Ok(())
+    }
+
+    async fn extend(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        subscription_type: SubscriptionType,
+        months: u32,
+    ) -> Result<SubscriptionInfo, RepoError> {
+        let rec = sqlx::query!(
+            "INSERT INTO credit (user_id, plan_id, paid_until)
+VALUES ($1, $2, timezone('utc', NOW()) + make_interval(months => $3))
+ON CONFLICT (user_id) DO UPDATE SET plan_id = EXCLUDED.plan_id, paid_until = GREATEST(credit.paid_until, timezone('utc', NOW())) + make_interval(months => $3)
+RETURNING plan_id, paid_until",
+            user_id,
+            subscription_type.to_string(),
+            months as i32
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        let subscription_type = SubscriptionType::from_string(&rec.plan_id)
+            .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?;
+        Ok(SubscriptionInfo {
+            paid_until: rec.paid_until,
+            subscription_type,
+        })
+    }
+
+    async fn shorten(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        months: u32,
+        fraction: f64,
+    ) -> Result<Option<SubscriptionInfo>, RepoError> {
+        let rec = sqlx::query!(
+            "UPDATE credit SET paid_until = paid_until - make_interval(months => $2) * $3::float8 WHERE user_id = $1 RETURNING plan_id, paid_until",
+            user_id,
+            months as i32,
+            fraction
+        )
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        let Some(rec) = rec else {
+            return Ok(None);
+        };
+        let subscription_type = SubscriptionType::from_string(&rec.plan_id)
+            .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?;
+        Ok(Some(SubscriptionInfo {
+            paid_until: rec.paid_until,
+            subscription_type,
+        }))
+    }
+
+    async fn find_expiring(&self, until: NaiveDateTime) -> Result<Vec<ExpiryNotice>, RepoError> {
+        let records = sqlx::query!(
+            "SELECT credit.user_id, credit.plan_id, credit.paid_until, users.email FROM credit
+JOIN users ON users.id = credit.user_id
+LEFT JOIN stripe_customers ON stripe_customers.user_id = credit.user_id
+WHERE credit.paid_until > timezone('utc', NOW()) AND credit.paid_until <= $1
+AND users.deleted IS NULL
+AND stripe_customers.subscription_status IS DISTINCT FROM 'active'
+AND stripe_customers.subscription_status IS DISTINCT FROM 'trialing'
+AND NOT EXISTS (
+    SELECT 1 FROM expiry_notifications
+    WHERE expiry_notifications.user_id = credit.user_id AND expiry_notifications.paid_until = credit.paid_until
+)
+ORDER BY credit.paid_until",
+            until
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        records
+            .into_iter()
+            .map(|rec| {
+                let subscription_type = SubscriptionType::from_string(&rec.plan_id)
+                    .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?;
+                Ok(ExpiryNotice {
+                    user_id: rec.user_id,
+                    email: rec.email,
+                    subscription: SubscriptionInfo {
+                        paid_until: rec.paid_until,
+                        subscription_type,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    async fn claim_expiry_notice(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        paid_until: NaiveDateTime,
+    ) -> Result<bool, RepoError> {
+        let result = sqlx::query!(
+            "INSERT INTO expiry_notifications (user_id, paid_until) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+            user_id,
+            paid_until
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn extend_until(
+        &self,
+        tx: &mut Transaction,
+        user_id: Uuid,
+        subscription_type: SubscriptionType,
+        until: NaiveDateTime,
+    ) -> Result<SubscriptionInfo, RepoError> {
+        let rec = sqlx::query!(
+            "INSERT INTO credit (user_id, plan_id, paid_until) VALUES ($1, $2, $3)
+ON CONFLICT (user_id) DO UPDATE SET plan_id = EXCLUDED.plan_id, paid_until = GREATEST(credit.paid_until, EXCLUDED.paid_until)
+RETURNING plan_id, paid_until",
+            user_id,
+            subscription_type.to_string(),
+            until
+        )
+        .fetch_one(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        let subscription_type = SubscriptionType::from_string(&rec.plan_id)
+            .ok_or_else(|| RepoError::NotFound("Invalid subscription type".to_string()))?;
+        Ok(SubscriptionInfo {
+            paid_until: rec.paid_until,
+            subscription_type,
+        })
+    }
 }
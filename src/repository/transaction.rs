@@ -0,0 +1,7 @@
+use crate::repository::error::RepoError;
+
+pub type Transaction = sqlx::Transaction<'static, sqlx::Postgres>;
+
+pub trait TransactionalRepository {
+    fn begin(&self) -> impl Future<Output = Result<Transaction, RepoError>>;
+}
@@ -0,0 +1,109 @@
+use chrono::NaiveDateTime;
+use uuid::Uuid;
+use sqlx::PgPool;
+use crate::models::repository::{MessageType, UnicastMessage};
+use crate::models::user::MLSClientId;
+use crate::repository::error::RepoError;
+
+#[derive(Clone, Debug)]
+pub struct UnicastMessageRepository {
+    conn: PgPool,
+}
+
+pub trait UnicastMessageRepositoryTrait {
+    fn create(&self, message: &UnicastMessage) -> impl Future<Output = Result<(), RepoError>>;
+    // Messages for `recipient` that it has not acknowledged yet, oldest first
+    fn find_pending(
+        &self,
+        recipient: &MLSClientId,
+        limit: u32,
+    ) -> impl Future<Output = Result<Vec<UnicastMessage>, RepoError>>;
+    // Writes read receipts for those of `ids` addressed to `recipient`. Returns how many were
+    // newly acknowledged, messages that already were are skipped.
+    fn acknowledge(
+        &self,
+        recipient: &MLSClientId,
+        ids: &[Uuid],
+        read_at: NaiveDateTime,
+    ) -> impl Future<Output = Result<u64, RepoError>>;
+}
+
+impl UnicastMessageRepository {
+    pub fn new(conn: PgPool) -> Self {
+        Self { conn }
+    }
+}
+
+struct UnicastMessageRow {
+    id: Uuid,
+    recipient_id: Uuid,
+    sender_id: Uuid,
+    message_type: String,
+    mls_data: Vec<u8>,
+    created: NaiveDateTime,
+}
+
+impl TryFrom<UnicastMessageRow> for UnicastMessage {
+    type Error = RepoError;
+
+    fn try_from(row: UnicastMessageRow) -> Result<Self, Self::Error> {
+        Ok(UnicastMessage {
+            id: row.id,
+            recipient: MLSClientId(row.recipient_id),
+            sender: MLSClientId(row.sender_id),
+            message_type: MessageType::from_string(&row.message_type)
+                .ok_or_else(|| RepoError::NotFound("Invalid message type".to_string()))?,
+            payload: row.mls_data,
+            created_at: row.created,
+        })
+    }
+}
+
+impl UnicastMessageRepositoryTrait for UnicastMessageRepository {
+    async fn create(&self, message: &UnicastMessage) -> Result<(), RepoError> {
+        sqlx::query!(
+            "INSERT INTO unicast_messages (id, mls_data, message_type, created, sender_id, recipient_id) VALUES ($1, $2, $3, $4, $5, $6)",
+            message.id,
+            message.payload,
+            message.message_type.to_string(),
+            message.created_at,
+            message.sender.0,
+            message.recipient.0
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
+
+    async fn find_pending(&self, recipient: &MLSClientId, limit: u32) -> Result<Vec<UnicastMessage>, RepoError> {
+        let rows = sqlx::query_as!(
+            UnicastMessageRow,
+            "SELECT unicast_messages.id, recipient_id, sender_id, message_type, mls_data, created FROM unicast_messages
+LEFT JOIN unicast_messages_read_receipts ON unicast_messages_read_receipts.message_id = unicast_messages.id
+WHERE recipient_id = $1 AND deleted IS NULL AND unicast_messages_read_receipts.message_id IS NULL
+ORDER BY created, unicast_messages.id LIMIT $2",
+            recipient.0,
+            limit as i64
+        )
+        .fetch_all(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        rows.into_iter().map(UnicastMessage::try_from).collect()
+    }
+
+    async fn acknowledge(&self, recipient: &MLSClientId, ids: &[Uuid], read_at: NaiveDateTime) -> Result<u64, RepoError> {
+        let result = sqlx::query!(
+            "INSERT INTO unicast_messages_read_receipts (message_id, read_at)
+SELECT id, $3 FROM unicast_messages WHERE id = ANY($1) AND recipient_id = $2 AND deleted IS NULL
+ON CONFLICT (message_id) DO NOTHING",
+            ids,
+            recipient.0,
+            read_at
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected())
+    }
+}
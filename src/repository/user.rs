@@ -4,8 +4,11 @@ use crate::models::account::AutoPushBehaviour;
 use crate::models::account::CommandStyle;
 use crate::repository::error::RepoError;
 use crate::models::account::Settings;
+use crate::models::user::UserProfile;
 use crate::repository::settings::SettingsRepository;
 use crate::repository::settings::SettingsRepositoryTrait;
+use crate::repository::transaction::Transaction;
+use chrono::NaiveDateTime;
 use sqlx::PgPool;
 use uuid::Uuid;
 
@@ -16,11 +19,37 @@ pub struct UserRepository {
 }
 
 pub trait UserRepositoryTrait {
-    fn create(&self, email: &str) -> impl Future<Output = Result<Uuid, RepoError>>;
+    fn create(
+        &self,
+        email: &str,
+        username: Option<&str>,
+    ) -> impl Future<Output = Result<Uuid, RepoError>>;
     fn find_by_email(
         &self,
         email: &str,
     ) -> impl Future<Output = Result<Option<Uuid>, RepoError>>;
+    fn find_profile(&self, uid: Uuid) -> impl Future<Output = Result<Option<UserProfile>, RepoError>>;
+    // Usernames are matched ignoring case
+    fn find_by_username(
+        &self,
+        username: &str,
+    ) -> impl Future<Output = Result<Option<UserProfile>, RepoError>>;
+    // DuplicateEntry if another user has the name in any case. Returns false without changing
+    // anything if the user already has a username that was set at or after `changed_before`.
+    fn set_username(
+        &self,
+        uid: Uuid,
+        username: &str,
+        changed_before: NaiveDateTime,
+    ) -> impl Future<Output = Result<bool, RepoError>>;
+    fn is_admin(&self, uid: Uuid) -> impl Future<Output = Result<bool, RepoError>>;
+    // Marks the account for support to review. Keeps the first flag if it is already flagged.
+    fn flag(
+        &self,
+        tx: &mut Transaction,
+        uid: Uuid,
+        reason: &str,
+    ) -> impl Future<Output = Result<(), RepoError>>;
 }
 
 impl UserRepository {
@@ -33,12 +62,13 @@ impl UserRepository {
 }
 
 impl UserRepositoryTrait for UserRepository {
-    async fn create(&self, email: &str) -> Result<Uuid, RepoError> {
+    async fn create(&self, email: &str, username: Option<&str>) -> Result<Uuid, RepoError> {
         let tx = self.conn.begin().await?;
         let id = sqlx::query!(
-            "INSERT INTO users (id, email) VALUES ($1, $2) RETURNING id;",
+            "INSERT INTO users (id, email, username, username_changed) VALUES ($1, $2, $3, CASE WHEN $3::text IS NULL THEN NULL ELSE timezone('utc', NOW()) END) RETURNING id;",
             Uuid::new_v4(),
-            email
+            email,
+            username
         )
         .fetch_one(&self.conn)
         .await.map_err(|e: sqlx::Error| RepoError::from(e))?
@@ -60,4 +90,61 @@ impl UserRepositoryTrait for UserRepository {
             .await.map_err(|e| RepoError::from(e))?
             .map(|v| v.id))
     }
+
+    async fn find_profile(&self, uid: Uuid) -> Result<Option<UserProfile>, RepoError> {
+        sqlx::query_as!(
+            UserProfile,
+            "SELECT id, username, username_changed, created FROM users WHERE id = $1 AND deleted IS NULL",
+            uid
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<UserProfile>, RepoError> {
+        sqlx::query_as!(
+            UserProfile,
+            "SELECT id, username, username_changed, created FROM users WHERE lower(username) = lower($1) AND deleted IS NULL",
+            username
+        )
+        .fetch_optional(&self.conn)
+        .await
+        .map_err(RepoError::from)
+    }
+
+    async fn set_username(&self, uid: Uuid, username: &str, changed_before: NaiveDateTime) -> Result<bool, RepoError> {
+        // the cooldown is checked by the update itself, so concurrent changes cannot both pass it
+        let result = sqlx::query!(
+            "UPDATE users SET username = $2, username_changed = timezone('utc', NOW())
+WHERE id = $1 AND deleted IS NULL AND (username IS NULL OR username_changed IS NULL OR username_changed < $3)",
+            uid,
+            username,
+            changed_before
+        )
+        .execute(&self.conn)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn is_admin(&self, uid: Uuid) -> Result<bool, RepoError> {
+        let rec = sqlx::query!("SELECT admin FROM users WHERE id = $1 AND deleted IS NULL", uid)
+            .fetch_optional(&self.conn)
+            .await
+            .map_err(RepoError::from)?;
+        Ok(rec.is_some_and(|rec| rec.admin))
+    }
+
+    async fn flag(&self, tx: &mut Transaction, uid: Uuid, reason: &str) -> Result<(), RepoError> {
+        sqlx::query!(
+            "UPDATE users SET flagged = COALESCE(flagged, timezone('utc', NOW())), flag_reason = COALESCE(flag_reason, $2) WHERE id = $1",
+            uid,
+            reason
+        )
+        .execute(&mut **tx)
+        .await
+        .map_err(RepoError::from)?;
+        Ok(())
+    }
 }
@@ -1,5 +1,22 @@
+use crate::logic::events::{LocalEventBus, MessageWaiters};
+use crate::logic::payment::CheckoutUrls;
+use crate::repository::payment_log::PaymentLogRepository;
+use crate::repository::promo_code::PromoCodeRepository;
+use crate::repository::repository::RepoRepository;
+use crate::repository::repo_transfer::RepoTransferRepository;
+use crate::repository::mls_client::MLSClientRepository;
+use crate::repository::key_package::KeyPackageRepository;
+use crate::repository::unicast_message::UnicastMessageRepository;
+use crate::repository::broadcast_message::BroadcastMessageRepository;
+use crate::repository::message_notification::MessageNotificationRepository;
+use crate::repository::commit::CommitRepository;
+use crate::repository::branch::BranchRepository;
 use crate::repository::settings::SettingsRepository;
+use crate::repository::stripe_customer::StripeCustomerRepository;
+use crate::repository::stripe_event::StripeEventRepository;
+use crate::repository::subscription::SubscriptionRepository;
 use axum::extract::FromRef;
+use chrono::TimeDelta;
 use sqlx::PgPool;
 use std::sync::Arc;
 use firebase_auth::{FirebaseAuth, FirebaseAuthState};
@@ -13,21 +30,79 @@ pub enum Environment {
     Testing,
 }
 
+#[derive(Clone)]
+pub struct BillingConfig {
+    pub stripe_client: stripe::Client,
+    // signing secret used to verify that webhook requests really come from Stripe
+    pub webhook_secret: String,
+    pub checkout_urls: CheckoutUrls,
+    // how long paid features keep working after `paid_until` has passed
+    pub grace_period: TimeDelta,
+}
+
+#[derive(Clone)]
+pub struct MessagingConfig {
+    // how many unclaimed key packages each client should keep uploaded
+    pub key_package_target: u32,
+}
+
 #[derive(Clone)]
 pub struct AppState {
     // auth: firebase_auth_sdk::Auth,
     pub user_repository: UserRepository,
     pub settings_repository: SettingsRepository,
+    pub subscription_repository: SubscriptionRepository,
+    pub payment_log_repository: PaymentLogRepository,
+    pub stripe_event_repository: StripeEventRepository,
+    pub stripe_customer_repository: StripeCustomerRepository,
+    pub promo_code_repository: PromoCodeRepository,
+    pub repo_repository: RepoRepository,
+    pub repo_transfer_repository: RepoTransferRepository,
+    pub mls_client_repository: MLSClientRepository,
+    pub key_package_repository: KeyPackageRepository,
+    pub unicast_message_repository: UnicastMessageRepository,
+    pub broadcast_message_repository: BroadcastMessageRepository,
+    pub message_notification_repository: MessageNotificationRepository,
+    pub commit_repository: CommitRepository,
+    pub branch_repository: BranchRepository,
+    pub events: LocalEventBus,
+    pub waiters: MessageWaiters,
     pub firebase_auth: FirebaseAuthState,
+    pub billing: BillingConfig,
+    pub messaging: MessagingConfig,
     pub environment: Environment
 }
 
 impl AppState {
-    pub fn new(pool: PgPool, firebase_auth: Arc<FirebaseAuth>, environment: Environment) -> Self {
+    pub fn new(
+        pool: PgPool,
+        firebase_auth: Arc<FirebaseAuth>,
+        billing: BillingConfig,
+        messaging: MessagingConfig,
+        environment: Environment,
+    ) -> Self {
         return AppState {
             user_repository: UserRepository::new(pool.clone()),
-            settings_repository: SettingsRepository::new(pool),
+            settings_repository: SettingsRepository::new(pool.clone()),
+            subscription_repository: SubscriptionRepository::new(pool.clone()),
+            payment_log_repository: PaymentLogRepository::new(pool.clone()),
+            stripe_event_repository: StripeEventRepository::new(pool.clone()),
+            stripe_customer_repository: StripeCustomerRepository::new(pool.clone()),
+            promo_code_repository: PromoCodeRepository::new(pool.clone()),
+            repo_repository: RepoRepository::new(pool.clone()),
+            repo_transfer_repository: RepoTransferRepository::new(pool.clone()),
+            mls_client_repository: MLSClientRepository::new(pool.clone()),
+            key_package_repository: KeyPackageRepository::new(pool.clone()),
+            unicast_message_repository: UnicastMessageRepository::new(pool.clone()),
+            broadcast_message_repository: BroadcastMessageRepository::new(pool.clone()),
+            message_notification_repository: MessageNotificationRepository::new(pool.clone()),
+            commit_repository: CommitRepository::new(pool.clone()),
+            branch_repository: BranchRepository::new(pool),
+            events: LocalEventBus::default(),
+            waiters: MessageWaiters::default(),
             firebase_auth: FirebaseAuthState { firebase_auth },
+            billing,
+            messaging,
             environment,
         }
     }
@@ -39,4 +114,3 @@ impl FromRef<AppState> for FirebaseAuthState {
         app_state.firebase_auth.clone()
     }
 }
-
@@ -9,6 +9,11 @@ use fars::Config;
 use fars::Email;
 use fars::Password;
 use reqwest::Client;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::models::account::{SubscriptionInfo, SubscriptionType};
+use crate::repository::subscription::{SubscriptionRepository, SubscriptionRepositoryTrait};
 
 pub struct TestEnvironment {
     pub firebase_auth: FirebaseAuth,
@@ -98,3 +103,23 @@ fn load_base_url() -> String {
     );
     return base_url;
 }
+
+// Gives a signed up user a plan that lasts until `paid_until`, straight in the database the
+// server under test uses, so tests do not have to go through Stripe
+pub async fn grant_plan(uid: &str, subscription_type: SubscriptionType, paid_until: chrono::NaiveDateTime) {
+    if dotenv().is_err() {
+        println!("no .env file found...")
+    }
+    let database_url = env::var("DATABASE_URL").expect(
+        "Could not find DATABASE_URL environment variable anywhere. Try putting it in .env",
+    );
+    let pool = PgPool::connect(&database_url).await.unwrap();
+    let sub_info = SubscriptionInfo {
+        paid_until,
+        subscription_type,
+    };
+    SubscriptionRepository::new(pool)
+        .create(Uuid::parse_str(uid).unwrap(), sub_info)
+        .await
+        .unwrap();
+}
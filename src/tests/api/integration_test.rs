@@ -1,6 +1,7 @@
 use reqwest::Client;
 use serde_json::Value;
 
+use crate::models::account::SubscriptionType;
 use crate::tests::api::common::*;
 
 #[tokio::test]
@@ -28,6 +29,25 @@ async fn signup(id_token: &str, client: &Client, base_url: &str) -> reqwest::Res
     return res;
 }
 
+// Registers a new client device for the user, returning its id
+async fn register_client(id_token: &str, client: &Client, base_url: &str) -> String {
+    let res = client
+        .post(base_url.to_owned() + "/clients")
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let registered: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    registered["id"].as_str().unwrap().to_owned()
+}
+
+// Signs the user up with a month of `plan`, returning their id
+async fn signup_with_plan(id_token: &str, client: &Client, base_url: &str, plan: SubscriptionType) -> String {
+    let uid = signup(id_token, client, base_url).await.text().await.unwrap();
+    grant_plan(&uid, plan, (chrono::Utc::now() + chrono::TimeDelta::days(30)).naive_utc()).await;
+    uid
+}
+
 #[tokio::test]
 async fn test_signup_then_me() {
     let test_env = TestEnvironment::init("signup_then_me", 2).await;
@@ -124,6 +144,35 @@ async fn test_signup_then_get_account_settings() {
     assert!(json_obj.get("auto_pull_behaviour").is_some());
 }
 
+#[tokio::test]
+async fn test_signup_then_get_payment_info() {
+    let test_env = TestEnvironment::init("signup_then_get_payment_info", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+
+    signup(id_token_1, client, base_url).await;
+
+    let res1 = client
+        .get(base_url.to_owned() + "/account/payment-info")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+
+    assert!(res1.status().is_success());
+
+    let text1 = res1.text().await.unwrap();
+    let json_obj: Value = serde_json::from_str(&text1).unwrap();
+    // a freshly signed up user has never paid
+    assert_eq!(json_obj["subscription_type"], "none");
+    assert_eq!(json_obj["active"], false);
+    assert_eq!(json_obj["days_remaining"], 0);
+    assert!(json_obj["recent_payments"].as_array().unwrap().is_empty());
+}
+
 #[tokio::test]
 async fn test_signup_then_post_account_settings() {
     let test_env = TestEnvironment::init("signup_then_post_account_Settings", 1).await;
@@ -190,3 +239,1050 @@ async fn test_signup_then_post_account_settings() {
     assert!(res1.status().is_success());
     */
 }
+
+#[tokio::test]
+async fn test_routes_require_plan() {
+    let test_env = TestEnvironment::init("routes_require_plan", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup(id_token_1, client, base_url).await;
+    signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "unpaid" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 402);
+
+    // account routes stay open without a plan
+    let res = client
+        .get(base_url.to_owned() + "/account/profile")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "name": "synced" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+
+    // sharing needs Sync Collaborate
+    let res = client
+        .post(format!("{}/repositories/{}/synced/transfer", base_url, created["owner"].as_str().unwrap()))
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "to": created["owner_id"] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 402);
+}
+
+#[tokio::test]
+async fn test_lapsed_plan_warning() {
+    let test_env = TestEnvironment::init("lapsed_plan_warning", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    // lapsed a minute ago, well inside the server's grace period of GRACE_PERIOD_DAYS >= 1
+    let uid = signup(id_token, client, base_url).await.text().await.unwrap();
+    let paid_until = (chrono::Utc::now() - chrono::TimeDelta::minutes(1)).naive_utc();
+    grant_plan(&uid, SubscriptionType::CloudSync, paid_until).await;
+
+    let res = client
+        .get(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+    let warning = res.headers().get("x-subscription-warning").unwrap().to_str().unwrap();
+    assert!(warning.starts_with("expired"));
+}
+
+#[tokio::test]
+async fn test_repository_lifecycle() {
+    let test_env = TestEnvironment::init("repository_lifecycle", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::CloudSync).await;
+    signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "work" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let owner = created["owner"].as_str().unwrap().to_owned();
+
+    let res = client
+        .get(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+    assert_eq!(listed[0]["name"], "work");
+
+    // another user cannot see or change it
+    let repo_url = format!("{}/repositories/{}/work", base_url, owner);
+    let res = client
+        .get(&repo_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    // names are only unique per owner
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "name": "work" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let other: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_ne!(other["id"], created["id"]);
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "work" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+
+    let res = client
+        .patch(&repo_url)
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "archive" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+    let renamed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(renamed["name"], "archive");
+
+    let res = client
+        .delete(format!("{}/repositories/{}/archive", base_url, owner))
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 204);
+
+    let res = client
+        .get(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert!(listed.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_username_claim_and_lookup() {
+    let test_env = TestEnvironment::init("username_claim", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::CloudSync).await;
+    signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let username = format!("user-{}", chrono::Utc::now().timestamp_millis());
+    let res = client
+        .put(base_url.to_owned() + "/account/username")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "username": username }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+
+    // taken, even in another case
+    let res = client
+        .put(base_url.to_owned() + "/account/username")
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "username": username.to_uppercase() }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+
+    let res = client
+        .put(base_url.to_owned() + "/account/username")
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "username": "-bad name-" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = client
+        .get(format!("{}/users/{}", base_url, username.to_uppercase()))
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+    let profile: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(profile["username"], username);
+
+    // changing again right away is rate limited
+    let res = client
+        .put(base_url.to_owned() + "/account/username")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "username": format!("{}-new", username) }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 429);
+
+    // repositories are now addressed by username
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "notes" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(created["identifier"], format!("{}/notes", username));
+}
+
+#[tokio::test]
+async fn test_repository_transfer_requires_recipient_plan() {
+    let test_env = TestEnvironment::init("repository_transfer", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::SyncCollaborate).await;
+    let res = signup(id_token_2, client, base_url).await;
+    let recipient = res.text().await.unwrap();
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "handoff" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let transfer_url = format!("{}/repositories/{}/transfer", base_url, created["identifier"].as_str().unwrap());
+
+    let res = client
+        .post(&transfer_url)
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "to": created["owner_id"] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    // only the owner can offer it
+    let res = client
+        .post(&transfer_url)
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "to": recipient }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    // new users have no plan to take it over with
+    let res = client
+        .post(&transfer_url)
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "to": recipient }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 402);
+
+    let res = client
+        .get(base_url.to_owned() + "/transfers")
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let transfers: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert!(transfers.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_transferred_slug_redirects_reads_only() {
+    let test_env = TestEnvironment::init("transferred_slug", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::SyncCollaborate).await;
+    let recipient = signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "moving" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let old_url = format!("{}/repositories/{}", base_url, created["identifier"].as_str().unwrap());
+
+    let res = client
+        .post(old_url.clone() + "/transfer")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "to": recipient }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let transfer: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let res = client
+        .post(format!("{}/transfers/{}/accept", base_url, transfer["id"].as_str().unwrap()))
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert!(res.status().is_success());
+
+    // reads of the old slug are pointed at the new one
+    let no_redirects = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+    let res = no_redirects
+        .get(&old_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 307);
+    assert!(res.headers().contains_key("location"));
+
+    // changes are not replayed against a repository the client did not name
+    let res = no_redirects
+        .patch(&old_url)
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "name": "renamed" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 410);
+    let res = no_redirects
+        .delete(&old_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 410);
+}
+
+#[tokio::test]
+async fn test_client_devices() {
+    let test_env = TestEnvironment::init("client_devices", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup(id_token_1, client, base_url).await;
+    signup(id_token_2, client, base_url).await;
+
+    let res = client
+        .post(base_url.to_owned() + "/clients")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "Work laptop" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let laptop: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(laptop["name"], "Work laptop");
+
+    let res = client
+        .post(base_url.to_owned() + "/clients")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = client
+        .get(base_url.to_owned() + "/clients")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 2);
+
+    let laptop_url = format!("{}/clients/{}", base_url, laptop["id"].as_str().unwrap());
+    let res = client
+        .patch(&laptop_url)
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "name": "Home laptop" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let renamed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(renamed["name"], "Home laptop");
+
+    // other users cannot remove it
+    let res = client
+        .delete(&laptop_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = client
+        .delete(&laptop_url)
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 204);
+
+    let res = client
+        .get(base_url.to_owned() + "/clients")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let listed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(listed.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_key_package_claims() {
+    let test_env = TestEnvironment::init("key_package_claims", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::CloudSync).await;
+    signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for id_token in [id_token_1, id_token_2] {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+
+    let expires = chrono::Utc::now().timestamp_millis() + 86_400_000;
+    let res = client
+        .post(format!("{}/clients/{}/key-packages", base_url, client_ids[0]))
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({
+            "key_packages": [{ "data": "AQID", "expires": expires }],
+            "last_resort": { "data": "BAUG", "expires": expires },
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = client
+        .get(base_url.to_owned() + "/key-packages/inventory")
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let inventory: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(inventory[0]["client_id"], client_ids[0].as_str());
+    assert_eq!(inventory[0]["available"], 1);
+    assert_eq!(inventory[0]["has_last_resort"], true);
+    let target = inventory[0]["target"].as_u64().unwrap();
+    assert_eq!(inventory[0]["upload"].as_u64().unwrap(), target.saturating_sub(1));
+
+    // the one-time package is consumed first, then the last resort one keeps being handed out
+    for (data, last_resort) in [("AQID", false), ("BAUG", true), ("BAUG", true)] {
+        let res = client
+            .post(base_url.to_owned() + "/key-packages/claim")
+            .bearer_auth(id_token_2)
+            .json(&serde_json::json!({ "claimer": client_ids[1], "clients": [client_ids[0]] }))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert!(res.status().is_success());
+        let claimed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+        assert_eq!(claimed[0]["data"], data);
+        assert_eq!(claimed[0]["last_resort"], last_resort);
+    }
+
+    // nothing has been uploaded for the second client
+    let res = client
+        .post(base_url.to_owned() + "/key-packages/claim")
+        .bearer_auth(id_token_1)
+        .json(&serde_json::json!({ "claimer": client_ids[0], "clients": [client_ids[1]] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn test_unicast_messages() {
+    let test_env = TestEnvironment::init("unicast_messages", 2).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token_1 = &test_env.id_tokens[0];
+    let id_token_2 = &test_env.id_tokens[1];
+
+    signup_with_plan(id_token_1, client, base_url, SubscriptionType::CloudSync).await;
+    signup_with_plan(id_token_2, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for id_token in [id_token_1, id_token_2] {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+    let inbox_url = format!("{}/clients/{}/messages", base_url, client_ids[1]);
+
+    for payload in ["AQID", "BAUG"] {
+        let res = client
+            .post(&inbox_url)
+            .bearer_auth(id_token_1)
+            .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "welcome", "payload": payload }))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(res.status().as_u16(), 201);
+    }
+
+    // only the recipient's user can read its inbox
+    let res = client
+        .get(&inbox_url)
+        .bearer_auth(id_token_1)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = client
+        .get(&inbox_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let pending: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(pending.as_array().unwrap().len(), 2);
+    assert_eq!(pending[0]["payload"], "AQID");
+    assert_eq!(pending[0]["message_type"], "welcome");
+
+    let res = client
+        .post(format!("{}/ack", inbox_url))
+        .bearer_auth(id_token_2)
+        .json(&serde_json::json!({ "ids": [pending[0]["id"]] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let acknowledged: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(acknowledged["acknowledged"], 1);
+
+    let res = client
+        .get(&inbox_url)
+        .bearer_auth(id_token_2)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let pending: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert_eq!(pending[0]["payload"], "BAUG");
+}
+
+#[tokio::test]
+async fn test_broadcast_messages() {
+    let test_env = TestEnvironment::init("broadcast_messages", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    signup_with_plan(id_token, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for _ in 0..2 {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+
+    // the first client founds the repository's group, the second never joins it
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "name": "shared", "client": client_ids[0] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let messages_url = format!(
+        "{}/repositories/{}/shared/messages",
+        base_url,
+        created["owner"].as_str().unwrap()
+    );
+
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "commit", "epoch": 0, "payload": "AQID" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let winner: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(winner["sequence"], 1);
+
+    // a concurrent commit for the same epoch loses and gets the winning commit back
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "commit", "epoch": 0, "payload": "BAUG" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+    let conflict: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(conflict["id"], winner["id"]);
+
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "proposal", "epoch": 2, "payload": "BAUG" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "commit", "epoch": 1, "payload": "BAUG" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let next: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(next["sequence"], 2);
+
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[1], "message_type": "commit", "epoch": 2, "payload": "AQID" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    // welcomes are sent to the joining client's inbox instead
+    let res = client
+        .post(&messages_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "welcome", "epoch": 2, "payload": "AQID" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = client
+        .get(format!("{}?client={}", messages_url, client_ids[1]))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    // senders do not receive their own messages
+    let res = client
+        .get(format!("{}?client={}", messages_url, client_ids[0]))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let pending: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert!(pending.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_repository_event_stream() {
+    let test_env = TestEnvironment::init("repository_event_stream", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    signup_with_plan(id_token, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for _ in 0..2 {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "name": "streamed", "client": client_ids[0] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let repo_url = format!("{}/repositories/{}/streamed", base_url, created["owner"].as_str().unwrap());
+
+    for payload in ["AQID", "BAUG"] {
+        let res = client
+            .post(format!("{}/messages", repo_url))
+            .bearer_auth(id_token)
+            .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "application", "epoch": 0, "payload": payload }))
+            .send()
+            .await
+            .expect("Failed to send request");
+        assert_eq!(res.status().as_u16(), 201);
+    }
+
+    let res = client
+        .get(format!("{}/events?client={}", repo_url, client_ids[1]))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    let res = client
+        .get(format!("{}/events?client={}", repo_url, client_ids[0]))
+        .bearer_auth(id_token)
+        .header("Last-Event-ID", "not a sequence number")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    // resuming after the first message only replays the second
+    let mut res = client
+        .get(format!("{}/events?client={}", repo_url, client_ids[0]))
+        .bearer_auth(id_token)
+        .header("Last-Event-ID", "1")
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 200);
+    let mut body = String::new();
+    while !body.contains("\n\n") {
+        let chunk = res.chunk().await.unwrap().expect("Stream ended early");
+        body.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    assert!(body.contains("id: 2"));
+    assert!(!body.contains("id: 1\n"));
+
+    // without a sequence number only new events are sent, so the stream starts out subscribed
+    let mut res = client
+        .get(format!("{}/events?client={}", repo_url, client_ids[0]))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 200);
+    let mut body = String::new();
+    while !body.contains("\n\n") {
+        let chunk = res.chunk().await.unwrap().expect("Stream ended early");
+        body.push_str(&String::from_utf8_lossy(&chunk));
+    }
+    assert!(body.contains("\"type\":\"subscribed\""));
+    assert!(body.contains("\"more_available\":false"));
+    assert!(!body.contains("id: "));
+}
+
+#[tokio::test]
+async fn test_long_poll_fetch() {
+    let test_env = TestEnvironment::init("long_poll_fetch", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    signup_with_plan(id_token, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for _ in 0..2 {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+    let inbox_url = format!("{}/clients/{}/messages", base_url, client_ids[1]);
+
+    // an empty inbox is returned once the wait is over
+    let started = std::time::Instant::now();
+    let res = client
+        .get(format!("{}?wait=1", inbox_url))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    let pending: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert!(pending.as_array().unwrap().is_empty());
+    assert!(started.elapsed() >= std::time::Duration::from_secs(1));
+
+    // a message sent while waiting is returned right away
+    let started = std::time::Instant::now();
+    let waiting = tokio::spawn({
+        let client = client.clone();
+        let url = format!("{}?wait=20", inbox_url);
+        let id_token = id_token.clone();
+        async move { client.get(url).bearer_auth(id_token).send().await }
+    });
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    let res = client
+        .post(&inbox_url)
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "sender": client_ids[0], "message_type": "welcome", "payload": "AQID" }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let res = waiting.await.unwrap().expect("Failed to send request");
+    let pending: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(pending.as_array().unwrap().len(), 1);
+    assert!(started.elapsed() < std::time::Duration::from_secs(20));
+}
+
+#[tokio::test]
+async fn test_push_commits() {
+    let test_env = TestEnvironment::init("push_commits", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    signup_with_plan(id_token, client, base_url, SubscriptionType::CloudSync).await;
+
+    let mut client_ids = Vec::new();
+    for _ in 0..2 {
+        client_ids.push(register_client(id_token, client, base_url).await);
+    }
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "name": "pushed", "client": client_ids[0] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let repo = serde_json::json!({ "owner": created["owner"], "name": "pushed" });
+
+    // fresh hashes, so the test does not depend on what earlier runs pushed
+    let hash = |name: &str| format!("{}{}", name, uuid::Uuid::new_v4().simple());
+    let (root, child, orphan, unknown) = (hash("a"), hash("b"), hash("c"), hash("d"));
+    let commit = |hash: &str, parents: &[&str], author: &str| {
+        serde_json::json!({ "hash": hash, "parents": parents, "author": author, "changes": "AQID", "message": "BAUG" })
+    };
+    let push = |pusher: &str, commits: Vec<Value>| {
+        client
+            .post(base_url.to_owned() + "/repositories/push")
+            .bearer_auth(id_token)
+            .json(&serde_json::json!({ "repo": repo, "client": pusher, "epoch": 0, "commits": commits }))
+            .send()
+    };
+
+    let res = push(
+        &client_ids[0],
+        vec![commit(&root, &[], &client_ids[0]), commit(&child, &[&root], &client_ids[0])],
+    )
+    .await
+    .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let pushed: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(pushed["commits"].as_array().unwrap().len(), 2);
+    assert_eq!(pushed["commits"][1]["parents"][0], root.as_str());
+
+    // one unknown parent rejects the whole batch
+    let res = push(
+        &client_ids[0],
+        vec![commit(&orphan, &[&child], &client_ids[0]), commit(&hash("e"), &[&unknown], &client_ids[0])],
+    )
+    .await
+    .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+    let res = push(&client_ids[0], vec![commit(&orphan, &[&child], &client_ids[0])])
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+
+    let res = push(&client_ids[0], vec![commit(&root, &[], &client_ids[0])])
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+
+    // the second client is not a member, neither as pusher nor as author
+    let res = push(&client_ids[1], vec![commit(&unknown, &[&child], &client_ids[1])])
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+    let res = push(&client_ids[0], vec![commit(&unknown, &[&child], &client_ids[1])])
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 403);
+
+    // hashes are scoped to their repository, so another repository can hold the same commit
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "name": "pushed-copy", "client": client_ids[0] }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let copy = serde_json::json!({ "owner": created["owner"], "name": "pushed-copy" });
+    let res = client
+        .post(base_url.to_owned() + "/repositories/push")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({
+            "repo": copy,
+            "client": client_ids[0],
+            "epoch": 0,
+            "commits": [commit(&root, &[], &client_ids[0])],
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+}
+
+#[tokio::test]
+async fn test_branch_compare_and_swap() {
+    let test_env = TestEnvironment::init("branch_compare_and_swap", 1).await;
+
+    let client = &test_env.client;
+    let base_url = &test_env.base_url;
+
+    let id_token = &test_env.id_tokens[0];
+
+    signup_with_plan(id_token, client, base_url, SubscriptionType::CloudSync).await;
+
+    let client_id = register_client(id_token, client, base_url).await;
+
+    let res = client
+        .post(base_url.to_owned() + "/repositories")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({ "name": "branched", "client": client_id }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    let created: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    let owner = created["owner"].as_str().unwrap().to_owned();
+
+    let hash = |name: &str| format!("{}{}", name, uuid::Uuid::new_v4().simple());
+    let (root, laptop, phone) = (hash("a"), hash("b"), hash("c"));
+    // two devices both committed on top of the root
+    let commit = |hash: &str, parents: &[&str]| {
+        serde_json::json!({ "hash": hash, "parents": parents, "author": client_id, "changes": "AQID", "message": "BAUG" })
+    };
+    let commits = vec![commit(&root, &[]), commit(&laptop, &[&root]), commit(&phone, &[&root])];
+    let res = client
+        .post(base_url.to_owned() + "/repositories/push")
+        .bearer_auth(id_token)
+        .json(&serde_json::json!({
+            "repo": { "owner": owner, "name": "branched" },
+            "client": client_id,
+            "epoch": 0,
+            "commits": commits,
+        }))
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+
+    let branch_url = format!("{}/repositories/{}/branched/branches/main", base_url, owner);
+    let update = |expected: Option<&str>, head: &str| {
+        client
+            .put(branch_url.clone())
+            .bearer_auth(id_token)
+            .json(&serde_json::json!({ "client": client_id, "expected": expected, "head": head }))
+            .send()
+    };
+
+    let res = update(None, &root).await.expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 201);
+    let res = update(None, &laptop).await.expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+
+    // both saw the root, only the first to move the branch wins
+    let res = update(Some(&root), &laptop).await.expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 200);
+    let res = update(Some(&root), &phone).await.expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 409);
+    let conflict: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(conflict["head"], laptop.as_str());
+
+    // heads must be commits of the repository
+    let res = update(Some(&laptop), &hash("d")).await.expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 400);
+
+    let res = client
+        .get(format!("{}/repositories/{}/branched/branches?client={}", base_url, owner, client_id))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 200);
+    let branches: Value = serde_json::from_str(&res.text().await.unwrap()).unwrap();
+    assert_eq!(branches.as_array().unwrap().len(), 1);
+    assert_eq!(branches[0]["head"], laptop.as_str());
+
+    let res = client
+        .get(format!("{}/repositories/{}/branched/branches/other?client={}", base_url, owner, client_id))
+        .bearer_auth(id_token)
+        .send()
+        .await
+        .expect("Failed to send request");
+    assert_eq!(res.status().as_u16(), 404);
+}